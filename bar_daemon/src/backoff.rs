@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// # Documentation
+/// Policy describing how long to wait between retries of a value which keeps failing to be read
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct BackoffPolicy {
+    /// Delay before the first retry in milliseconds
+    pub initial_delay: u64,
+    /// Factor which the delay is multiplied by after every retry
+    pub multiplier: f64,
+    /// Maximum delay between retries in milliseconds
+    pub max_delay: u64,
+    /// Fraction of the delay which is randomly added or removed (0.0 to 1.0)
+    pub jitter: f64,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: 1000,
+            multiplier: 2.0,
+            max_delay: 300_000,
+            jitter: 0.1,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// # Documentation
+    /// Get the delay (without jitter) which should be waited before the given retry (starting at 0)
+    #[must_use]
+    pub fn base_delay(&self, retry: u32) -> Duration {
+        let exponent = i32::try_from(retry).unwrap_or(i32::MAX);
+        let delay_ms = (self.initial_delay as f64 * self.multiplier.max(1.0).powi(exponent)).min(self.max_delay as f64);
+
+        Duration::from_millis(delay_ms as u64)
+    }
+
+    /// # Documentation
+    /// Get the delay which should be waited before the given retry, with `jitter` applied using `random` (0.0 to 1.0)
+    #[must_use]
+    pub fn delay(&self, retry: u32, random: f64) -> Duration {
+        let base = self.base_delay(retry).as_millis() as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+
+        // Move the delay by up to +/- jitter of its value
+        let offset = base * jitter * random.clamp(0.0, 1.0).mul_add(2.0, -1.0);
+
+        Duration::from_millis((base + offset).max(0.0) as u64)
+    }
+}
+
+/// # Documentation
+/// Iterates through the delays of a `BackoffPolicy`
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    policy: BackoffPolicy,
+    retry: u32,
+}
+
+impl Backoff {
+    #[must_use]
+    pub const fn new(policy: BackoffPolicy) -> Self {
+        Self { policy, retry: 0 }
+    }
+
+    /// # Documentation
+    /// Get the next delay and advance the retry counter
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.policy.delay(self.retry, fastrand::f64());
+        self.retry = self.retry.saturating_add(1);
+
+        delay
+    }
+
+    /// # Documentation
+    /// Start the delays again from `initial_delay`
+    pub const fn reset(&mut self) {
+        self.retry = 0;
+    }
+
+    #[must_use]
+    pub const fn retries(&self) -> u32 {
+        self.retry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::BackoffPolicy;
+
+    #[test]
+    fn base_delay_grows_until_capped() {
+        let policy = BackoffPolicy {
+            initial_delay: 100,
+            multiplier: 3.0,
+            max_delay: 1000,
+            jitter: 0.0,
+        };
+
+        let delays = (0..5).map(|retry| policy.base_delay(retry)).collect::<Vec<_>>();
+
+        assert_eq!(
+            delays,
+            [100, 300, 900, 1000, 1000].map(Duration::from_millis).to_vec(),
+            "Backoff delays did not grow or cap correctly"
+        );
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = BackoffPolicy {
+            initial_delay: 1000,
+            multiplier: 2.0,
+            max_delay: 60_000,
+            jitter: 0.25,
+        };
+
+        assert_eq!(policy.delay(0, 0.0), Duration::from_millis(750));
+        assert_eq!(policy.delay(0, 0.5), Duration::from_secs(1));
+        assert_eq!(policy.delay(0, 1.0), Duration::from_millis(1250));
+        assert_eq!(policy.delay(1, 1.0), Duration::from_millis(2500));
+    }
+}
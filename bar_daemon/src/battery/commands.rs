@@ -0,0 +1,32 @@
+use clap::Subcommand;
+
+use crate::protocol::{DaemonItem, DaemonMessage};
+
+use super::BatteryItem;
+
+#[derive(Subcommand)]
+pub enum BatteryGetCommands {
+    #[command(alias = "s")]
+    State,
+    #[command(alias = "per", alias = "p")]
+    Percent,
+    #[command(alias = "t")]
+    Time,
+    #[command(alias = "i")]
+    Icon,
+}
+
+#[must_use]
+pub const fn match_get_commands(commands: &Option<BatteryGetCommands>) -> DaemonMessage {
+    DaemonMessage::Get {
+        item: match commands {
+            Some(commands) => match commands {
+                BatteryGetCommands::State => DaemonItem::Battery(BatteryItem::State),
+                BatteryGetCommands::Percent => DaemonItem::Battery(BatteryItem::Percent),
+                BatteryGetCommands::Time => DaemonItem::Battery(BatteryItem::Time),
+                BatteryGetCommands::Icon => DaemonItem::Battery(BatteryItem::Icon),
+            },
+            None => DaemonItem::Battery(BatteryItem::All),
+        },
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::value::BatteryState;
+
+/// Weight of the latest rate in the moving average, the rest is the average of the previous rates
+const SMOOTHING: f64 = 0.3;
+
+/// Number of rates which are averaged before the estimate is confident
+const CONFIDENT_AFTER: u32 = 3;
+
+static ESTIMATOR: Mutex<Estimator> = Mutex::new(Estimator::new());
+
+/// # Documentation
+/// Estimated time until the battery is empty (When discharging) or full (When charging)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeEstimate {
+    pub remaining: Duration,
+    /// Whether the estimate comes from enough changes of the percent in this state, otherwise it is the time given by acpi,
+    /// or an estimate from too few changes
+    pub confident: bool,
+}
+
+impl std::fmt::Display for TimeEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let minutes = (self.remaining.as_secs() + 30) / 60;
+        let approximate = if self.confident { "" } else { "~" };
+
+        match (minutes / 60, minutes % 60) {
+            (0, minutes) => write!(f, "{approximate}{minutes}m"),
+            (hours, minutes) => write!(f, "{approximate}{hours}h {minutes}m"),
+        }
+    }
+}
+
+/// # Documentation
+/// Estimates the time until the battery is empty or full, from an exponential moving average of the rate which its percent changes at
+#[derive(Debug, Default, Clone)]
+pub struct Estimator {
+    state: Option<BatteryState>,
+    percent: Option<u32>,
+    /// When the percent last changed (`None` until it has changed in this state, since the first percent may have been partly used)
+    changed_at: Option<SystemTime>,
+    /// Smoothed rate, in percent per second
+    rate: Option<f64>,
+    /// Number of rates which have been averaged
+    samples: u32,
+}
+
+impl Estimator {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            state: None,
+            percent: None,
+            changed_at: None,
+            rate: None,
+            samples: 0,
+        }
+    }
+
+    /// # Documentation
+    /// Add a reading of the battery at `now`, and estimate the time until it is empty or full.
+    /// `reported` (The time given by acpi) is used until the rate is known, and there is no estimate when not charging or discharging
+    pub fn update(
+        &mut self,
+        now: SystemTime,
+        state: BatteryState,
+        percent: u32,
+        reported: Option<Duration>,
+    ) -> Option<TimeEstimate> {
+        // The rate is different when charging, so start again when the state changes
+        if self.state != Some(state) {
+            *self = Self {
+                state: Some(state),
+                ..Self::new()
+            };
+        }
+
+        self.add_reading(now, state, percent);
+
+        let percent_left = match state {
+            BatteryState::Discharging => f64::from(percent),
+            BatteryState::Charging => f64::from(100_u32.saturating_sub(percent)),
+            BatteryState::FullyCharged | BatteryState::NotCharging => return None,
+        };
+
+        let Some(rate) = self.rate else {
+            return reported.map(|remaining| TimeEstimate {
+                remaining,
+                confident: false,
+            });
+        };
+
+        // When the percent hasn't changed for longer than the rate predicts (e.g. the load dropped), the rate is at most 1 percent over that time
+        let since_change = self
+            .changed_at
+            .and_then(|changed_at| now.duration_since(changed_at).ok())
+            .unwrap_or_default();
+        let rate = rate.min(1.0 / since_change.as_secs_f64());
+
+        Duration::try_from_secs_f64(percent_left / rate)
+            .ok()
+            .map(|remaining| TimeEstimate {
+                remaining,
+                confident: self.samples >= CONFIDENT_AFTER,
+            })
+    }
+
+    fn add_reading(&mut self, now: SystemTime, state: BatteryState, percent: u32) {
+        let Some(last_percent) = self.percent.replace(percent) else {
+            return;
+        };
+        if last_percent == percent {
+            return;
+        }
+
+        // Only changes in the direction of the state give a rate
+        let towards_state = match state {
+            BatteryState::Discharging => percent < last_percent,
+            BatteryState::Charging => percent > last_percent,
+            BatteryState::FullyCharged | BatteryState::NotCharging => false,
+        };
+
+        if let Some(changed_at) = self.changed_at.filter(|_| towards_state) {
+            let elapsed = now.duration_since(changed_at).unwrap_or_default().as_secs_f64();
+
+            if elapsed > 0.0 {
+                let rate = f64::from(percent.abs_diff(last_percent)) / elapsed;
+
+                self.rate = Some(
+                    self.rate
+                        .map_or(rate, |average| SMOOTHING.mul_add(rate, (1.0 - SMOOTHING) * average)),
+                );
+                self.samples += 1;
+            }
+        }
+
+        self.changed_at = Some(now);
+    }
+}
+
+/// # Documentation
+/// Add a reading of the battery to the daemon's estimator, and estimate the time until it is empty or full
+#[must_use]
+pub fn estimate_time(state: BatteryState, percent: u32, reported: Option<Duration>) -> Option<TimeEstimate> {
+    ESTIMATOR
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .update(SystemTime::now(), state, percent, reported)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{Estimator, TimeEstimate};
+    use crate::battery::BatteryState;
+
+    /// Read the battery every 10 seconds for `seconds`, with its percent given by `percent_at`, returning the last estimate
+    fn run_curve(
+        estimator: &mut Estimator,
+        start: SystemTime,
+        seconds: std::ops::Range<u64>,
+        state: BatteryState,
+        percent_at: impl Fn(f64) -> f64,
+    ) -> Option<TimeEstimate> {
+        seconds
+            .step_by(10)
+            .map(|second| {
+                let percent = percent_at(second as f64).round() as u32;
+                estimator.update(start + Duration::from_secs(second), state, percent, None)
+            })
+            .last()
+            .flatten()
+    }
+
+    fn minutes(estimate: Option<TimeEstimate>) -> f64 {
+        estimate.map_or(0.0, |estimate| estimate.remaining.as_secs_f64() / 60.0)
+    }
+
+    #[test]
+    fn steady_discharge() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut estimator = Estimator::new();
+
+        // 1 percent per minute from 80 percent, so 70 minutes are left after 10 minutes
+        let estimate = run_curve(&mut estimator, start, 0..601, BatteryState::Discharging, |second| {
+            80.0 - second / 60.0
+        });
+
+        assert!(estimate.is_some_and(|estimate| estimate.confident), "{estimate:?}");
+        assert!((minutes(estimate) - 70.0).abs() < 2.0, "{estimate:?}");
+    }
+
+    #[test]
+    fn load_changes_are_smoothed() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut estimator = Estimator::new();
+
+        let _ = run_curve(&mut estimator, start, 0..1201, BatteryState::Discharging, |second| {
+            90.0 - second / 60.0
+        });
+
+        // The load doubles, so the rate moves towards 2 percent per minute without jumping straight to it
+        let after_one_change = run_curve(&mut estimator, start, 1210..1241, BatteryState::Discharging, |second| {
+            70.0 - (second - 1200.0) / 30.0
+        });
+        assert!(minutes(after_one_change) > 69.0 / 2.0 + 5.0, "{after_one_change:?}");
+
+        let settled = run_curve(&mut estimator, start, 1250..1801, BatteryState::Discharging, |second| {
+            70.0 - (second - 1200.0) / 30.0
+        });
+        assert!((minutes(settled) - 50.0 / 2.0).abs() < 3.0, "{settled:?}");
+
+        // The load drops to nothing, so the estimate grows while the percent doesn't change
+        let idle = run_curve(&mut estimator, start, 1810..2401, BatteryState::Discharging, |_| 50.0);
+        assert!(minutes(idle) > 2.0 * minutes(settled), "{idle:?} {settled:?}");
+    }
+
+    #[test]
+    fn charging_estimates_time_until_full() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut estimator = Estimator::new();
+
+        // 2 percent per minute from 20 percent, so 40 percent (20 minutes) is left after 20 minutes
+        let estimate = run_curve(&mut estimator, start, 0..1201, BatteryState::Charging, |second| {
+            20.0 + second / 30.0
+        });
+
+        assert!(estimate.is_some_and(|estimate| estimate.confident), "{estimate:?}");
+        assert!((minutes(estimate) - 20.0).abs() < 1.0, "{estimate:?}");
+    }
+
+    #[test]
+    fn reported_time_is_used_until_the_rate_is_known() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut estimator = Estimator::new();
+        let reported = Some(Duration::from_hours(2));
+
+        let estimate = estimator.update(start, BatteryState::Discharging, 50, reported);
+        assert_eq!(
+            estimate,
+            Some(TimeEstimate {
+                remaining: Duration::from_hours(2),
+                confident: false,
+            })
+        );
+        assert_eq!(estimator.update(start, BatteryState::Discharging, 50, None), None);
+
+        // The first change only starts the timing, the second gives a rate which isn't confident yet
+        let _ = estimator.update(start + Duration::from_secs(30), BatteryState::Discharging, 49, reported);
+        let estimate = estimator.update(start + Duration::from_secs(90), BatteryState::Discharging, 48, reported);
+        assert!(estimate.is_some_and(|estimate| !estimate.confident), "{estimate:?}");
+        assert!((minutes(estimate) - 48.0).abs() < 0.1, "{estimate:?}");
+
+        // Changing state starts again, and there is no estimate when not charging or discharging
+        let estimate = estimator.update(start + Duration::from_secs(100), BatteryState::Charging, 48, None);
+        assert_eq!(estimate, None);
+        assert_eq!(
+            estimator.update(start + Duration::from_secs(110), BatteryState::FullyCharged, 100, reported),
+            None
+        );
+    }
+
+    #[test]
+    fn estimates_are_formatted() {
+        let estimate = |minutes: u64, confident: bool| TimeEstimate {
+            remaining: Duration::from_mins(minutes),
+            confident,
+        };
+
+        assert_eq!(estimate(151, true).to_string(), "2h 31m");
+        assert_eq!(estimate(45, true).to_string(), "45m");
+        assert_eq!(estimate(45, false).to_string(), "~45m");
+    }
+}
@@ -1,4 +1,11 @@
-pub use value::{Battery, BatteryGetCommands, BatteryItem, evaluate_item, match_get_commands};
+#[cfg(feature = "daemon")]
+pub use commands::{BatteryGetCommands, match_get_commands};
+pub use estimate::{Estimator, TimeEstimate, estimate_time};
+pub use source::COMMAND;
+pub use value::{Battery, BatteryItem, BatteryState, evaluate_item};
 
+#[cfg(feature = "daemon")]
+mod commands;
+mod estimate;
 mod source;
 mod value;
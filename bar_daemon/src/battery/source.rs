@@ -1,10 +1,13 @@
-use std::str::Split;
+use std::{str::Split, sync::Arc, time::Duration};
 
 use tracing::{instrument, warn};
 
-use super::value::{Battery, BatteryState};
+use super::{
+    estimate::estimate_time,
+    value::{Battery, BatteryState},
+};
 use crate::{
-    command,
+    command::{CommandRunner, default_runner},
     error::DaemonError,
     observed::Observed::{self},
     snapshot::update_snapshot,
@@ -15,36 +18,59 @@ pub trait BatterySource {
     fn read(&self) -> impl std::future::Future<Output = Result<Observed<Battery>, DaemonError>> + Send;
 }
 
+/// Command which the battery is read with
+pub const COMMAND: &str = "acpi";
+
 // -------------- Default Source ---------------
 
 #[must_use]
 pub fn default_source() -> impl BatterySource {
-    AcpiBattery
+    AcpiBattery::new(default_runner())
 }
 
 // ---------------- ACPI Source ----------------
 
 #[derive(Debug)]
-pub struct AcpiBattery;
+pub struct AcpiBattery {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl AcpiBattery {
+    #[must_use]
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    async fn read_inner(&self) -> Result<Battery, DaemonError> {
+        // Get ACPI output and split it into sections
+        let output = get_acpi_output(self.runner.as_ref()).await?;
+
+        // acpi prints nothing to stdout when there is no battery
+        if output.is_empty() {
+            return Err(DaemonError::DeviceAbsent(String::from("battery")));
+        }
+
+        let output_split = get_acpi_split(&output);
+
+        // Parse the state, percentage, and time remaining
+        let state = get_state_from_acpi_split(output_split.clone())?;
+        let percent = get_percent_from_acpi_split(output_split.clone())?;
+        let reported = get_time_from_acpi_split(output_split);
+
+        // acpi's time jumps with the load, so it is only used until the daemon's own estimate is known
+        Ok(Battery {
+            state,
+            percent,
+            time: estimate_time(state, percent, reported),
+        })
+    }
+}
 
 impl BatterySource for AcpiBattery {
     #[instrument]
     async fn read(&self) -> Result<Observed<Battery>, DaemonError> {
-        fn read_inner() -> Result<Battery, DaemonError> {
-            // Get ACPI output and split it into sections
-            let output = get_acpi_output()?;
-            let output_split = get_acpi_split(&output);
-
-            // Parse the state, percentage, and time remaining
-            Ok(Battery {
-                state: get_state_from_acpi_split(output_split.clone())?,
-                percent: get_percent_from_acpi_split(output_split.clone())?,
-                time: get_time_from_acpi_split(output_split)?,
-            })
-        }
-
         // Set as unavailable if the inner function threw an error
-        let battery: Observed<_> = read_inner().into();
+        let battery: Observed<_> = self.read_inner().await.into();
 
         // Update current snapshot
         let _update = update_snapshot(battery.clone()).await;
@@ -53,9 +79,9 @@ impl BatterySource for AcpiBattery {
     }
 }
 
-fn get_acpi_output() -> Result<String, DaemonError> {
+async fn get_acpi_output(runner: &dyn CommandRunner) -> Result<String, DaemonError> {
     // Get the output of the 'acpi -b' command
-    command::run("acpi", &["-b"])
+    runner.run(COMMAND, &["-b"]).await
 }
 
 fn get_acpi_split(output: &str) -> Split<'_, char> {
@@ -91,16 +117,99 @@ fn get_percent_from_acpi_split(mut output_split: std::str::Split<char>) -> Resul
 }
 
 #[instrument(skip(output_split))]
-fn get_time_from_acpi_split(mut output_split: std::str::Split<char>) -> Result<String, DaemonError> {
-    // Return empty string if the time part of the output_split is not present
-    let Some(time_string_unsplit) = output_split.nth(2) else {
-        return Ok(String::new());
-    };
-
-    // Get the time portion of the split
-    Ok(time_string_unsplit
-        .split_whitespace()
-        .next()
-        .ok_or_else(|| DaemonError::ParseError(output_split.collect::<String>()))?
-        .to_string())
+fn get_time_from_acpi_split(mut output_split: std::str::Split<char>) -> Option<Duration> {
+    // The time is left out when full, and is replaced by a message when acpi doesn't know the rate
+    let time_string = output_split.nth(2)?.split_whitespace().next()?;
+
+    // Parse the time from the HH:MM:SS format
+    let mut parts = time_string.split(':').map(str::parse::<u64>);
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(hours)), Some(Ok(minutes)), Some(Ok(seconds)), None) => {
+            Some(Duration::from_secs(hours * 3600 + minutes * 60 + seconds))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{AcpiBattery, Battery, BatteryState};
+    use crate::{battery::TimeEstimate, command::fake::FakeRunner, error::DaemonError};
+
+    fn source(output: &str) -> AcpiBattery {
+        AcpiBattery::new(Arc::new(FakeRunner::new().with_output("acpi -b", output)))
+    }
+
+    #[tokio::test]
+    async fn reads_each_battery_state() {
+        let battery = source("Battery 0: Discharging, 57%, 02:31:09 remaining").read_inner().await;
+        assert_eq!(
+            battery.ok(),
+            Some(Battery {
+                state: BatteryState::Discharging,
+                percent: 57,
+                // acpi's time is used until the rate is known
+                time: Some(TimeEstimate {
+                    remaining: Duration::from_secs(2 * 3600 + 31 * 60 + 9),
+                    confident: false,
+                }),
+            })
+        );
+
+        let battery = source("Battery 0: Charging, 80%, 00:20:00 until charged").read_inner().await;
+        assert_eq!(
+            battery.ok().map(|battery| (battery.state, battery.time)),
+            Some((
+                BatteryState::Charging,
+                Some(TimeEstimate {
+                    remaining: Duration::from_mins(20),
+                    confident: false,
+                })
+            ))
+        );
+
+        let battery = source("Battery 0: Discharging, 57%, rate information unavailable")
+            .read_inner()
+            .await;
+        assert_eq!(battery.ok().map(|battery| battery.time), Some(None));
+
+        let battery = source("Battery 0: Fully charged, 100%").read_inner().await;
+        assert_eq!(
+            battery.ok(),
+            Some(Battery {
+                state: BatteryState::FullyCharged,
+                percent: 100,
+                time: None,
+            })
+        );
+
+        let battery = source("Battery 0: Not charging, 95%").read_inner().await;
+        assert_eq!(battery.ok().map(|battery| battery.state), Some(BatteryState::NotCharging));
+    }
+
+    #[tokio::test]
+    async fn unknown_state_is_a_parse_error() {
+        let battery = source("Battery 0: Exploding, 57%").read_inner().await;
+
+        assert!(
+            matches!(battery, Err(DaemonError::ParseError(ref state)) if state == "Exploding"),
+            "{battery:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn unparseable_percent_is_an_error() {
+        let battery = source("Battery 0: Charging, lots").read_inner().await;
+
+        assert!(matches!(battery, Err(DaemonError::IntegerFromString(_))), "{battery:?}");
+    }
+
+    #[tokio::test]
+    async fn no_battery_is_device_absent() {
+        let battery = source("").read_inner().await;
+
+        assert!(matches!(battery, Err(DaemonError::DeviceAbsent(_))), "{battery:?}");
+    }
 }
@@ -1,6 +1,5 @@
 use std::sync::LazyLock;
 
-use clap::Subcommand;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use tracing::{error, instrument};
@@ -8,22 +7,25 @@ use tracing::{error, instrument};
 use crate::{
     ICON_EXT, NOTIFICATION_ID, command,
     config::get_config,
-    daemon::{DaemonItem, DaemonMessage, DaemonReply},
     error::DaemonError,
     impl_monitored,
     monitored::{Monitored, MonitoredUpdate},
     notification::Notify,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
+    observed::{
+        Observed::{self, Failed, Recovering, Unavailable, Valid},
+        UnavailableReason,
+    },
     polled::Polled,
-    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent},
+    protocol::{DaemonItem, DaemonReply},
+    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, with_timestamps},
     tuples::ToTuples,
 };
 
-use super::source::BatterySource;
+use super::{estimate::TimeEstimate, source::BatterySource};
 
 const NOTIFICATION_OFFSET: u32 = 0;
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug, Default, PartialOrd, Ord)]
 pub enum BatteryState {
     FullyCharged = 0,
     Charging = 1,
@@ -38,18 +40,6 @@ impl std::fmt::Display for BatteryState {
     }
 }
 
-#[derive(Subcommand)]
-pub enum BatteryGetCommands {
-    #[command(alias = "s")]
-    State,
-    #[command(alias = "per", alias = "p")]
-    Percent,
-    #[command(alias = "t")]
-    Time,
-    #[command(alias = "i")]
-    Icon,
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum BatteryItem {
     State,
@@ -73,12 +63,23 @@ struct BatteryNotifyState {
 static BAT_NOTIFY_STATE: LazyLock<RwLock<BatteryNotifyState>> = LazyLock::new(|| RwLock::new(BatteryNotifyState::default()));
 
 #[derive(
-    Clone, Debug, Default, PartialEq, PartialOrd, Ord, Eq, bar_daemon_derive::Polled, bar_daemon_derive::IntoSnapshotEvent,
+    Serialize,
+    Deserialize,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    PartialOrd,
+    Ord,
+    Eq,
+    bar_daemon_derive::Polled,
+    bar_daemon_derive::IntoSnapshotEvent,
 )]
 pub struct Battery {
     pub state: BatteryState,
     pub percent: u32,
-    pub time: String,
+    /// Estimated time until the battery is empty or full (`None` when it isn't charging or discharging, or nothing is known yet)
+    pub time: Option<TimeEstimate>,
 }
 
 impl_monitored!(Battery, battery, battery);
@@ -126,7 +127,7 @@ impl ToTuples for Battery {
             vec![
                 state.to_string(),
                 percent.to_string(),
-                time.clone(),
+                time.map(|time| time.to_string()).unwrap_or_default(),
                 format!("{icon}{ICON_EXT}"),
             ]
         };
@@ -142,7 +143,7 @@ impl Notify<Self> for Battery {
     /// Returns an error if notification command could not be run
     #[instrument]
     async fn notify(update: MonitoredUpdate<Self>) -> Result<(), DaemonError> {
-        fn do_notification(battery: &Battery) -> Result<(), DaemonError> {
+        async fn do_notification(battery: &Battery) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -158,12 +159,13 @@ impl Notify<Self> for Battery {
                     format!("int:value:{}", battery.percent).as_str(),
                     "Battery: ",
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
 
-        fn do_notification_unavailable() -> Result<(), DaemonError> {
+        async fn do_notification_unavailable(reason: Option<&UnavailableReason>) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -173,9 +175,11 @@ impl Notify<Self> for Battery {
                     get_config().notification_timeout.to_string().as_str(),
                     "-r",
                     (NOTIFICATION_ID + NOTIFICATION_OFFSET).to_string().as_str(),
-                    format!("Battery Unavailable: {}", Unavailable::<Battery>).as_str(),
+                    "Battery Unavailable",
+                    reason.map(ToString::to_string).unwrap_or_default().as_str(),
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
@@ -189,7 +193,7 @@ impl Notify<Self> for Battery {
                     *(BAT_NOTIFY_STATE.write().await) = BatteryNotifyState::default();
 
                     // Perform the notification
-                    do_notification(&new)?;
+                    do_notification(&new).await?;
 
                     // Because of this notification, no further checks need to be done
                     return Ok(());
@@ -205,7 +209,7 @@ impl Notify<Self> for Battery {
                             BAT_NOTIFY_STATE.write().await.high = true;
 
                             // Perform the notification
-                            do_notification(&new)?;
+                            do_notification(&new).await?;
                         }
                     }
                     BatteryState::Discharging => {
@@ -217,7 +221,7 @@ impl Notify<Self> for Battery {
                                 BAT_NOTIFY_STATE.write().await.low[i] = true;
 
                                 // Perform the notification
-                                do_notification(&new)?;
+                                do_notification(&new).await?;
                             }
                         }
                     }
@@ -228,12 +232,12 @@ impl Notify<Self> for Battery {
                             BAT_NOTIFY_STATE.write().await.not_charging = true;
 
                             // Perform the notification
-                            do_notification(&new)?;
+                            do_notification(&new).await?;
                         }
                     }
                 }
             }
-            Unavailable | Recovering => do_notification_unavailable()?,
+            Unavailable(_) | Recovering | Failed { .. } => do_notification_unavailable(update.new.reason()).await?,
         }
 
         Ok(())
@@ -244,44 +248,20 @@ impl Notify<Self> for Battery {
 /// Returns an error if the requested value could not be parsed
 #[instrument]
 pub async fn evaluate_item(item: DaemonItem, battery_item: &BatteryItem) -> Result<DaemonReply, DaemonError> {
-    Ok(
-        // Get value (use latest() since this value changes without bar_daemon changing it)
-        match battery_item {
-            BatteryItem::State => DaemonReply::Value {
-                item,
-                value: Battery::latest().await?.map(|battery| battery.state.to_string()).to_string(),
-            },
-            BatteryItem::Percent => DaemonReply::Value {
-                item,
-                value: Battery::latest().await?.map(|battery| battery.percent).to_string(),
-            },
-            BatteryItem::Time => DaemonReply::Value {
-                item,
-                value: Battery::latest().await?.map(|battery| battery.time).to_string(),
-            },
-            BatteryItem::Icon => DaemonReply::Value {
-                item,
-                value: Battery::latest().await?.map(|battery| battery.get_icon()).to_string(),
-            },
-            BatteryItem::All => DaemonReply::Tuples {
-                item,
-                tuples: Battery::latest().await?.to_tuples(),
-            },
-        },
-    )
-}
-
-#[must_use]
-pub const fn match_get_commands(commands: &Option<BatteryGetCommands>) -> DaemonMessage {
-    DaemonMessage::Get {
-        item: match commands {
-            Some(commands) => match commands {
-                BatteryGetCommands::State => DaemonItem::Battery(BatteryItem::State),
-                BatteryGetCommands::Percent => DaemonItem::Battery(BatteryItem::Percent),
-                BatteryGetCommands::Time => DaemonItem::Battery(BatteryItem::Time),
-                BatteryGetCommands::Icon => DaemonItem::Battery(BatteryItem::Icon),
-            },
-            None => DaemonItem::Battery(BatteryItem::All),
+    // Get value (use latest() since this value changes without bar_daemon changing it)
+    let battery = Battery::latest().await?;
+
+    Ok(match battery_item {
+        BatteryItem::State => DaemonReply::from_observed(item, battery.map(|battery| battery.state)),
+        BatteryItem::Percent => DaemonReply::from_observed(item, battery.map(|battery| battery.percent)),
+        BatteryItem::Time => DaemonReply::from_observed(
+            item,
+            battery.map(|battery| battery.time.map(|time| time.to_string()).unwrap_or_default()),
+        ),
+        BatteryItem::Icon => DaemonReply::from_observed(item, battery.map(|battery| battery.get_icon())),
+        BatteryItem::All => DaemonReply::Tuples {
+            item,
+            tuples: with_timestamps::<Battery>(battery.to_tuples()).await,
         },
-    }
+    })
 }
@@ -0,0 +1,48 @@
+use clap::{ArgAction, Subcommand};
+
+use crate::{
+    cli::parse_bool,
+    protocol::{DaemonItem, DaemonMessage},
+};
+
+use super::BluetoothItem;
+
+#[derive(Subcommand)]
+pub enum BluetoothGetCommands {
+    #[command(alias = "s")]
+    State,
+    #[command(alias = "i")]
+    Icon,
+}
+
+#[derive(Subcommand)]
+pub enum BluetoothSetCommands {
+    #[command(alias = "s")]
+    State {
+        #[arg(action = ArgAction::Set, value_parser = parse_bool)]
+        value: Option<bool>,
+    },
+}
+
+#[must_use]
+pub const fn match_get_commands(commands: &Option<BluetoothGetCommands>) -> DaemonMessage {
+    DaemonMessage::Get {
+        item: match commands {
+            Some(commands) => match commands {
+                BluetoothGetCommands::State => DaemonItem::Bluetooth(BluetoothItem::State),
+                BluetoothGetCommands::Icon => DaemonItem::Bluetooth(BluetoothItem::Icon),
+            },
+            None => DaemonItem::Bluetooth(BluetoothItem::All),
+        },
+    }
+}
+
+#[must_use]
+pub fn match_set_commands(commands: &BluetoothSetCommands) -> DaemonMessage {
+    match commands {
+        BluetoothSetCommands::State { value } => DaemonMessage::Set {
+            item: DaemonItem::Bluetooth(BluetoothItem::State),
+            value: value.map_or("toggle".to_string(), |value| value.to_string()),
+        },
+    }
+}
@@ -1,9 +1,11 @@
 use source::BluetoothSource;
 
-pub use source::default_source;
-pub use value::{
-    Bluetooth, BluetoothGetCommands, BluetoothItem, BluetoothSetCommands, evaluate_item, match_get_commands, match_set_commands,
-};
+#[cfg(feature = "daemon")]
+pub use commands::{BluetoothGetCommands, BluetoothSetCommands, match_get_commands, match_set_commands};
+pub use source::{COMMAND, default_source};
+pub use value::{Bluetooth, BluetoothItem, evaluate_item};
 
+#[cfg(feature = "daemon")]
+mod commands;
 mod source;
 mod value;
@@ -1,11 +1,12 @@
+use std::sync::Arc;
+
 use tracing::instrument;
 
 use crate::{
-    command,
+    command::{CommandRunner, default_runner},
     error::DaemonError,
-    monitored::Monitored,
     observed::Observed::{self, Valid},
-    snapshot::{current_snapshot, update_snapshot},
+    snapshot::{known_value, require_known, update_snapshot},
 };
 
 use super::Bluetooth;
@@ -18,37 +19,49 @@ pub trait BluetoothSource {
     fn set_state(&self, state_str: &str) -> impl std::future::Future<Output = Result<(), DaemonError>> + Send;
 }
 
+/// Command which the bluetooth state is read and set with
+pub const COMMAND: &str = "bluetooth";
+
 // -------------- Default Source ---------------
 
 #[must_use]
 pub fn default_source() -> impl BluetoothSource {
-    BluezBluetooth
+    BluezBluetooth::new(default_runner())
 }
 
 // ---------------- Bluez Source ---------------
 
 #[derive(Debug)]
-pub struct BluezBluetooth;
+pub struct BluezBluetooth {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl BluezBluetooth {
+    #[must_use]
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    async fn read_inner(&self) -> Result<Bluetooth, DaemonError> {
+        // Get output for bluetooth command (From Bluez)
+        let output = self.runner.run(COMMAND, &[]).await?;
+
+        // Split the output and check if it is on or off
+        output
+            .clone()
+            .split_whitespace()
+            .nth(2)
+            .map_or(Err(DaemonError::ParseError(output)), |state| {
+                Ok(Bluetooth { state: state == "on" })
+            })
+    }
+}
 
 impl BluetoothSource for BluezBluetooth {
     #[instrument]
     async fn read(&self) -> Result<Observed<Bluetooth>, DaemonError> {
-        fn read_inner() -> Result<Bluetooth, DaemonError> {
-            // Get output for bluetooth command (From Bluez)
-            let output = command::run("bluetooth", &[])?;
-
-            // Split the output and check if it is on or off
-            output
-                .clone()
-                .split_whitespace()
-                .nth(2)
-                .map_or(Err(DaemonError::ParseError(output)), |state| {
-                    Ok(Bluetooth { state: state == "on" })
-                })
-        }
-
         // Set as unavailable if the inner function threw an error
-        let bluetooth: Observed<_> = read_inner().into();
+        let bluetooth: Observed<_> = self.read_inner().await.into();
 
         // Update current snapshot
         let _update = update_snapshot(bluetooth.clone()).await;
@@ -58,33 +71,17 @@ impl BluetoothSource for BluezBluetooth {
 
     #[instrument]
     async fn set_state(&self, state_str: &str) -> Result<(), DaemonError> {
-        let new_state;
+        // Only toggling needs the current bluetooth state
+        let known = if state_str == "toggle" {
+            known_value(self.read()).await
+        } else {
+            None
+        };
 
         // Allow toggling of the bluetooth state
-        let state = match state_str {
-            "toggle" => {
-                new_state = !current_snapshot()
-                    .await
-                    .bluetooth
-                    .unwrap_or(Bluetooth::latest().await?.unwrap_or_default())
-                    .state;
-
-                "toggle"
-            }
-            _ => {
-                if state_str.parse::<bool>()? {
-                    new_state = true;
+        let (state, new_state) = get_state_from_str(state_str, known.as_ref())?;
 
-                    "on"
-                } else {
-                    new_state = false;
-
-                    "off"
-                }
-            }
-        };
-
-        command::run("bluetooth", &[state])?;
+        self.runner.run(COMMAND, &[state]).await?;
 
         // Change the value within the snapshot
         let _update = update_snapshot(Valid(Bluetooth { state: new_state })).await;
@@ -92,3 +89,83 @@ impl BluetoothSource for BluezBluetooth {
         Ok(())
     }
 }
+
+// ------------- Helper Functions --------------
+
+/// # Documentation
+/// Get the argument for the `bluetooth` command and the state which `state_str` will result in
+fn get_state_from_str(state_str: &str, known: Option<&Bluetooth>) -> Result<(&'static str, bool), DaemonError> {
+    match state_str {
+        "toggle" => Ok(("toggle", !require_known(known)?.state)),
+        _ => {
+            if state_str.parse::<bool>()? {
+                Ok(("on", true))
+            } else {
+                Ok(("off", false))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Bluetooth, BluetoothSource, BluezBluetooth, get_state_from_str};
+    use crate::{command::fake::FakeRunner, error::DaemonError};
+
+    fn source(output: &str) -> BluezBluetooth {
+        BluezBluetooth::new(Arc::new(FakeRunner::new().with_output("bluetooth", output)))
+    }
+
+    #[tokio::test]
+    async fn reads_bluetooth_state() {
+        assert_eq!(
+            source("bluetooth = on").read_inner().await.ok(),
+            Some(Bluetooth { state: true })
+        );
+        assert_eq!(
+            source("bluetooth = off").read_inner().await.ok(),
+            Some(Bluetooth { state: false })
+        );
+    }
+
+    #[tokio::test]
+    async fn truncated_output_is_a_parse_error() {
+        let bluetooth = source("bluetooth").read_inner().await;
+
+        assert!(matches!(bluetooth, Err(DaemonError::ParseError(_))), "{bluetooth:?}");
+    }
+
+    #[tokio::test]
+    async fn set_runs_bluetooth() {
+        let runner = Arc::new(
+            FakeRunner::new()
+                .with_output("bluetooth on", "")
+                .with_output("bluetooth off", ""),
+        );
+        let source = BluezBluetooth::new(runner.clone());
+
+        assert!(source.set_state("true").await.is_ok());
+        assert!(source.set_state("false").await.is_ok());
+        assert_eq!(runner.invocations(), ["bluetooth on", "bluetooth off"]);
+    }
+
+    #[test]
+    fn toggle_uses_known_state() {
+        assert_eq!(
+            get_state_from_str("toggle", Some(&Bluetooth { state: true })).ok(),
+            Some(("toggle", false))
+        );
+        assert_eq!(get_state_from_str("true", None).ok(), Some(("on", true)));
+        assert_eq!(get_state_from_str("false", None).ok(), Some(("off", false)));
+        assert!(matches!(
+            get_state_from_str("toggle", None),
+            Err(DaemonError::NoKnownValue(_))
+        ));
+        assert!(matches!(
+            get_state_from_str("maybe", None),
+            Err(DaemonError::BoolFromString(_))
+        ));
+    }
+}
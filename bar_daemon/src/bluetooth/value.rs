@@ -1,19 +1,19 @@
-use clap::{ArgAction, Subcommand};
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
 use crate::{
-    ICON_END, ICON_EXT, NOTIFICATION_ID,
-    cli::parse_bool,
-    command,
+    ICON_END, ICON_EXT, NOTIFICATION_ID, command,
     config::get_config,
-    daemon::{DaemonItem, DaemonMessage, DaemonReply},
     error::DaemonError,
     impl_monitored,
     monitored::{Monitored, MonitoredUpdate},
     notification::Notify,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
-    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot},
+    observed::{
+        Observed::{self, Failed, Recovering, Unavailable, Valid},
+        UnavailableReason,
+    },
+    protocol::{DaemonItem, DaemonReply},
+    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot, with_timestamps},
     tuples::ToTuples,
 };
 
@@ -21,23 +21,6 @@ use super::{BluetoothSource, default_source};
 
 const NOTIFICATION_OFFSET: u32 = 1;
 
-#[derive(Subcommand)]
-pub enum BluetoothGetCommands {
-    #[command(alias = "s")]
-    State,
-    #[command(alias = "i")]
-    Icon,
-}
-
-#[derive(Subcommand)]
-pub enum BluetoothSetCommands {
-    #[command(alias = "s")]
-    State {
-        #[arg(action = ArgAction::Set, value_parser = parse_bool)]
-        value: Option<bool>,
-    },
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum BluetoothItem {
     State,
@@ -45,7 +28,7 @@ pub enum BluetoothItem {
     All,
 }
 
-#[derive(Clone, Debug, Default, PartialEq, PartialOrd, Ord, Eq, bar_daemon_derive::IntoSnapshotEvent)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, PartialOrd, Ord, Eq, bar_daemon_derive::IntoSnapshotEvent)]
 pub struct Bluetooth {
     pub state: bool,
 }
@@ -87,7 +70,7 @@ impl Notify<Self> for Bluetooth {
     /// Returns an error if notification command could not be run
     #[instrument]
     async fn notify(update: MonitoredUpdate<Self>) -> Result<(), DaemonError> {
-        fn do_notification(new: &Bluetooth) -> Result<(), DaemonError> {
+        async fn do_notification(new: &Bluetooth) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -101,12 +84,13 @@ impl Notify<Self> for Bluetooth {
                     get_config().notification_timeout.to_string().as_str(),
                     format!("Bluetooth: {}", if new.state { "on" } else { "off" }).as_str(),
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
 
-        fn do_notification_unavailable() -> Result<(), DaemonError> {
+        async fn do_notification_unavailable(reason: Option<&UnavailableReason>) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -117,16 +101,18 @@ impl Notify<Self> for Bluetooth {
                     "-t",
                     get_config().notification_timeout.to_string().as_str(),
                     "Bluetooth Unavailable",
+                    reason.map(ToString::to_string).unwrap_or_default().as_str(),
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
 
         // If the new values are valid
         match update.new {
-            Valid(new) => do_notification(&new)?,
-            Unavailable | Recovering => do_notification_unavailable()?,
+            Valid(new) => do_notification(&new).await?,
+            Unavailable(_) | Recovering | Failed { .. } => do_notification_unavailable(update.new.reason()).await?,
         }
 
         Ok(())
@@ -150,48 +136,18 @@ pub async fn evaluate_item(
         DaemonReply::Value { item, value }
     } else {
         // Get value
+        let bluetooth = match current_snapshot().await.bluetooth {
+            Valid(bluetooth) => Valid(bluetooth),
+            Unavailable(_) | Recovering | Failed { .. } => Bluetooth::latest().await?,
+        };
+
         match bluetooth_item {
-            BluetoothItem::State => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.bluetooth {
-                    Valid(bluetooth) => bluetooth.state.to_string(),
-                    Unavailable | Recovering => Bluetooth::latest().await?.map(|bluetooth| bluetooth.state).to_string(),
-                },
-            },
-            BluetoothItem::Icon => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.bluetooth {
-                    Valid(bluetooth) => bluetooth.get_icon(),
-                    Unavailable | Recovering => Bluetooth::latest().await?.map(|bluetooth| bluetooth.get_icon()).to_string(),
-                },
-            },
+            BluetoothItem::State => DaemonReply::from_observed(item, bluetooth.map(|bluetooth| bluetooth.state)),
+            BluetoothItem::Icon => DaemonReply::from_observed(item, bluetooth.map(|bluetooth| bluetooth.get_icon())),
             BluetoothItem::All => DaemonReply::Tuples {
                 item,
-                tuples: Bluetooth::latest().await?.to_tuples(),
+                tuples: with_timestamps::<Bluetooth>(Bluetooth::latest().await?.to_tuples()).await,
             },
         }
     })
 }
-
-#[must_use]
-pub const fn match_get_commands(commands: &Option<BluetoothGetCommands>) -> DaemonMessage {
-    DaemonMessage::Get {
-        item: match commands {
-            Some(commands) => match commands {
-                BluetoothGetCommands::State => DaemonItem::Bluetooth(BluetoothItem::State),
-                BluetoothGetCommands::Icon => DaemonItem::Bluetooth(BluetoothItem::Icon),
-            },
-            None => DaemonItem::Bluetooth(BluetoothItem::All),
-        },
-    }
-}
-
-#[must_use]
-pub fn match_set_commands(commands: &BluetoothSetCommands) -> DaemonMessage {
-    match commands {
-        BluetoothSetCommands::State { value } => DaemonMessage::Set {
-            item: DaemonItem::Bluetooth(BluetoothItem::State),
-            value: value.map_or("toggle".to_string(), |value| value.to_string()),
-        },
-    }
-}
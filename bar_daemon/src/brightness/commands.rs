@@ -0,0 +1,57 @@
+use clap::Subcommand;
+
+use crate::protocol::{DaemonItem, DaemonMessage};
+
+use super::BrightnessItem;
+
+#[derive(Subcommand)]
+pub enum BrightnessGetCommands {
+    #[command(alias = "mon", alias = "m")]
+    Monitor,
+    #[command(alias = "key", alias = "k")]
+    Keyboard,
+    #[command(alias = "i")]
+    Icon,
+}
+
+#[derive(Subcommand)]
+pub enum BrightnessSetCommands {
+    #[command(alias = "mon", alias = "m")]
+    Monitor {
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+    #[command(alias = "key", alias = "k")]
+    Keyboard {
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+}
+
+#[must_use]
+pub const fn match_get_commands(commands: &Option<BrightnessGetCommands>) -> DaemonMessage {
+    DaemonMessage::Get {
+        item: match commands {
+            Some(commands) => match commands {
+                BrightnessGetCommands::Monitor => DaemonItem::Brightness(BrightnessItem::Monitor),
+                BrightnessGetCommands::Keyboard => DaemonItem::Brightness(BrightnessItem::Keyboard),
+                BrightnessGetCommands::Icon => DaemonItem::Brightness(BrightnessItem::Icon),
+            },
+            None => DaemonItem::Brightness(BrightnessItem::All),
+        },
+    }
+}
+
+#[must_use]
+pub fn match_set_commands(commands: BrightnessSetCommands) -> DaemonMessage {
+    match commands {
+        BrightnessSetCommands::Monitor { value } => DaemonMessage::Set {
+            item: DaemonItem::Brightness(BrightnessItem::Monitor),
+            value,
+        },
+        BrightnessSetCommands::Keyboard { value } => DaemonMessage::Set {
+            item: DaemonItem::Brightness(BrightnessItem::Keyboard),
+            value,
+        },
+    }
+}
@@ -1,10 +1,11 @@
 use source::{BrightnessSource, default_source};
 
-pub use source::{KEYBOARD_ID, MONITOR_ID};
-pub use value::{
-    Brightness, BrightnessGetCommands, BrightnessItem, BrightnessSetCommands, evaluate_item, match_get_commands,
-    match_set_commands,
-};
+#[cfg(feature = "daemon")]
+pub use commands::{BrightnessGetCommands, BrightnessSetCommands, match_get_commands, match_set_commands};
+pub use source::{COMMAND, KEYBOARD_ID, MONITOR_ID};
+pub use value::{Brightness, BrightnessItem, evaluate_item};
 
+#[cfg(feature = "daemon")]
+mod commands;
 mod source;
 mod value;
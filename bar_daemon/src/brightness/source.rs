@@ -1,14 +1,13 @@
-use std::str::Split;
+use std::{str::Split, sync::Arc};
 
 use itertools::Itertools;
 use tracing::instrument;
 
 use crate::{
-    command,
+    command::{CommandRunner, default_runner},
     error::DaemonError,
-    monitored::Monitored,
     observed::Observed::{self},
-    snapshot::{current_snapshot, update_snapshot},
+    snapshot::{known_value, require_known, update_snapshot},
 };
 
 use super::Brightness;
@@ -25,17 +24,53 @@ pub trait BrightnessSource {
     fn set_keyboard(&self, percent_str: &str) -> impl std::future::Future<Output = Result<(), DaemonError>> + Send;
 }
 
+/// Command which the brightness is read and set with
+pub const COMMAND: &str = "brightnessctl";
+
 // -------------- Default Source ---------------
 
 #[must_use]
 pub fn default_source() -> impl BrightnessSource {
-    BctlBrightness
+    BctlBrightness::new(default_runner())
 }
 
 // ---------------- Bctl Source ----------------
 
 #[derive(Debug)]
-pub struct BctlBrightness;
+pub struct BctlBrightness {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl BctlBrightness {
+    #[must_use]
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    async fn read_inner(&self) -> Result<Brightness, DaemonError> {
+        // Get the brightness via brightnessctl
+        let monitor = read_bctl_device(self.runner.as_ref(), MONITOR_ID).await?;
+        let keyboard = read_bctl_device(self.runner.as_ref(), KEYBOARD_ID).await?;
+
+        Ok(Brightness { monitor, keyboard })
+    }
+
+    async fn set_device(&self, device_id: &str, percent_str: &str) -> Result<(), DaemonError> {
+        // Only a change in percentage needs the current brightness
+        let known = if percent_str.starts_with('+') || percent_str.starts_with('-') {
+            known_value(self.read()).await
+        } else {
+            None
+        };
+
+        set_bctl_device(self.runner.as_ref(), device_id, percent_str, known.as_ref()).await?;
+
+        // Read the brightness back, which updates the snapshot
+        let _brightness = self.read().await?;
+
+        Ok(())
+    }
+}
 
 impl BrightnessSource for BctlBrightness {
     /// # Errors
@@ -43,16 +78,8 @@ impl BrightnessSource for BctlBrightness {
     /// Returns an error if values in the output of the command cannot be parsed
     #[instrument]
     async fn read(&self) -> Result<Observed<Brightness>, DaemonError> {
-        fn read_inner() -> Result<Brightness, DaemonError> {
-            // Get the brightness via brightnessctl
-            let monitor = read_bctl_device(MONITOR_ID)?;
-            let keyboard = read_bctl_device(KEYBOARD_ID)?;
-
-            Ok(Brightness { monitor, keyboard })
-        }
-
         // Set as unavailable if the inner function threw an error
-        let brightness: Observed<_> = read_inner().into();
+        let brightness: Observed<_> = self.read_inner().await.into();
 
         // Update the snapshot
         let _update = update_snapshot(brightness.clone()).await;
@@ -65,15 +92,7 @@ impl BrightnessSource for BctlBrightness {
     /// Returns an error if values in the output of the command cannot be parsed
     #[instrument]
     async fn set_monitor(&self, percent_str: &str) -> Result<(), DaemonError> {
-        set_bctl_device(MONITOR_ID, percent_str).await?;
-
-        // Get brightness from latest()
-        let brightness = Brightness::latest().await?;
-
-        // Update snapshot
-        let _update = update_snapshot(brightness).await;
-
-        Ok(())
+        self.set_device(MONITOR_ID, percent_str).await
     }
 
     /// # Errors
@@ -81,21 +100,13 @@ impl BrightnessSource for BctlBrightness {
     /// Returns an error if values in the output of the command cannot be parsed
     #[instrument]
     async fn set_keyboard(&self, percent_str: &str) -> Result<(), DaemonError> {
-        set_bctl_device(KEYBOARD_ID, percent_str).await?;
-
-        // Get brightness from latest()
-        let brightness = Brightness::latest().await?;
-
-        // Update snapshot
-        let _update = update_snapshot(brightness).await;
-
-        Ok(())
+        self.set_device(KEYBOARD_ID, percent_str).await
     }
 }
 
-fn get_bctl_output(device_id: &str) -> Result<String, DaemonError> {
+async fn get_bctl_output(runner: &dyn CommandRunner, device_id: &str) -> Result<String, DaemonError> {
     // Get brightness output of device
-    command::run("brightnessctl", &["-m", "-d", device_id, "i"])
+    runner.run(COMMAND, &["-m", "-d", device_id, "i"]).await
 }
 
 fn get_bctl_split(output: &str) -> Split<'_, char> {
@@ -125,22 +136,51 @@ fn get_bctl_percentage_from_split(mut split: Split<'_, char>) -> Result<u32, Dae
 /// # Errors
 /// Returns an error if the command cannot be spawned
 /// Returns an error if values in the output of the command cannot be parsed
-#[instrument]
-fn read_bctl_device(device_id: &str) -> Result<u32, DaemonError> {
-    let output = get_bctl_output(device_id)?;
+#[instrument(skip(runner))]
+async fn read_bctl_device(runner: &dyn CommandRunner, device_id: &str) -> Result<u32, DaemonError> {
+    let output = get_bctl_output(runner, device_id).await.map_err(|e| match e {
+        // brightnessctl fails with "Device '...' not found." when the device doesn't exist
+        DaemonError::CommandError { ref stderr, .. } if stderr.contains("not found") => {
+            DaemonError::DeviceAbsent(device_id.to_string())
+        }
+        e => e,
+    })?;
+
+    // brightnessctl prints nothing to stdout when the device doesn't exist
+    if output.is_empty() {
+        return Err(DaemonError::DeviceAbsent(device_id.to_string()));
+    }
+
     let output_split = get_bctl_split(&output);
 
     get_bctl_percentage_from_split(output_split)
 }
 
-#[instrument]
-async fn set_bctl_device(device_id: &str, percent_str: &str) -> Result<(), DaemonError> {
+#[instrument(skip(runner))]
+async fn set_bctl_device(
+    runner: &dyn CommandRunner,
+    device_id: &str,
+    percent_str: &str,
+    known: Option<&Brightness>,
+) -> Result<(), DaemonError> {
+    let percent = get_bctl_percent_from_str(device_id, percent_str, known)?;
+
+    // Set the percentage
+    runner
+        .run(COMMAND, &["-d", device_id, "s", format!("{percent}%").as_str()])
+        .await?;
+
+    Ok(())
+}
+
+// ------------- Helper Functions --------------
+
+/// # Documentation
+/// Get the percentage which `percent_str` sets the device to, relative changes (+5, -5) are applied to `known`
+fn get_bctl_percent_from_str(device_id: &str, percent_str: &str, known: Option<&Brightness>) -> Result<f64, DaemonError> {
     // Change the percentage based on the delta percentage
-    let percent = if percent_str.starts_with('+') || percent_str.starts_with('-') {
-        let current_brightness = current_snapshot()
-            .await
-            .brightness
-            .unwrap_or(Brightness::latest().await?.unwrap_or_default());
+    Ok(if percent_str.starts_with('+') || percent_str.starts_with('-') {
+        let current_brightness = require_known(known)?;
 
         let delta_percent = percent_str.parse::<f64>()?;
 
@@ -155,10 +195,101 @@ async fn set_bctl_device(device_id: &str, percent_str: &str) -> Result<(), Daemo
         (current_percent + delta_percent).clamp(0.0, 100.0)
     } else {
         percent_str.parse::<f64>()?
-    };
+    })
+}
 
-    // Set the percentage
-    command::run("brightnessctl", &["-d", device_id, "s", format!("{percent}%").as_str()])?;
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{BctlBrightness, Brightness, BrightnessSource, KEYBOARD_ID, MONITOR_ID, get_bctl_percent_from_str};
+    use crate::{command::fake::FakeRunner, error::DaemonError};
+
+    const MONITOR_INFO: &str = "brightnessctl -m -d nvidia_wmi_ec_backlight i";
+    const KEYBOARD_INFO: &str = "brightnessctl -m -d asus::kbd_backlight i";
+
+    #[tokio::test]
+    async fn reads_monitor_and_keyboard() {
+        let source = BctlBrightness::new(Arc::new(
+            FakeRunner::new()
+                .with_output(MONITOR_INFO, "nvidia_wmi_ec_backlight,backlight,60,60%,100")
+                .with_output(KEYBOARD_INFO, "asus::kbd_backlight,leds,1,33%,3"),
+        ));
+
+        assert_eq!(
+            source.read_inner().await.ok(),
+            Some(Brightness {
+                monitor: 60,
+                keyboard: 33
+            })
+        );
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn missing_device_is_device_absent() {
+        let runner = FakeRunner::new()
+            .with_output(MONITOR_INFO, "nvidia_wmi_ec_backlight,backlight,60,60%,100")
+            .with_failure(KEYBOARD_INFO, "Device 'asus::kbd_backlight' not found.");
+        let brightness = BctlBrightness::new(Arc::new(runner)).read_inner().await;
+
+        assert!(
+            matches!(brightness, Err(DaemonError::DeviceAbsent(ref id)) if id == KEYBOARD_ID),
+            "{brightness:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn truncated_output_is_a_parse_error() {
+        let runner = FakeRunner::new().with_output(MONITOR_INFO, "nvidia_wmi_ec_backlight,backlight,60");
+        let brightness = BctlBrightness::new(Arc::new(runner)).read_inner().await;
+
+        assert!(matches!(brightness, Err(DaemonError::ParseError(_))), "{brightness:?}");
+    }
+
+    #[tokio::test]
+    async fn set_runs_brightnessctl_for_each_device() {
+        let set_monitor = "brightnessctl -d nvidia_wmi_ec_backlight s 40%";
+        let set_keyboard = "brightnessctl -d asus::kbd_backlight s 100%";
+        let runner = Arc::new(
+            FakeRunner::new()
+                .with_output(set_monitor, "")
+                .with_output(set_keyboard, "")
+                .with_output(MONITOR_INFO, "nvidia_wmi_ec_backlight,backlight,40,40%,100")
+                .with_output(KEYBOARD_INFO, "asus::kbd_backlight,leds,3,100%,3"),
+        );
+        let source = BctlBrightness::new(runner.clone());
+
+        // The brightness is read back with the same runner after each set
+        assert!(source.set_monitor("40").await.is_ok());
+        assert!(source.set_keyboard("100").await.is_ok());
+        assert_eq!(
+            runner.invocations(),
+            [
+                set_monitor,
+                MONITOR_INFO,
+                KEYBOARD_INFO,
+                set_keyboard,
+                MONITOR_INFO,
+                KEYBOARD_INFO
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_percent_changes_the_right_device() {
+        let known = Brightness {
+            monitor: 50,
+            keyboard: 100,
+        };
+
+        assert_eq!(get_bctl_percent_from_str(MONITOR_ID, "20", None).ok(), Some(20.0));
+        assert_eq!(get_bctl_percent_from_str(MONITOR_ID, "+10", Some(&known)).ok(), Some(60.0));
+        assert_eq!(get_bctl_percent_from_str(KEYBOARD_ID, "-10", Some(&known)).ok(), Some(90.0));
+        assert_eq!(get_bctl_percent_from_str(KEYBOARD_ID, "+10", Some(&known)).ok(), Some(100.0));
+        assert_eq!(get_bctl_percent_from_str(MONITOR_ID, "-80", Some(&known)).ok(), Some(0.0));
+        assert!(matches!(
+            get_bctl_percent_from_str(MONITOR_ID, "+10", None),
+            Err(DaemonError::NoKnownValue(_))
+        ));
+    }
 }
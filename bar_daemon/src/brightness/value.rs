@@ -1,4 +1,3 @@
-use clap::Subcommand;
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
@@ -8,13 +7,16 @@ use crate::{
     changed::{Changed, ChangedConstructor},
     command,
     config::get_config,
-    daemon::{DaemonItem, DaemonMessage, DaemonReply},
     error::DaemonError,
     impl_monitored,
     monitored::{Monitored, MonitoredUpdate},
     notification::Notify,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
-    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot},
+    observed::{
+        Observed::{self, Failed, Recovering, Unavailable, Valid},
+        UnavailableReason,
+    },
+    protocol::{DaemonItem, DaemonReply},
+    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot, with_timestamps},
     tuples::ToTuples,
 };
 
@@ -22,30 +24,6 @@ use super::{BrightnessSource, MONITOR_ID, default_source};
 
 const NOTIFICATION_OFFSET: u32 = 2;
 
-#[derive(Subcommand)]
-pub enum BrightnessGetCommands {
-    #[command(alias = "mon", alias = "m")]
-    Monitor,
-    #[command(alias = "key", alias = "k")]
-    Keyboard,
-    #[command(alias = "i")]
-    Icon,
-}
-
-#[derive(Subcommand)]
-pub enum BrightnessSetCommands {
-    #[command(alias = "mon", alias = "m")]
-    Monitor {
-        #[arg(allow_hyphen_values = true)]
-        value: String,
-    },
-    #[command(alias = "key", alias = "k")]
-    Keyboard {
-        #[arg(allow_hyphen_values = true)]
-        value: String,
-    },
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum BrightnessItem {
     Monitor,
@@ -55,7 +33,17 @@ pub enum BrightnessItem {
 }
 
 #[derive(
-    Clone, Debug, Default, PartialEq, PartialOrd, Ord, Eq, bar_daemon_derive::Changed, bar_daemon_derive::IntoSnapshotEvent,
+    Serialize,
+    Deserialize,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    PartialOrd,
+    Ord,
+    Eq,
+    bar_daemon_derive::Changed,
+    bar_daemon_derive::IntoSnapshotEvent,
 )]
 pub struct Brightness {
     pub monitor: u32,
@@ -122,7 +110,7 @@ impl Notify<Self> for Brightness {
     /// Returns an error if the requested value could not be parsed
     #[instrument]
     async fn notify(update: MonitoredUpdate<Self>) -> Result<(), DaemonError> {
-        fn do_notification(new: &Brightness, device_id: &str) -> Result<(), DaemonError> {
+        async fn do_notification(new: &Brightness, device_id: &str) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -143,12 +131,13 @@ impl Notify<Self> for Brightness {
                     .as_str(),
                     format!("{}: ", if device_id == MONITOR_ID { "Monitor" } else { "Keyboard" }).as_str(),
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
 
-        fn do_notification_unavailable(device_id: &str) -> Result<(), DaemonError> {
+        async fn do_notification_unavailable(device_id: &str, reason: Option<&UnavailableReason>) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -159,8 +148,10 @@ impl Notify<Self> for Brightness {
                     "-t",
                     get_config().notification_timeout.to_string().as_str(),
                     format!("{}: ", if device_id == MONITOR_ID { "Monitor" } else { "Keyboard" }).as_str(),
+                    reason.map(ToString::to_string).unwrap_or_default().as_str(),
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
@@ -176,8 +167,10 @@ impl Notify<Self> for Brightness {
         for device_id in device_ids {
             // If the new values are valid
             match update.new {
-                Valid(ref new) => do_notification(new, device_id)?,
-                Unavailable | Recovering => do_notification_unavailable(device_id)?,
+                Valid(ref new) => do_notification(new, device_id).await?,
+                Unavailable(_) | Recovering | Failed { .. } => {
+                    do_notification_unavailable(device_id, update.new.reason()).await?;
+                }
             }
         }
 
@@ -185,34 +178,6 @@ impl Notify<Self> for Brightness {
     }
 }
 
-#[must_use]
-pub const fn match_get_commands(commands: &Option<BrightnessGetCommands>) -> DaemonMessage {
-    DaemonMessage::Get {
-        item: match commands {
-            Some(commands) => match commands {
-                BrightnessGetCommands::Monitor => DaemonItem::Brightness(BrightnessItem::Monitor),
-                BrightnessGetCommands::Keyboard => DaemonItem::Brightness(BrightnessItem::Keyboard),
-                BrightnessGetCommands::Icon => DaemonItem::Brightness(BrightnessItem::Icon),
-            },
-            None => DaemonItem::Brightness(BrightnessItem::All),
-        },
-    }
-}
-
-#[must_use]
-pub fn match_set_commands(commands: BrightnessSetCommands) -> DaemonMessage {
-    match commands {
-        BrightnessSetCommands::Monitor { value } => DaemonMessage::Set {
-            item: DaemonItem::Brightness(BrightnessItem::Monitor),
-            value,
-        },
-        BrightnessSetCommands::Keyboard { value } => DaemonMessage::Set {
-            item: DaemonItem::Brightness(BrightnessItem::Keyboard),
-            value,
-        },
-    }
-}
-
 /// # Errors
 /// Returns an error if the requested value could not be evaluated
 #[instrument]
@@ -231,37 +196,20 @@ pub async fn evaluate_item(
 
         DaemonReply::Value { item, value }
     } else {
+        let brightness = match current_snapshot().await.brightness {
+            Valid(brightness) => Valid(brightness),
+            Unavailable(_) | Recovering | Failed { .. } => Brightness::latest().await?,
+        };
+
         match brightness_item {
-            BrightnessItem::Monitor => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.brightness {
-                    Valid(brightness) => brightness.monitor.to_string(),
-                    Unavailable | Recovering => Brightness::latest().await?.map(|brightness| brightness.monitor).to_string(),
-                },
-            },
-            BrightnessItem::Keyboard => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.brightness {
-                    Valid(brightness) => brightness.keyboard.to_string(),
-                    Unavailable | Recovering => Brightness::latest().await?.map(|brightness| brightness.keyboard).to_string(),
-                },
-            },
-            BrightnessItem::Icon => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.brightness {
-                    Valid(brightness) => brightness.get_icon(MONITOR_ID),
-                    Unavailable | Recovering => Brightness::latest()
-                        .await?
-                        .map(|brightness| brightness.get_icon(MONITOR_ID))
-                        .to_string(),
-                },
-            },
+            BrightnessItem::Monitor => DaemonReply::from_observed(item, brightness.map(|brightness| brightness.monitor)),
+            BrightnessItem::Keyboard => DaemonReply::from_observed(item, brightness.map(|brightness| brightness.keyboard)),
+            BrightnessItem::Icon => {
+                DaemonReply::from_observed(item, brightness.map(|brightness| brightness.get_icon(MONITOR_ID)))
+            }
             BrightnessItem::All => DaemonReply::Tuples {
                 item,
-                tuples: match current_snapshot().await.brightness {
-                    Valid(brightness) => brightness.to_tuples(),
-                    Unavailable | Recovering => Brightness::latest().await?.to_tuples(),
-                },
+                tuples: with_timestamps::<Brightness>(brightness.to_tuples()).await,
             },
         }
     })
@@ -1,15 +1,25 @@
-use clap::{Parser, Subcommand};
-use tracing::{info, instrument};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use tracing::{info, instrument, warn};
 
 use crate::{
     battery::{self, BatteryGetCommands},
     bluetooth::{self, BluetoothGetCommands, BluetoothSetCommands},
     brightness::{self, BrightnessGetCommands, BrightnessSetCommands},
-    daemon::{DaemonItem, DaemonMessage, do_daemon, send_daemon_messaage},
-    error::DaemonError,
+    config::{Config, load_config, set_config},
+    daemon::{DaemonOptions, do_daemon, request_history_from, request_status_from, send_daemon_message_to},
+    doctor::run_doctor,
+    error::{DaemonError, ErrorCode},
     fan_profile::{self, FanProfileGetCommands, FanProfileSetCommands},
-    listener::listen,
+    history::{history_csv, parse_age},
+    listener::listen_to,
+    protocol::{DaemonItem, DaemonMessage, DaemonReply},
     ram::{self, RamGetCommands},
+    simulate::Scenario,
+    socket::socket_path,
+    systemd::SystemdEnv,
+    tuples::TupleName,
     volume::{self, VolumeGetCommands, VolumeSetCommands},
 };
 
@@ -18,6 +28,10 @@ use crate::{
 pub struct Cli {
     #[command(subcommand)]
     pub commands: CliCommands,
+
+    /// Path of the daemon's socket (Overrides the config)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -34,8 +48,36 @@ pub enum CliCommands {
     },
     #[command(alias = "lis", alias = "l")]
     Listen,
+    /// Show the daemon's uptime, and where each module's value comes from, and why it may be unavailable
+    #[command(alias = "stat")]
+    Status,
+    /// Show the recent changes of a module, e.g. `history battery --since 1h --format csv`
+    #[command(alias = "hist")]
+    History {
+        module: TupleName,
+        /// Only show changes within this long ago, such as 90s, 15m, 1h, or 2d
+        #[arg(long, value_parser = parse_age)]
+        since: Option<Duration>,
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Json)]
+        format: HistoryFormat,
+    },
+    /// Check that the commands, devices and services which each module needs are available, and that the config and socket can be used
+    Doctor,
     #[command(alias = "dae", alias = "d")]
-    Daemon,
+    Daemon {
+        /// Run with simulated hardware, driven by the timeline in this scenario file
+        #[arg(long, value_name = "SCENARIO")]
+        simulate: Option<PathBuf>,
+        /// Shut down a daemon which is already running, and take its place
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum HistoryFormat {
+    Json,
+    Csv,
 }
 
 #[derive(Subcommand)]
@@ -112,14 +154,32 @@ pub enum GetCommands {
     All,
 }
 
+/// # Documentation
+/// Run the command given by the CLI arguments, returning the exit code for the process.
+/// `systemd` is what systemd passed to the process, which is given to the daemon
+///
 /// # Errors
 /// Returns an error if the command for requested value cannot be spawned
 /// Returns an error if values in the output of the command cannot be parsed
 /// Returns an error if daemon or listener have received an error
 #[instrument]
-pub async fn evaluate_cli() -> Result<(), DaemonError> {
+pub async fn evaluate_cli(systemd: SystemdEnv) -> Result<ExitCode, DaemonError> {
     let cli = Cli::parse();
 
+    // Read the config once at startup, so a config file which can't be used stops the CLI instead of being used halfway
+    match load_config() {
+        Ok(config) => set_config(config),
+        // `doctor` explains what is wrong with the config file, so it still runs
+        Err(e) if matches!(cli.commands, CliCommands::Doctor) => {
+            warn!("Using default config: {e}");
+            set_config(Config::default());
+        }
+        Err(e) => return Err(e),
+    }
+
+    // The socket given on the command line is used over the one in the config
+    let socket_path = cli.socket.unwrap_or_else(socket_path);
+
     let message_to_send = match cli.commands {
         CliCommands::Get { commands } => {
             if let Some(commands) = commands {
@@ -143,23 +203,77 @@ pub async fn evaluate_cli() -> Result<(), DaemonError> {
             SetCommands::FanProfile { commands } => fan_profile::match_set_commands(commands),
         },
         CliCommands::Listen => {
-            listen().await?;
+            listen_to(&socket_path).await?;
+
+            return Ok(ExitCode::SUCCESS);
+        }
+        CliCommands::Status => {
+            match request_status_from(&socket_path).await? {
+                DaemonReply::Status(status) => print!("{status}"),
+                DaemonReply::Error { code, module, message } => return Ok(report_error(code, module.as_deref(), &message)),
+                reply => println!("{reply:?}"),
+            }
 
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
-        CliCommands::Daemon => {
-            do_daemon().await?;
+        CliCommands::History { module, since, format } => {
+            match request_history_from(&socket_path, module, since).await? {
+                DaemonReply::History { module, entries } => match format {
+                    HistoryFormat::Json => println!("{}", serde_json::to_string(&entries)?),
+                    HistoryFormat::Csv => print!("{}", history_csv(module, &entries)),
+                },
+                DaemonReply::Error { code, module, message } => return Ok(report_error(code, module.as_deref(), &message)),
+                reply => println!("{reply:?}"),
+            }
+
+            return Ok(ExitCode::SUCCESS);
+        }
+        CliCommands::Doctor => {
+            let report = run_doctor(&socket_path).await;
+            print!("{report}");
+
+            return Ok(if report.passed() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
+        }
+        CliCommands::Daemon { simulate, replace } => {
+            let scenario = simulate.map(Scenario::from_file).transpose()?;
+
+            do_daemon(DaemonOptions {
+                socket_path,
+                scenario,
+                replace,
+                systemd,
+                ..DaemonOptions::default()
+            })
+            .await?;
 
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
     };
 
     info!("Cli command: {message_to_send:?}");
 
-    let reply = send_daemon_messaage(message_to_send).await?;
+    let reply = send_daemon_message_to(&socket_path, message_to_send).await?;
+
+    // Show errors on stderr, exiting with the code for this type of error
+    if let DaemonReply::Error { code, module, message } = reply {
+        return Ok(report_error(code, module.as_deref(), &message));
+    }
+
     println!("{reply:?}");
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+/// # Documentation
+/// Show an error which the daemon replied with on stderr, returning the exit code for this type of error
+fn report_error(code: ErrorCode, module: Option<&str>, message: &str) -> ExitCode {
+    eprintln!("Error ({code}) in module '{}': {message}", module.unwrap_or("all"));
+
+    ExitCode::from(code.exit_code())
 }
 
 /// # Errors
@@ -1,19 +1,317 @@
-use crate::error::DaemonError;
+use std::{
+    process::Stdio,
+    sync::{Arc, LazyLock, PoisonError, RwLock},
+    time::Duration,
+};
 
+use tracing::instrument;
+
+use crate::{config::get_config, error::DaemonError, metrics::record_command_failure};
+
+/// # Documentation
+/// Runs the external commands which sources read from and write to
+#[async_trait::async_trait]
+pub trait CommandRunner: std::fmt::Debug + Send + Sync {
+    /// # Errors
+    /// Returns an error if the command cannot be run, or exits unsuccessfully
+    async fn run(&self, name: &str, args: &[&str]) -> Result<String, DaemonError>;
+
+    /// # Documentation
+    /// Where the commands are run, shown by `bar_daemon status`
+    fn kind(&self) -> &'static str {
+        "system"
+    }
+}
+
+// System Runner
+
+/// # Documentation
+/// Runs commands as child processes, waiting at most the `command_timeout` from the config
+#[derive(Debug, Default)]
+pub struct SystemRunner;
+
+#[async_trait::async_trait]
+impl CommandRunner for SystemRunner {
+    async fn run(&self, name: &str, args: &[&str]) -> Result<String, DaemonError> {
+        run_with_timeout(name, args, Duration::from_millis(get_config().command_timeout))
+            .await
+            .inspect_err(|e| record_command_failure(name, e))
+    }
+}
+
+// Default Runner
+
+static DEFAULT_RUNNER: LazyLock<RwLock<Arc<dyn CommandRunner>>> = LazyLock::new(|| RwLock::new(Arc::new(SystemRunner)));
+
+/// # Documentation
+/// The runner which is given to sources created using `default_source()`
+#[must_use]
+pub fn default_runner() -> Arc<dyn CommandRunner> {
+    DEFAULT_RUNNER.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// # Documentation
+/// Replace the runner which is given to sources created using `default_source()`
+pub fn set_default_runner(runner: Arc<dyn CommandRunner>) {
+    *DEFAULT_RUNNER.write().unwrap_or_else(PoisonError::into_inner) = runner;
+}
+
+/// # Documentation
+/// Run a command using the default runner
+///
+/// # Errors
+/// Returns an error if the command for requested value cannot be spawned
+/// Returns an error if the command times out, or exits unsuccessfully
+/// Returns an error if output cannot be converted to String
+pub async fn run<S: AsRef<str> + Send + Sync>(name: S, args: &[S]) -> Result<String, DaemonError> {
+    let args = args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+
+    default_runner().run(name.as_ref(), &args).await
+}
+
+/// # Documentation
+/// Run a command, killing it if it hasn't finished within `timeout`
+///
 /// # Errors
 /// Returns an error if the command for requested value cannot be spawned
-/// Returns an error if values in the output of the command cannot found
+/// Returns an error if the command times out, or exits unsuccessfully
 /// Returns an error if output cannot be converted to String
-pub fn run<S: AsRef<str>>(name: S, args: &[S]) -> Result<String, DaemonError> {
-    // Run the command, changing any errors into CommandError with the name and args given as parameters
-    let command_output = std::process::Command::new(name.as_ref())
-        .args(args.iter().map(AsRef::as_ref))
-        .output()
-        .map_err(|e| DaemonError::CommandError {
-            name: name.as_ref().to_string(),
-            args: args.iter().map(AsRef::as_ref).map(ToString::to_string).collect::<Vec<_>>(),
-            e: e.to_string(),
+#[instrument(skip(name, args), fields(name = name.as_ref()))]
+pub async fn run_with_timeout<S: AsRef<str> + Send + Sync>(
+    name: S,
+    args: &[S],
+    timeout: Duration,
+) -> Result<String, DaemonError> {
+    let name = name.as_ref().to_string();
+    let args = args.iter().map(AsRef::as_ref).map(ToString::to_string).collect::<Vec<_>>();
+
+    let command_error = |e: String, stderr: String| DaemonError::CommandError {
+        name: name.clone(),
+        args: args.clone(),
+        e,
+        stderr,
+    };
+
+    // Spawn the command, changing any errors into CommandError with the name and args given as parameters
+    let child = tokio::process::Command::new(&name)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => DaemonError::CommandNotFound { name: name.clone() },
+            _ => command_error(e.to_string(), String::new()),
         })?;
 
+    // Wait for the command to finish (The child is killed when dropped if it times out)
+    let command_output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| DaemonError::CommandTimeout {
+            name: name.clone(),
+            args: args.clone(),
+            timeout,
+        })?
+        .map_err(|e| command_error(e.to_string(), String::new()))?;
+
+    // Treat a non-zero exit status as a failure
+    if !command_output.status.success() {
+        return Err(command_error(
+            command_output.status.to_string(),
+            String::from_utf8_lossy(&command_output.stderr).trim().to_string(),
+        ));
+    }
+
     Ok(String::from_utf8(command_output.stdout)?.trim().to_string())
 }
+
+#[cfg(test)]
+pub mod fake {
+    use std::{
+        collections::{HashMap, VecDeque},
+        sync::{Mutex, PoisonError},
+        time::Duration,
+    };
+
+    use super::CommandRunner;
+    use crate::error::DaemonError;
+
+    /// # Documentation
+    /// What a `FakeRunner` does when a command is run
+    #[derive(Clone, Debug)]
+    pub enum FakeOutput {
+        Stdout(String),
+        Failure { status: String, stderr: String },
+        NotFound,
+        Timeout,
+    }
+
+    /// # Documentation
+    /// Replays canned outputs for command lines (e.g. `"wpctl get-volume @DEFAULT_SINK@"`) and records every invocation
+    ///
+    /// Outputs for a command line are replayed in order, the last one is repeated once the others are used up
+    #[derive(Debug, Default)]
+    pub struct FakeRunner {
+        outputs: Mutex<HashMap<String, VecDeque<FakeOutput>>>,
+        invocations: Mutex<Vec<String>>,
+    }
+
+    impl FakeRunner {
+        #[must_use]
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        #[must_use]
+        pub fn with_output(self, command_line: &str, stdout: &str) -> Self {
+            self.push(command_line, FakeOutput::Stdout(stdout.to_string()));
+            self
+        }
+
+        #[must_use]
+        pub fn with_failure(self, command_line: &str, stderr: &str) -> Self {
+            self.push(
+                command_line,
+                FakeOutput::Failure {
+                    status: String::from("exit status: 1"),
+                    stderr: stderr.to_string(),
+                },
+            );
+            self
+        }
+
+        pub fn push(&self, command_line: &str, output: FakeOutput) {
+            self.outputs
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(command_line.to_string())
+                .or_default()
+                .push_back(output);
+        }
+
+        /// # Documentation
+        /// Every command line which has been run, in order
+        #[must_use]
+        pub fn invocations(&self) -> Vec<String> {
+            self.invocations.lock().unwrap_or_else(PoisonError::into_inner).clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl CommandRunner for FakeRunner {
+        async fn run(&self, name: &str, args: &[&str]) -> Result<String, DaemonError> {
+            let command_line = std::iter::once(name)
+                .chain(args.iter().copied())
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            self.invocations
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(command_line.clone());
+
+            let output = {
+                let mut outputs = self.outputs.lock().unwrap_or_else(PoisonError::into_inner);
+
+                outputs.get_mut(&command_line).and_then(|queue| {
+                    if queue.len() > 1 {
+                        queue.pop_front()
+                    } else {
+                        queue.front().cloned()
+                    }
+                })
+            };
+
+            let args = args.iter().map(ToString::to_string).collect();
+
+            match output {
+                Some(FakeOutput::Stdout(stdout)) => Ok(stdout.trim().to_string()),
+                Some(FakeOutput::Failure { status, stderr }) => Err(DaemonError::CommandError {
+                    name: name.to_string(),
+                    args,
+                    e: status,
+                    stderr,
+                }),
+                Some(FakeOutput::Timeout) => Err(DaemonError::CommandTimeout {
+                    name: name.to_string(),
+                    args,
+                    timeout: Duration::ZERO,
+                }),
+                Some(FakeOutput::NotFound) | None => Err(DaemonError::CommandNotFound { name: name.to_string() }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        CommandRunner,
+        fake::{FakeOutput, FakeRunner},
+        run_with_timeout,
+    };
+    use crate::error::DaemonError;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn output_is_trimmed() {
+        let output = run_with_timeout("echo", &["  Volume: 0.50  "], TIMEOUT).await;
+
+        assert_eq!(output.ok().as_deref(), Some("Volume: 0.50"));
+    }
+
+    #[tokio::test]
+    async fn failing_command_captures_stderr() {
+        let output = run_with_timeout("sh", &["-c", "echo 'Device not found' >&2; exit 3"], TIMEOUT).await;
+
+        assert!(
+            matches!(&output, Err(DaemonError::CommandError { e, stderr, .. }) if e.contains('3') && stderr == "Device not found"),
+            "{output:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_command_is_not_found() {
+        let output = run_with_timeout("bar_daemon_command_which_does_not_exist", &[], TIMEOUT).await;
+
+        assert!(matches!(output, Err(DaemonError::CommandNotFound { .. })), "{output:?}");
+    }
+
+    #[tokio::test]
+    async fn hung_command_times_out() {
+        let start = std::time::Instant::now();
+        let output = run_with_timeout("sleep", &["10"], Duration::from_millis(100)).await;
+
+        assert!(matches!(output, Err(DaemonError::CommandTimeout { .. })), "{output:?}");
+        assert!(start.elapsed() < Duration::from_secs(5), "Timeout took {:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn fake_runner_replays_outputs_in_order() {
+        let runner = FakeRunner::new()
+            .with_output("bluetooth", "bluetooth = on")
+            .with_output("bluetooth", "bluetooth = off");
+        runner.push("bluetooth off", FakeOutput::Timeout);
+
+        assert_eq!(runner.run("bluetooth", &[]).await.ok().as_deref(), Some("bluetooth = on"));
+        assert_eq!(runner.run("bluetooth", &[]).await.ok().as_deref(), Some("bluetooth = off"));
+        assert_eq!(runner.run("bluetooth", &[]).await.ok().as_deref(), Some("bluetooth = off"));
+        assert!(matches!(
+            runner.run("bluetooth", &["off"]).await,
+            Err(DaemonError::CommandTimeout { .. })
+        ));
+        assert!(matches!(
+            runner.run("bluetooth", &["on"]).await,
+            Err(DaemonError::CommandNotFound { .. })
+        ));
+
+        assert_eq!(
+            runner.invocations(),
+            ["bluetooth", "bluetooth", "bluetooth", "bluetooth off", "bluetooth on"]
+        );
+    }
+}
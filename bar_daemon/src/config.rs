@@ -1,21 +1,42 @@
-use std::{fs, path::Path, sync::LazyLock};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
 
 use serde::Deserialize;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
-use crate::error::DaemonError;
+use crate::{backoff::BackoffPolicy, error::DaemonError};
 
 const CONFIG_PATH: &str = ".config/bar_daemon/config.toml";
 const DEFAULT_CONFIG_PATH: &str = "/etc/bar_daemon/config.toml";
 
 /// # Documentation
-/// The `Config` derived from the `config.toml` file
-#[derive(Deserialize, Clone)]
+/// The `Config` derived from the `config.toml` file (Missing fields use their default values)
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct Config {
     /// Timeout of notifications in milliseconds
     pub notification_timeout: u32,
     /// Polling rate for polled values in milliseconds
     pub polling_rate: u64,
+    /// Time which commands are given to finish before they are killed, in milliseconds
+    pub command_timeout: u64,
+    /// Time after which a polled value which hasn't been read again is marked as stale, in milliseconds
+    pub stale_after: u64,
+    /// Backoff used when retrying values which could not be read
+    pub backoff: BackoffPolicy,
+    /// Path of the socket which the daemon listens on (Defaults to `$XDG_RUNTIME_DIR/bar_daemon/bar_daemon.sock`)
+    pub socket_path: Option<PathBuf>,
+    /// HTTP server for web-based clients (Only used when built with the `http` feature, disabled when not given)
+    pub http: Option<HttpConfig>,
+    /// How changes are queued for listeners
+    pub listeners: ListenerConfig,
+    /// Prometheus metrics of the values, and of the daemon's health
+    pub metrics: MetricsConfig,
+    /// How much of each module's history is kept, and whether it is kept across restarts
+    pub history: HistoryConfig,
 }
 
 impl Default for Config {
@@ -23,20 +44,141 @@ impl Default for Config {
         Self {
             notification_timeout: 1000,
             polling_rate: 2000,
+            command_timeout: 5000,
+            stale_after: 15000,
+            backoff: BackoffPolicy::default(),
+            socket_path: None,
+            http: None,
+            listeners: ListenerConfig::default(),
+            metrics: MetricsConfig::default(),
+            history: HistoryConfig::default(),
         }
     }
 }
 
-static CONFIG: LazyLock<Config> = LazyLock::new(init_config);
+/// # Documentation
+/// Where the HTTP server listens, and the token which clients must give
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Address to listen on, either `host:port` or `unix:<path>` for a Unix socket
+    pub address: String,
+    /// Token which clients must give as `Authorization: Bearer <token>` or `?token=<token>`.
+    /// Required for a TCP address, and optional for a Unix socket, which only the same user can connect to
+    pub token: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            address: String::from("127.0.0.1:7878"),
+            token: None,
+        }
+    }
+}
+
+/// # Documentation
+/// Where Prometheus metrics are written, for `node_exporter`'s textfile collector
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// File which the metrics are written to, e.g. `/var/lib/node_exporter/textfile_collector/bar_daemon.prom` (Not written when not given)
+    pub textfile: Option<PathBuf>,
+    /// Time between writes of the textfile in milliseconds
+    pub interval: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            textfile: None,
+            interval: 15000,
+        }
+    }
+}
+
+/// # Documentation
+/// How much of each module's history is kept in memory, entries which are too old or too many are removed
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Time which changes are kept for, in milliseconds
+    pub max_age: u64,
+    /// Number of changes which are kept for each module
+    pub max_entries: usize,
+    /// Whether the changes are written to a file, and restored from it when the daemon starts
+    pub persist: bool,
+    /// File which the changes are written to (Defaults to `$XDG_STATE_HOME/bar_daemon/history`)
+    pub path: Option<PathBuf>,
+    /// Size in bytes which the file can grow to before it is compacted, with the previous file kept as `<path>.1`
+    pub max_file_size: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_age: 86_400_000,
+            max_entries: 10_000,
+            persist: false,
+            path: None,
+            max_file_size: 4_194_304,
+        }
+    }
+}
+
+/// # Documentation
+/// How many changes can be waiting to be written to each listener, and what happens when a listener falls behind
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ListenerConfig {
+    /// Number of frames which can be waiting to be written to a listener
+    pub queue_size: usize,
+    /// What is done when a listener's queue is full
+    pub slow_listener: SlowListenerPolicy,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: 32,
+            slow_listener: SlowListenerPolicy::default(),
+        }
+    }
+}
+
+/// # Documentation
+/// What is done with a listener which isn't reading its frames quickly enough to keep up with changes
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowListenerPolicy {
+    /// Replace the waiting frames with the latest state of every module
+    #[default]
+    Latest,
+    /// Drop the oldest waiting frame
+    DropOldest,
+    /// Disconnect the listener
+    Disconnect,
+}
+
+/// The config which is used, given with `set_config()` (The CLI loads it once at startup with `load_config()`)
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
 
 // TODO Paths in config are relative to $HOME but I could make it possible to be absolute or relative
+/// # Documentation
+/// Read the user's config file, copying the default config to it if it doesn't exist yet
+///
+/// # Errors
+/// Returns an error if the config file exists but can't be read, or isn't a valid config
 #[instrument]
-fn init_config() -> Config {
-    // Get the HOME directory for the user
-    let home = get_home_dir();
+pub fn load_config() -> Result<Config, DaemonError> {
+    load_config_from(&config_path())
+}
 
-    // Combine $HOME and CONFIG_PATH to create a full path to the config file
-    get_config_from_file(format!("{home}/{CONFIG_PATH}"))
+/// # Documentation
+/// Path of the user's config file (`$HOME/.config/bar_daemon/config.toml`)
+#[must_use]
+pub fn config_path() -> PathBuf {
+    PathBuf::from(get_home_dir()).join(CONFIG_PATH)
 }
 
 fn get_home_dir() -> String {
@@ -56,33 +198,128 @@ fn get_home_dir() -> String {
         .to_string()
 }
 
-fn get_config_from_file<S: AsRef<str>>(file_path: S) -> Config {
-    let config_path = Path::new(file_path.as_ref());
+fn load_config_from(config_path: &Path) -> Result<Config, DaemonError> {
+    match read_config_file(config_path) {
+        Ok(config) => Ok(config),
+        // Neither the config file nor the /etc/ config exist (e.g. when running from a checkout), so there is nothing to use
+        Err(e) if !config_path.exists() => {
+            warn!("Using default config, config file could not be created: {e}");
 
+            Ok(Config::default())
+        }
+        // A config file which exists but can't be used is a mistake which shouldn't be hidden behind the default config
+        Err(e) => Err(e),
+    }
+}
+
+/// # Errors
+/// Returns an error if the config file doesn't exist and can't be copied from /etc/
+/// Returns an error if the config file can't be read
+/// Returns an error if the config file isn't valid TOML
+fn read_config_file(config_path: &Path) -> Result<Config, DaemonError> {
     // If the config_path doesn't point to any file, copy it from /etc/
     if !config_path.exists() {
         // Create the config_path parent folders
-        fs::create_dir_all(config_path.parent().unwrap_or_else(|| {
-            panic!(
-                "{}",
-                DaemonError::PathCreateError(String::from("Could get parent of `config_path`"))
-            )
-        }))
-        .unwrap_or_else(|e| panic!("{}", DaemonError::PathCreateError(e.to_string())));
+        fs::create_dir_all(
+            config_path
+                .parent()
+                .ok_or_else(|| DaemonError::PathCreateError(String::from("Could get parent of `config_path`")))?,
+        )
+        .map_err(|e| DaemonError::PathCreateError(e.to_string()))?;
 
         // Copy the default config from /etc/
-        fs::copy(DEFAULT_CONFIG_PATH, config_path).unwrap_or_else(|e| panic!("{}", DaemonError::PathRwError(e.to_string())));
+        fs::copy(DEFAULT_CONFIG_PATH, config_path)
+            .map_err(|e| DaemonError::PathRwError(format!("{DEFAULT_CONFIG_PATH}: {e}")))?;
     }
 
-    // Read the config file as a String (Converting Error to DaemonError::PathRwError)
-    let config = fs::read_to_string(config_path)
-        .map_err(|e| DaemonError::PathRwError(e.to_string()))
-        .unwrap_or_else(|e| panic!("{e}"));
+    // Read the config file, converting its text to a Config struct using TOML
+    parse_config_file(config_path)
+}
+
+/// # Documentation
+/// Parse the config file at `config_path` without copying the default config to it (Used by `bar_daemon doctor`)
+///
+/// # Errors
+/// Returns an error if the config file can't be read
+/// Returns an error if the config file isn't valid TOML
+pub fn parse_config_file(config_path: &Path) -> Result<Config, DaemonError> {
+    let config =
+        fs::read_to_string(config_path).map_err(|e| DaemonError::PathRwError(format!("{}: {e}", config_path.display())))?;
 
-    // Convert the text in the config file to a Config struct using TOML
-    toml::from_str(config.as_str()).unwrap_or_else(|e| panic!("{e}"))
+    Ok(toml::from_str(config.as_str())?)
 }
 
+impl Config {
+    /// # Documentation
+    /// Values in the config which parse, but which the daemon can't work properly with
+    #[must_use]
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.polling_rate == 0 {
+            problems.push(String::from(
+                "`polling_rate` is 0, so polled values would be read continuously",
+            ));
+        }
+        if self.command_timeout == 0 {
+            problems.push(String::from("`command_timeout` is 0, so every command would time out"));
+        }
+        if self.stale_after <= self.polling_rate {
+            problems.push(format!(
+                "`stale_after` ({}ms) is not longer than `polling_rate` ({}ms), so polled values are marked stale between polls",
+                self.stale_after, self.polling_rate
+            ));
+        }
+        if self.listeners.queue_size == 0 {
+            problems.push(String::from(
+                "`listeners.queue_size` is 0, so no change could be queued for listeners",
+            ));
+        }
+        if self.metrics.textfile.is_some() && self.metrics.interval == 0 {
+            problems.push(String::from(
+                "`metrics.interval` is 0, so the metrics textfile would be written continuously",
+            ));
+        }
+
+        problems
+    }
+}
+
+/// # Documentation
+/// The config given with `set_config()`, or the config file when nothing was given (e.g. when used by a client library)
 pub fn get_config() -> Config {
-    CONFIG.clone()
+    CONFIG
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(|| {
+            load_config().unwrap_or_else(|e| {
+                warn!("Using default config: {e}");
+
+                Config::default()
+            })
+        })
+        .clone()
+}
+
+/// # Documentation
+/// Use `config` instead of the config file (The daemon uses the config in its `DaemonOptions`)
+pub fn set_config(config: Config) {
+    *CONFIG.lock().unwrap_or_else(PoisonError::into_inner) = Some(config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load_config_from;
+    use crate::error::DaemonError;
+
+    #[test]
+    fn malformed_config_is_not_replaced_by_default() {
+        let path = std::env::temp_dir().join(format!("bar_daemon_malformed_config_{}.toml", std::process::id()));
+        std::fs::write(&path, "polling_rate = \"fast\"").unwrap_or_else(|e| panic!("{e}"));
+
+        let config = load_config_from(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert!(matches!(config, Err(DaemonError::TomlError(_))), "{config:?}");
+    }
 }
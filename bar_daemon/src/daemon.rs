@@ -1,73 +1,112 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use serde::{Deserialize, Serialize};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{UnixListener, UnixStream},
+    io::BufReader,
+    net::UnixStream,
     sync::{Mutex, Notify},
 };
-use tracing::{error, info, instrument, trace};
-use uuid::Uuid;
+use tracing::{error, info, instrument, trace, warn};
+
+#[cfg(feature = "http")]
+use crate::http::{bind_http, spawn_http_server};
 
 use crate::{
-    battery::{self, Battery, BatteryItem},
-    bluetooth::{self, BluetoothItem},
-    brightness::{self, BrightnessItem},
+    battery::{self, Battery},
+    bluetooth, brightness,
+    config::{Config, get_config, set_config},
+    dbus_service::spawn_dbus_service,
     error::DaemonError,
-    fan_profile::{self, FanProfile, FanProfileItem},
-    listener::{Client, SharedClients, handle_clients},
+    fan_profile::{self, FanProfile},
+    history::{history_path, module_history, spawn_history_writer},
+    instance::{InstanceLock, pidfile_path, take_over_socket},
+    listener::{Client, ClientFormat, SharedClients, handle_clients, snapshot_json, states_frame},
+    metrics::spawn_textfile_writer,
+    module_value::{ModuleState, module_state},
     polled::spawn_poller,
-    ram::{self, Ram, RamItem},
+    protocol::{DaemonItem, DaemonMessage, DaemonReply, read_message, read_reply, write_message, write_reply},
+    ram::{self, Ram},
     shutdown::shutdown_signal,
-    snapshot::subscribe_snapshot,
-    tuples::get_all_tuples,
-    volume::{self, VolumeItem},
+    simulate::{Scenario, spawn_scenario},
+    snapshot::{current_snapshot, subscribe_snapshot},
+    socket::{bind_socket, create_socket_dir, is_same_user, socket_path},
+    status::{daemon_status, mark_started},
+    systemd::{SystemdEnv, activated_listener, spawn_notifier},
+    tuples::{TUPLE_NAMES, TupleName, get_all_tuples},
+    volume,
 };
 
-pub const SOCKET_PATH: &str = "/tmp/bar_daemon.sock";
-pub const BUFFER_SIZE: usize = 1024;
+/// # Documentation
+/// Where the daemon listens, and what it reads values from
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    /// Path of the Unix socket which clients connect to
+    pub socket_path: PathBuf,
+    /// Simulated hardware to use instead of running commands
+    pub scenario: Option<Scenario>,
+    /// Ask a daemon which is already using the socket to shut down, instead of refusing to start
+    pub replace: bool,
+    /// Config which the daemon uses, this also decides whether the HTTP server, metrics textfile, and history file are used
+    pub config: Config,
+    /// Export the values on the session bus as `org.bar_daemon.Daemon1`
+    pub dbus: bool,
+    /// What systemd passed to the daemon (A socket, and where to send its state), taken from the environment in `main`
+    pub systemd: SystemdEnv,
+}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum DaemonMessage {
-    Set { item: DaemonItem, value: String },
-    Get { item: DaemonItem },
-    Listen,
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            socket_path: socket_path(),
+            scenario: None,
+            replace: false,
+            config: get_config(),
+            dbus: true,
+            systemd: SystemdEnv::default(),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum DaemonReply {
-    Value {
-        item: DaemonItem,
-        value: String,
-    },
-    Tuples {
-        item: DaemonItem,
-        tuples: Vec<(String, String)>,
-    },
-    AllTuples {
-        tuples: Vec<(String, Vec<(String, String)>)>,
-    },
-    Error(String),
+impl DaemonOptions {
+    /// # Documentation
+    /// Options for a daemon which only uses `socket_path`, with the default config and without the D-Bus service.
+    /// Nothing belonging to the user's daemon is used (e.g. their config file, bus name, HTTP port, or history file), so this is used by tests
+    #[must_use]
+    pub fn isolated(socket_path: PathBuf) -> Self {
+        Self {
+            socket_path,
+            scenario: None,
+            replace: false,
+            config: Config::default(),
+            dbus: false,
+            systemd: SystemdEnv::default(),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum DaemonItem {
-    Volume(VolumeItem),
-    Brightness(BrightnessItem),
-    Bluetooth(BluetoothItem),
-    Battery(BatteryItem),
-    Ram(RamItem),
-    FanProfile(FanProfileItem),
-    All,
+/// # Errors
+/// Returns an error if another daemon is using the socket path
+/// Returns an error if ``UnixListener`` cannot be bound
+/// Returns an error if socket cannot be accepted
+#[instrument(skip(options))]
+pub async fn do_daemon(options: DaemonOptions) -> Result<(), DaemonError> {
+    run_daemon(options, shutdown_signal()).await
 }
 
+/// # Documentation
+/// Run the daemon until `shutdown` completes
+///
 /// # Errors
-/// Returns an error if ``SOCKET_PATH`` cannot be found
+/// Returns an error if another daemon is using the socket path
 /// Returns an error if ``UnixListener`` cannot be bound
 /// Returns an error if socket cannot be accepted
-#[instrument]
-pub async fn do_daemon() -> Result<(), DaemonError> {
-    match do_daemon_inner().await {
+#[instrument(skip(options, shutdown))]
+pub async fn run_daemon<F: Future<Output = ()> + Send>(options: DaemonOptions, shutdown: F) -> Result<(), DaemonError> {
+    match do_daemon_inner(options, shutdown).await {
         Ok(()) => Ok(()),
         Err(e) => {
             error!("{e}");
@@ -76,21 +115,50 @@ pub async fn do_daemon() -> Result<(), DaemonError> {
     }
 }
 
-async fn do_daemon_inner() -> Result<(), DaemonError> {
-    // Remove existing socket file
-    if Path::new(SOCKET_PATH).exists() {
-        std::fs::remove_file(SOCKET_PATH)?;
-    }
+async fn do_daemon_inner<F: Future<Output = ()> + Send>(options: DaemonOptions, shutdown: F) -> Result<(), DaemonError> {
+    let DaemonOptions {
+        socket_path,
+        scenario,
+        replace,
+        config,
+        dbus,
+        systemd,
+    } = options;
+
+    // Everything the daemon runs uses this config, instead of reading the config file
+    set_config(config);
+
+    // Use the socket passed by systemd if the daemon was socket activated
+    let activated_listener = activated_listener(&systemd)?;
+
+    // Make sure this is the only daemon using the socket (The lock is held until the daemon has shutdown)
+    let _instance_lock = if activated_listener.is_some() {
+        // systemd accepts connections on the socket, so there is no running daemon to probe for
+        create_socket_dir(&socket_path)?;
+        InstanceLock::try_acquire(&pidfile_path(&socket_path))?
+            .ok_or_else(|| DaemonError::AlreadyRunning(socket_path.display().to_string()))?
+    } else {
+        take_over_socket(&socket_path, replace).await?
+    };
 
-    // Create a future which waits for shutdown request
-    let shutdown = shutdown_signal();
+    // Remember when the daemon started, for its uptime
+    mark_started();
+
+    // Pin the future which waits for shutdown request
     tokio::pin!(shutdown);
 
     // Create Notify for broadcasting shutdown to all tasks
     let shutdown_notify = Arc::new(Notify::new());
 
-    // Create new UnixListener at SOCKET_PATH
-    let listener = UnixListener::bind(SOCKET_PATH)?;
+    // Create Notify for clients to request a shutdown
+    let shutdown_request = Arc::new(Notify::new());
+
+    // Otherwise create new UnixListener at the socket path (Replacing a stale socket)
+    let socket_activated = activated_listener.is_some();
+    let listener = match activated_listener {
+        Some(listener) => listener,
+        None => bind_socket(&socket_path)?,
+    };
 
     // Create a receiver for SnapshotEvents
     let mut snapshot_rx = subscribe_snapshot();
@@ -98,16 +166,50 @@ async fn do_daemon_inner() -> Result<(), DaemonError> {
     // Remember listener clients to broadcast to
     let clients: SharedClients = Arc::new(Mutex::new(HashMap::new()));
 
+    // Restore the history which was written before the daemon last stopped, before any value changes
+    let history_config = get_config().history;
+    if history_config.persist {
+        spawn_history_writer(history_path(), history_config.max_file_size, shutdown_notify.clone());
+    }
+
     // Spawn a task which handles listener clients
     let clients_clone = clients.clone();
     let shutdown_notify_clone = shutdown_notify.clone();
     tokio::spawn(async move { handle_clients(clients_clone, &mut snapshot_rx, shutdown_notify_clone).await });
 
+    // Replace the hardware with the scenario's simulated hardware
+    if let Some(scenario) = scenario {
+        spawn_scenario(scenario, shutdown_notify.clone());
+    }
+
     // Spawn poller tasks
     spawn_poller::<Battery>(shutdown_notify.clone());
     spawn_poller::<FanProfile>(shutdown_notify.clone());
     spawn_poller::<Ram>(shutdown_notify.clone());
 
+    // Read every value once, so systemd is only told the daemon is ready when values can be given to clients
+    if let Err(e) = get_all_tuples().await {
+        warn!("Initial read of values failed: {e}");
+    }
+
+    // Export the values on the session bus, and as metrics
+    spawn_services(dbus, &clients, &shutdown_notify);
+
+    // Serve the values over HTTP for web-based clients
+    #[cfg(feature = "http")]
+    if let Some(http_config) = get_config().http {
+        let http_listener = bind_http(&http_config).await?;
+        spawn_http_server(http_listener, http_config.token, clients.clone(), shutdown_notify.clone());
+    }
+
+    #[cfg(not(feature = "http"))]
+    if get_config().http.is_some() {
+        warn!("The [http] config is ignored, since bar_daemon was built without the `http` feature");
+    }
+
+    systemd.notify("READY=1");
+    spawn_notifier(&systemd, shutdown_notify.clone());
+
     // Handle sockets
     loop {
         tokio::select! {
@@ -118,20 +220,38 @@ async fn do_daemon_inner() -> Result<(), DaemonError> {
 
                 break;
             },
+            () = shutdown_request.notified() => {
+                info!("Shutdown requested by client, stopping connection accept loop");
+
+                shutdown_notify.notify_waiters();
+
+                break;
+            },
             accept_result = listener.accept() => {
                 let (stream, _) = accept_result?;
 
+                // Only the user running the daemon may use it
+                if !is_same_user(&stream) {
+                    warn!("Refused connection from a client belonging to another user");
+                    continue;
+                }
+
                 // Spawn a task which handles this socket
                 let clients_clone = clients.clone();
                 let shutdown_notify_clone = shutdown_notify.clone();
-                tokio::spawn(async move { handle_socket(stream, clients_clone, shutdown_notify_clone).await });
+                let shutdown_request_clone = shutdown_request.clone();
+                tokio::spawn(async move {
+                    handle_socket(stream, clients_clone, shutdown_notify_clone, shutdown_request_clone).await
+                });
             }
         }
     }
 
-    // Remove socket file after shutdown
-    if Path::new(SOCKET_PATH).exists() {
-        std::fs::remove_file(SOCKET_PATH)?;
+    systemd.notify("STOPPING=1");
+
+    // Remove socket file after shutdown (systemd owns the socket if it was passed to the daemon)
+    if !socket_activated && socket_path.exists() {
+        std::fs::remove_file(&socket_path)?;
     }
 
     info!("Daemon shutdown cleanly");
@@ -139,44 +259,104 @@ async fn do_daemon_inner() -> Result<(), DaemonError> {
     Ok(())
 }
 
+/// # Documentation
+/// Spawn the D-Bus service (If `dbus` is set), and the metrics textfile writer (If it is in the config)
+fn spawn_services(dbus: bool, clients: &SharedClients, shutdown_notify: &Arc<Notify>) {
+    // Export the values on the session bus
+    if dbus {
+        spawn_dbus_service(shutdown_notify.clone());
+    }
+
+    // Write metrics for node_exporter's textfile collector
+    let metrics_config = get_config().metrics;
+    if let Some(textfile) = metrics_config.textfile {
+        spawn_textfile_writer(
+            textfile,
+            Duration::from_millis(metrics_config.interval),
+            clients.clone(),
+            shutdown_notify.clone(),
+        );
+    }
+}
+
+/// # Documentation
+/// Reply to each message sent on this socket, requests which fail are replied to with `DaemonReply::Error`
+///
 /// # Errors
 /// Returns an error if socket cannot be read
-/// Returns an error if ``DaemonMessage`` could not be created from bytes
-/// Returns an error if requested value cannot be found or parsed
 /// Returns an error if socket could not be wrote to
-#[instrument(skip(stream, clients, shutdown_notify))]
+#[instrument(skip(stream, clients, shutdown_notify, shutdown_request))]
 pub async fn handle_socket(
-    mut stream: UnixStream,
+    stream: UnixStream,
     clients: SharedClients,
     // clients_tx: mpsc::UnboundedSender<ClientMessage>,
     shutdown_notify: Arc<Notify>,
+    shutdown_request: Arc<Notify>,
 ) -> Result<(), DaemonError> {
-    let mut buf = [0; BUFFER_SIZE];
+    // Requests are framed, so one may arrive in several reads, or several in one read
+    let mut reader = BufReader::new(stream);
     loop {
         tokio::select! {
-            read_result = stream.read(&mut buf) => {
-                let n = match read_result? {
+            message = read_message(&mut reader) => {
+                let reply = match message {
                     // Stream closed
-                    0 => break,
-                    n => n,
-                };
+                    Ok(None) => break,
+                    Ok(Some(DaemonMessage::Set { item, value })) => match_set_command(item.clone(), value)
+                        .await
+                        .unwrap_or_else(|e| error_reply(item.module(), &e)),
+                    Ok(Some(DaemonMessage::Get { item })) => match_get_command(item.clone())
+                        .await
+                        .unwrap_or_else(|e| error_reply(item.module(), &e)),
+                    Ok(Some(DaemonMessage::GetState { module })) => module_state(module)
+                        .await
+                        .map_or_else(|e| error_reply(Some(TUPLE_NAMES[module as usize]), &e), DaemonReply::State),
+                    Ok(Some(DaemonMessage::Listen)) => {
+                        // Hold the lock while the current state is queued, so no change is queued before it
+                        let mut clients = clients.lock().await;
+
+                        let json = snapshot_json(current_snapshot().await)? + "\n";
+                        let client = Client::spawn(reader.into_inner(), ClientFormat::Json, json.into_bytes().into());
+                        clients.insert(client.id, client);
+                        drop(clients);
 
-                let message: DaemonMessage = postcard::from_bytes(&buf[..n])?;
+                        return Ok(());
+                    }
+                    Ok(Some(DaemonMessage::Subscribe)) => {
+                        // Hold the lock while the current states are queued, so no change is queued before them
+                        let mut clients = clients.lock().await;
 
-                let reply = match message {
-                    DaemonMessage::Set { item, value }=>match_set_command(item.clone(), value.clone()).await?,
-                    DaemonMessage::Get { item } => match_get_command(item.clone()).await?,
-                    DaemonMessage::Listen => {
-                        // Add the client writer and their uuid to clients
-                        let client_id = Uuid::new_v4();
-                        clients.lock().await.insert(client_id, Client { id: client_id, stream });
+                        let states = states_frame(&ModuleState::all_from_snapshot(current_snapshot().await))?;
+                        let client = Client::spawn(reader.into_inner(), ClientFormat::Frames, states);
+                        clients.insert(client.id, client);
+                        drop(clients);
+
+                        return Ok(());
+                    }
+                    Ok(Some(DaemonMessage::Status)) => DaemonReply::Status(daemon_status(&clients).await),
+                    Ok(Some(DaemonMessage::History { module, since })) => DaemonReply::History {
+                        module,
+                        entries: module_history(module, since),
+                    },
+                    Ok(Some(DaemonMessage::Shutdown)) => {
+                        info!("Client asked the daemon to shut down");
+
+                        // Reply before shutting down, so the client knows the request was accepted
+                        write_reply(reader.get_mut(), &DaemonReply::ShuttingDown).await?;
+                        shutdown_request.notify_one();
 
                         return Ok(());
                     }
+                    // The rest of the request can't be told apart from the next one, so the connection is closed
+                    Err(e @ DaemonError::MessageTooLarge(_)) => {
+                        write_reply(reader.get_mut(), &error_reply(None, &e)).await?;
+                        break;
+                    }
+                    Err(e @ DaemonError::PostcardError(_)) => error_reply(None, &e),
+                    Err(e) => return Err(e),
                 };
 
                 // Send the reply back
-                stream.write_all(&postcard::to_stdvec(&reply)?).await?;
+                write_reply(reader.get_mut(), &reply).await?;
             },
             () = shutdown_notify.notified() => {
                 info!("Socket handler received shutdown notification");
@@ -190,41 +370,91 @@ pub async fn handle_socket(
     Ok(())
 }
 
+/// # Documentation
+/// Log a request which failed, and create the reply which tells the client why
+fn error_reply(module: Option<&str>, e: &DaemonError) -> DaemonReply {
+    warn!("Request for module '{}' failed: {e}", module.unwrap_or("all"));
+
+    DaemonReply::from_error(module, e)
+}
+
 /// # Errors
-/// Returns an error if ``SOCKET_PATH`` cannot be found
+/// Returns an error if the socket path cannot be found
 /// Returns an error if socket cannot be read
 /// Returns an error if socket could not be wrote to
-#[instrument]
+/// Returns an error if the daemon closed the connection without replying
 pub async fn send_daemon_messaage(message: DaemonMessage) -> Result<DaemonReply, DaemonError> {
+    send_daemon_message_to(&socket_path(), message).await
+}
+
+/// # Documentation
+/// Send a message to the daemon listening at `socket_path`, and wait for its reply
+///
+/// # Errors
+/// Returns an error if `socket_path` cannot be found
+/// Returns an error if socket cannot be read
+/// Returns an error if socket could not be wrote to
+/// Returns an error if the daemon closed the connection without replying
+#[instrument]
+pub async fn send_daemon_message_to(socket_path: &Path, message: DaemonMessage) -> Result<DaemonReply, DaemonError> {
     // Connect to the daemon
-    let mut stream = UnixStream::connect(SOCKET_PATH).await?;
+    let mut stream = UnixStream::connect(socket_path).await?;
 
     // Write the serialized message to the daemon
-    stream.write_all(&postcard::to_stdvec(&message)?).await?;
+    write_message(&mut stream, &message).await?;
 
     trace!("Message sent to daemon: {message:?}");
 
     // Get the response from the daemon
-    let mut buf = vec![0u8; BUFFER_SIZE];
-    let n = stream.read(&mut buf).await?;
-
-    let reply = postcard::from_bytes(&buf[..n])?;
+    let reply = read_reply(&mut stream).await?;
 
     trace!("Response from daemon: {reply:?}");
 
-    // Serialize reply into DaemonMessage
     Ok(reply)
 }
 
+/// # Documentation
+/// Get the changes of `module` within the last `since` from the daemon listening at `socket_path`
+///
+/// # Errors
+/// Returns an error if `socket_path` cannot be found
+/// Returns an error if socket cannot be read
+/// Returns an error if socket could not be wrote to
+/// Returns an error if the daemon closed the connection without replying
+#[instrument]
+pub async fn request_history_from(
+    socket_path: &Path,
+    module: TupleName,
+    since: Option<Duration>,
+) -> Result<DaemonReply, DaemonError> {
+    send_daemon_message_to(socket_path, DaemonMessage::History { module, since }).await
+}
+
+/// # Documentation
+/// Get the status of the daemon listening at `socket_path`
+///
+/// # Errors
+/// Returns an error if `socket_path` cannot be found
+/// Returns an error if socket cannot be read
+/// Returns an error if socket could not be wrote to
+/// Returns an error if the daemon closed the connection without replying
+#[instrument]
+pub async fn request_status_from(socket_path: &Path) -> Result<DaemonReply, DaemonError> {
+    send_daemon_message_to(socket_path, DaemonMessage::Status).await
+}
+
 /// # Errors
 /// Returns an error if the requested value could not be parsed
+/// Returns an error if the requested item can't be set
 pub async fn match_set_command(item: DaemonItem, value: String) -> Result<DaemonReply, DaemonError> {
     let message = match item.clone() {
         DaemonItem::Volume(volume_item) => volume::evaluate_item(item, &volume_item, Some(value)).await?,
         DaemonItem::Brightness(brightness_item) => brightness::evaluate_item(item, &brightness_item, Some(value)).await?,
         DaemonItem::Bluetooth(bluetooth_item) => bluetooth::evaluate_item(item, &bluetooth_item, Some(value)).await?,
         DaemonItem::FanProfile(fan_profile_item) => fan_profile::evaluate_item(item, &fan_profile_item, Some(value)).await?,
-        _ => DaemonReply::Value { item, value },
+        DaemonItem::Battery(_) | DaemonItem::Ram(_) | DaemonItem::All => {
+            return Err(DaemonError::ReadOnlyItem(format!("{item:?}")));
+        }
     };
 
     Ok(message)
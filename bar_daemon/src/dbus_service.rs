@@ -0,0 +1,539 @@
+use std::sync::Arc;
+
+use tokio::sync::{Notify, broadcast::error::RecvError};
+use tracing::{info, instrument, warn};
+use zbus::{
+    Connection, connection,
+    object_server::{Interface, InterfaceRef},
+};
+
+use crate::{
+    bluetooth::BluetoothItem,
+    brightness::{BrightnessItem, MONITOR_ID},
+    daemon::match_set_command,
+    error::{DaemonError, ErrorCode},
+    fan_profile::{FanProfile, FanProfileItem},
+    observed::Observed,
+    protocol::DaemonItem,
+    ram::Ram,
+    snapshot::{current_snapshot, subscribe_snapshot},
+    tuples::{TUPLE_NAMES, TupleName},
+    volume::VolumeItem,
+};
+
+/// Well-known name of the daemon on the session bus
+pub const BUS_NAME: &str = "org.bar_daemon.Daemon1";
+/// Path which each module's object is under
+pub const OBJECT_PATH: &str = "/org/bar_daemon/Daemon1";
+
+/// # Documentation
+/// Errors returned by methods of the D-Bus interfaces, named after the `ErrorCode` of the failed request
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.bar_daemon.Daemon1.Error")]
+pub enum BusError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    Internal(String),
+    InvalidMessage(String),
+    InvalidValue(String),
+    NoKnownValue(String),
+    CommandMissing(String),
+    CommandFailed(String),
+    DeviceAbsent(String),
+}
+
+impl From<DaemonError> for BusError {
+    fn from(e: DaemonError) -> Self {
+        let message = e.to_string();
+
+        match ErrorCode::from(&e) {
+            ErrorCode::Internal | ErrorCode::DaemonUnreachable => Self::Internal(message),
+            ErrorCode::InvalidMessage => Self::InvalidMessage(message),
+            ErrorCode::InvalidValue => Self::InvalidValue(message),
+            ErrorCode::NoKnownValue => Self::NoKnownValue(message),
+            ErrorCode::CommandMissing => Self::CommandMissing(message),
+            ErrorCode::CommandFailed => Self::CommandFailed(message),
+            ErrorCode::DeviceAbsent => Self::DeviceAbsent(message),
+        }
+    }
+}
+
+/// # Documentation
+/// Set `item` to `value`, in the same way as `DaemonMessage::Set`
+async fn set(item: DaemonItem, value: String) -> Result<(), BusError> {
+    match_set_command(item, value).await?;
+
+    Ok(())
+}
+
+/// The value of `observed`, or the default value if it isn't `Valid` (Clients check `Available` to tell these apart)
+fn value_or_default<T: Default>(observed: Observed<T>) -> T {
+    match observed {
+        Observed::Valid(value) => value,
+        _ => T::default(),
+    }
+}
+
+/// Why `observed` isn't `Valid`, or an empty string if it is
+fn reason<T>(observed: &Observed<T>) -> String {
+    match observed {
+        Observed::Valid(_) => String::new(),
+        Observed::Recovering => String::from("Recovering"),
+        other => other.reason().map_or_else(String::new, ToString::to_string),
+    }
+}
+
+#[derive(Debug)]
+pub struct VolumeObject;
+
+#[zbus::interface(name = "org.bar_daemon.Daemon1.Volume")]
+impl VolumeObject {
+    #[zbus(property)]
+    async fn available(&self) -> bool {
+        current_snapshot().await.volume.is_valid()
+    }
+
+    #[zbus(property)]
+    async fn reason(&self) -> String {
+        reason(&current_snapshot().await.volume)
+    }
+
+    #[zbus(property)]
+    async fn percent(&self) -> u32 {
+        value_or_default(current_snapshot().await.volume).percent
+    }
+
+    #[zbus(property)]
+    async fn mute(&self) -> bool {
+        value_or_default(current_snapshot().await.volume).mute
+    }
+
+    #[zbus(property)]
+    async fn icon(&self) -> String {
+        value_or_default(current_snapshot().await.volume).get_icon()
+    }
+
+    /// Set the volume percent, relative changes (e.g. "+5") are allowed
+    async fn set_percent(&self, value: String) -> Result<(), BusError> {
+        set(DaemonItem::Volume(VolumeItem::Percent), value).await
+    }
+
+    /// Set mute to "true", "false" or "toggle"
+    async fn set_mute(&self, value: String) -> Result<(), BusError> {
+        set(DaemonItem::Volume(VolumeItem::Mute), value).await
+    }
+}
+
+#[derive(Debug)]
+pub struct BrightnessObject;
+
+#[zbus::interface(name = "org.bar_daemon.Daemon1.Brightness")]
+impl BrightnessObject {
+    #[zbus(property)]
+    async fn available(&self) -> bool {
+        current_snapshot().await.brightness.is_valid()
+    }
+
+    #[zbus(property)]
+    async fn reason(&self) -> String {
+        reason(&current_snapshot().await.brightness)
+    }
+
+    #[zbus(property)]
+    async fn monitor(&self) -> u32 {
+        value_or_default(current_snapshot().await.brightness).monitor
+    }
+
+    #[zbus(property)]
+    async fn keyboard(&self) -> u32 {
+        value_or_default(current_snapshot().await.brightness).keyboard
+    }
+
+    #[zbus(property)]
+    async fn icon(&self) -> String {
+        value_or_default(current_snapshot().await.brightness).get_icon(MONITOR_ID)
+    }
+
+    /// Set the monitor brightness percent, relative changes (e.g. "+5") are allowed
+    async fn set_monitor(&self, value: String) -> Result<(), BusError> {
+        set(DaemonItem::Brightness(BrightnessItem::Monitor), value).await
+    }
+
+    /// Set the keyboard brightness percent, relative changes (e.g. "+5") are allowed
+    async fn set_keyboard(&self, value: String) -> Result<(), BusError> {
+        set(DaemonItem::Brightness(BrightnessItem::Keyboard), value).await
+    }
+}
+
+#[derive(Debug)]
+pub struct BluetoothObject;
+
+#[zbus::interface(name = "org.bar_daemon.Daemon1.Bluetooth")]
+impl BluetoothObject {
+    #[zbus(property)]
+    async fn available(&self) -> bool {
+        current_snapshot().await.bluetooth.is_valid()
+    }
+
+    #[zbus(property)]
+    async fn reason(&self) -> String {
+        reason(&current_snapshot().await.bluetooth)
+    }
+
+    #[zbus(property)]
+    async fn state(&self) -> bool {
+        value_or_default(current_snapshot().await.bluetooth).state
+    }
+
+    #[zbus(property)]
+    async fn icon(&self) -> String {
+        value_or_default(current_snapshot().await.bluetooth).get_icon()
+    }
+
+    /// Set the state to "true", "false" or "toggle"
+    async fn set_state(&self, value: String) -> Result<(), BusError> {
+        set(DaemonItem::Bluetooth(BluetoothItem::State), value).await
+    }
+}
+
+#[derive(Debug)]
+pub struct BatteryObject;
+
+#[zbus::interface(name = "org.bar_daemon.Daemon1.Battery")]
+impl BatteryObject {
+    #[zbus(property)]
+    async fn available(&self) -> bool {
+        current_snapshot().await.battery.is_valid()
+    }
+
+    #[zbus(property)]
+    async fn reason(&self) -> String {
+        reason(&current_snapshot().await.battery)
+    }
+
+    #[zbus(property)]
+    async fn state(&self) -> String {
+        value_or_default(current_snapshot().await.battery).state.to_string()
+    }
+
+    #[zbus(property)]
+    async fn percent(&self) -> u32 {
+        value_or_default(current_snapshot().await.battery).percent
+    }
+
+    #[zbus(property)]
+    async fn time(&self) -> String {
+        value_or_default(current_snapshot().await.battery)
+            .time
+            .map(|time| time.to_string())
+            .unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    async fn icon(&self) -> String {
+        value_or_default(current_snapshot().await.battery).get_icon()
+    }
+}
+
+#[derive(Debug)]
+pub struct RamObject;
+
+#[zbus::interface(name = "org.bar_daemon.Daemon1.Ram")]
+impl RamObject {
+    #[zbus(property)]
+    async fn available(&self) -> bool {
+        current_snapshot().await.ram.is_valid()
+    }
+
+    #[zbus(property)]
+    async fn reason(&self) -> String {
+        reason(&current_snapshot().await.ram)
+    }
+
+    #[zbus(property)]
+    async fn total(&self) -> u64 {
+        value_or_default(current_snapshot().await.ram).total
+    }
+
+    #[zbus(property)]
+    async fn used(&self) -> u64 {
+        value_or_default(current_snapshot().await.ram).used
+    }
+
+    #[zbus(property)]
+    async fn percent(&self) -> u32 {
+        value_or_default(current_snapshot().await.ram).percent
+    }
+
+    #[zbus(property)]
+    #[allow(clippy::unused_self)]
+    fn icon(&self) -> String {
+        Ram::get_icon()
+    }
+}
+
+#[derive(Debug)]
+pub struct FanProfileObject;
+
+#[zbus::interface(name = "org.bar_daemon.Daemon1.FanProfile")]
+impl FanProfileObject {
+    #[zbus(property)]
+    async fn available(&self) -> bool {
+        current_snapshot().await.fan_profile.is_valid()
+    }
+
+    #[zbus(property)]
+    async fn reason(&self) -> String {
+        reason(&current_snapshot().await.fan_profile)
+    }
+
+    #[zbus(property)]
+    async fn profile(&self) -> String {
+        value_or_default(current_snapshot().await.fan_profile).profile.to_string()
+    }
+
+    #[zbus(property)]
+    #[allow(clippy::unused_self)]
+    fn icon(&self) -> String {
+        FanProfile::get_icon()
+    }
+
+    /// Set the profile by name, or to "next" or "prev"
+    async fn set_profile(&self, value: String) -> Result<(), BusError> {
+        set(DaemonItem::FanProfile(FanProfileItem::Profile), value).await
+    }
+}
+
+/// # Documentation
+/// Serve each module's object on the connection being built
+///
+/// # Errors
+/// Returns an error if an object can't be served
+pub fn serve_objects(builder: connection::Builder<'_>) -> zbus::Result<connection::Builder<'_>> {
+    builder
+        .serve_at(format!("{OBJECT_PATH}/Volume"), VolumeObject)?
+        .serve_at(format!("{OBJECT_PATH}/Brightness"), BrightnessObject)?
+        .serve_at(format!("{OBJECT_PATH}/Bluetooth"), BluetoothObject)?
+        .serve_at(format!("{OBJECT_PATH}/Battery"), BatteryObject)?
+        .serve_at(format!("{OBJECT_PATH}/Ram"), RamObject)?
+        .serve_at(format!("{OBJECT_PATH}/FanProfile"), FanProfileObject)
+}
+
+async fn connect_session() -> zbus::Result<Connection> {
+    serve_objects(connection::Builder::session()?.name(BUS_NAME)?)?.build().await
+}
+
+/// # Documentation
+/// Export the daemon on the session bus as `org.bar_daemon.Daemon1`, emitting `PropertiesChanged` whenever a value changes.
+/// The daemon keeps running without the service if there is no session bus, or the name is taken
+pub fn spawn_dbus_service(shutdown_notify: Arc<Notify>) {
+    tokio::spawn(async move {
+        let connection = match connect_session().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("D-Bus service not started: {e}");
+                return;
+            }
+        };
+
+        info!("D-Bus service started as {BUS_NAME}");
+
+        let mut snapshot_rx = subscribe_snapshot();
+
+        loop {
+            tokio::select! {
+                event = snapshot_rx.recv() => match event {
+                    Ok(event) => {
+                        if let Err(e) = emit_changes(&connection, event.module()).await {
+                            warn!("Could not emit D-Bus PropertiesChanged: {e}");
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        // The missed changes are unknown, so every module's properties are emitted from the current snapshot
+                        warn!("D-Bus service missed {n} snapshot events, emitting every property");
+
+                        if let Err(e) = emit_all_changes(&connection).await {
+                            warn!("Could not emit D-Bus PropertiesChanged: {e}");
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                () = shutdown_notify.notified() => break,
+            }
+        }
+    });
+}
+
+/// # Documentation
+/// Emit `PropertiesChanged` for every property of every module, each read from the current snapshot
+///
+/// # Errors
+/// Returns an error if a module's object isn't served, or a signal can't be sent
+#[instrument(skip(connection))]
+pub async fn emit_all_changes(connection: &Connection) -> zbus::Result<()> {
+    for module in (0..TUPLE_NAMES.len()).filter_map(|index| TupleName::try_from(index).ok()) {
+        emit_changes(connection, module).await?;
+    }
+
+    Ok(())
+}
+
+/// # Documentation
+/// Emit `PropertiesChanged` for every property of `module`, each read from the current snapshot
+///
+/// # Errors
+/// Returns an error if the module's object isn't served, or a signal can't be sent
+#[instrument(skip(connection))]
+pub async fn emit_changes(connection: &Connection, module: TupleName) -> zbus::Result<()> {
+    match module {
+        TupleName::Volume => {
+            let object = object::<VolumeObject>(connection, "Volume").await?;
+            let (iface, emitter) = (object.get().await, object.signal_emitter());
+            iface.available_changed(emitter).await?;
+            iface.reason_changed(emitter).await?;
+            iface.percent_changed(emitter).await?;
+            iface.mute_changed(emitter).await?;
+            iface.icon_changed(emitter).await?;
+        }
+        TupleName::Brightness => {
+            let object = object::<BrightnessObject>(connection, "Brightness").await?;
+            let (iface, emitter) = (object.get().await, object.signal_emitter());
+            iface.available_changed(emitter).await?;
+            iface.reason_changed(emitter).await?;
+            iface.monitor_changed(emitter).await?;
+            iface.keyboard_changed(emitter).await?;
+            iface.icon_changed(emitter).await?;
+        }
+        TupleName::Bluetooth => {
+            let object = object::<BluetoothObject>(connection, "Bluetooth").await?;
+            let (iface, emitter) = (object.get().await, object.signal_emitter());
+            iface.available_changed(emitter).await?;
+            iface.reason_changed(emitter).await?;
+            iface.state_changed(emitter).await?;
+            iface.icon_changed(emitter).await?;
+        }
+        TupleName::Battery => {
+            let object = object::<BatteryObject>(connection, "Battery").await?;
+            let (iface, emitter) = (object.get().await, object.signal_emitter());
+            iface.available_changed(emitter).await?;
+            iface.reason_changed(emitter).await?;
+            iface.state_changed(emitter).await?;
+            iface.percent_changed(emitter).await?;
+            iface.time_changed(emitter).await?;
+            iface.icon_changed(emitter).await?;
+        }
+        TupleName::Ram => {
+            let object = object::<RamObject>(connection, "Ram").await?;
+            let (iface, emitter) = (object.get().await, object.signal_emitter());
+            iface.available_changed(emitter).await?;
+            iface.reason_changed(emitter).await?;
+            iface.total_changed(emitter).await?;
+            iface.used_changed(emitter).await?;
+            iface.percent_changed(emitter).await?;
+        }
+        TupleName::FanProfile => {
+            let object = object::<FanProfileObject>(connection, "FanProfile").await?;
+            let (iface, emitter) = (object.get().await, object.signal_emitter());
+            iface.available_changed(emitter).await?;
+            iface.reason_changed(emitter).await?;
+            iface.profile_changed(emitter).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn object<I: Interface>(connection: &Connection, name: &str) -> zbus::Result<InterfaceRef<I>> {
+    connection.object_server().interface(format!("{OBJECT_PATH}/{name}")).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, time::Duration};
+
+    use futures::StreamExt;
+    use tokio::net::UnixStream;
+    use zbus::{Connection, Guid, MessageStream, connection, fdo::PropertiesProxy, names::InterfaceName};
+
+    use super::{BusError, OBJECT_PATH, emit_all_changes, serve_objects};
+    use crate::{error::DaemonError, tuples::TUPLE_NAMES};
+
+    #[test]
+    fn errors_are_named_after_error_code() {
+        assert!(matches!(
+            BusError::from(DaemonError::ReadOnlyItem(String::from("Ram"))),
+            BusError::InvalidMessage(_)
+        ));
+        assert!(matches!(
+            BusError::from(DaemonError::NoKnownValue(String::from("volume"))),
+            BusError::NoKnownValue(_)
+        ));
+    }
+
+    /// The service's objects served on one end of a peer-to-peer connection, and the other end
+    async fn served_pair() -> (Connection, Connection) {
+        let (server_stream, client_stream) = UnixStream::pair().unwrap_or_else(|e| panic!("{e}"));
+
+        let server = async {
+            serve_objects(
+                connection::Builder::unix_stream(server_stream)
+                    .server(Guid::generate())?
+                    .p2p(),
+            )?
+            .build()
+            .await
+        };
+        let client = connection::Builder::unix_stream(client_stream).p2p().build();
+
+        tokio::try_join!(server, client).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    #[tokio::test]
+    async fn properties_describe_unread_values() {
+        let (server, client) = served_pair().await;
+
+        let proxy = PropertiesProxy::builder(&client)
+            .path(format!("{OBJECT_PATH}/Ram"))
+            .and_then(|builder| builder.destination("org.bar_daemon.Daemon1"))
+            .unwrap_or_else(|e| panic!("{e}"))
+            .build()
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+
+        let properties = proxy
+            .get_all(InterfaceName::from_static_str_unchecked("org.bar_daemon.Daemon1.Ram"))
+            .await
+            .unwrap_or_else(|e| panic!("{e}"));
+
+        // Nothing has been read in this test, so the value isn't available yet
+        assert_eq!(properties.get("Available").and_then(|v| bool::try_from(v).ok()), Some(false));
+        assert_eq!(properties.get("Total").and_then(|v| u64::try_from(v).ok()), Some(0));
+
+        drop(server);
+    }
+    #[tokio::test]
+    async fn missed_changes_emit_every_module() {
+        let (server, client) = served_pair().await;
+        let mut messages = MessageStream::from(&client);
+
+        assert!(emit_all_changes(&server).await.is_ok());
+
+        // Every module's object emits its properties, not only those of the modules which changed
+        let mut paths = BTreeSet::new();
+        while paths.len() < TUPLE_NAMES.len() {
+            let message = tokio::time::timeout(Duration::from_secs(5), messages.next())
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| panic!("Only {paths:?} emitted PropertiesChanged"))
+                .unwrap_or_else(|e| panic!("{e}"));
+            let header = message.header();
+
+            if header.member().is_some_and(|member| member.as_str() == "PropertiesChanged") {
+                paths.extend(header.path().map(ToString::to_string));
+            }
+        }
+
+        assert!(paths.contains(&format!("{OBJECT_PATH}/FanProfile")), "{paths:?}");
+    }
+}
@@ -0,0 +1,470 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use tokio::net::UnixStream;
+use zbus::{Connection, fdo::DBusProxy, names::BusName};
+
+use crate::{
+    battery, bluetooth,
+    brightness::{self, KEYBOARD_ID, MONITOR_ID},
+    config::{config_path, parse_config_file},
+    fan_profile, ram,
+    socket::current_uid,
+    tuples::{TUPLE_NAMES, TupleName},
+    volume,
+};
+
+const SYSFS: &str = "/sys";
+
+/// Names which `asusd` (Used by `asusctl`) owns on the system bus, newest first
+const ASUSD_NAMES: &[&str] = &["xyz.ljones.Asusd", "org.asuslinux.Daemon"];
+
+/// # Documentation
+/// Whether a check passed, or how badly it failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    /// Something which only limits part of the daemon (e.g. the D-Bus service), or which may be intended
+    Warn,
+    Fail,
+}
+
+/// # Documentation
+/// The result of checking one thing which the daemon needs
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// The module, or part of the daemon, which was checked
+    pub subject: String,
+    pub outcome: Outcome,
+    pub message: String,
+    /// How to fix a check which didn't pass
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(subject: &str, message: impl Into<String>) -> Self {
+        Self {
+            subject: subject.to_string(),
+            outcome: Outcome::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(subject: &str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            subject: subject.to_string(),
+            outcome: Outcome::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(subject: &str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            subject: subject.to_string(),
+            outcome: Outcome::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// # Documentation
+/// Every check made by `bar_daemon doctor`
+#[derive(Debug, Clone, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    /// # Documentation
+    /// Whether no check failed (Warnings are allowed)
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.outcome != Outcome::Fail)
+    }
+}
+
+/// # Documentation
+/// Check the commands, devices and D-Bus services which each module's source needs,
+/// the config file, and the permissions of the socket at `socket_path`
+pub async fn run_doctor(socket_path: &Path) -> DoctorReport {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let sysfs = Path::new(SYSFS);
+
+    let mut checks = Vec::new();
+
+    // Commands which each module's source runs
+    for (module, command, hint) in [
+        (
+            TupleName::Volume,
+            volume::COMMAND,
+            "Install WirePlumber, which provides `wpctl` for Pipewire",
+        ),
+        (TupleName::Brightness, brightness::COMMAND, "Install `brightnessctl`"),
+        (
+            TupleName::Bluetooth,
+            bluetooth::COMMAND,
+            "Install the `bluetooth` command (From BlueZ)",
+        ),
+        (TupleName::Battery, battery::COMMAND, "Install `acpi`"),
+        (TupleName::Ram, ram::COMMAND, "Install procps, which provides `free`"),
+        (
+            TupleName::FanProfile,
+            fan_profile::COMMAND,
+            "Install asusctl (https://asus-linux.org)",
+        ),
+    ] {
+        checks.push(check_command(TUPLE_NAMES[module as usize], command, &path, hint));
+    }
+
+    // Devices which the brightness and battery are read from
+    checks.push(check_device(sysfs, "monitor", MONITOR_ID));
+    checks.push(check_device(sysfs, "keyboard", KEYBOARD_ID));
+    checks.push(check_battery(sysfs));
+
+    checks.extend(check_dbus().await);
+    checks.extend(check_config(&config_path()));
+    checks.extend(check_socket(socket_path).await);
+
+    DoctorReport { checks }
+}
+
+/// # Documentation
+/// Find the executable called `name` in the directories of `path` (Formatted like `$PATH`)
+#[must_use]
+pub fn find_in_path(name: &str, path: &OsStr) -> Option<PathBuf> {
+    std::env::split_paths(path).map(|dir| dir.join(name)).find(|candidate| {
+        fs::metadata(candidate).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    })
+}
+
+fn check_command(subject: &str, command: &str, path: &OsStr, hint: &str) -> Check {
+    match find_in_path(command, path) {
+        Some(found) => Check::pass(subject, format!("`{command}` found at {}", found.display())),
+        None => Check::fail(subject, format!("`{command}` is not on $PATH"), hint),
+    }
+}
+
+/// # Documentation
+/// Find the sysfs directory of the backlight or LED which brightnessctl calls `device_id`
+#[must_use]
+pub fn find_device(sysfs: &Path, device_id: &str) -> Option<PathBuf> {
+    ["backlight", "leds"]
+        .iter()
+        .map(|class| sysfs.join("class").join(class).join(device_id))
+        .find(|device| device.exists())
+}
+
+fn check_device(sysfs: &Path, name: &str, device_id: &str) -> Check {
+    let subject = TUPLE_NAMES[TupleName::Brightness as usize];
+
+    match find_device(sysfs, device_id) {
+        Some(device) => Check::pass(subject, format!("{name} device '{device_id}' found at {}", device.display())),
+        None => Check::fail(
+            subject,
+            format!("{name} device '{device_id}' does not exist"),
+            "List the devices with `brightnessctl -l`, and set `MONITOR_ID` and `KEYBOARD_ID` in src/brightness/source.rs",
+        ),
+    }
+}
+
+fn check_battery(sysfs: &Path) -> Check {
+    let subject = TUPLE_NAMES[TupleName::Battery as usize];
+
+    let battery = fs::read_dir(sysfs.join("class").join("power_supply"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .find(|supply| fs::read_to_string(supply.path().join("type")).is_ok_and(|kind| kind.trim() == "Battery"));
+
+    match battery {
+        Some(battery) => Check::pass(subject, format!("battery found at {}", battery.path().display())),
+        None => Check::warn(
+            subject,
+            "no battery was found in /sys/class/power_supply",
+            "The battery will always be unavailable on this machine",
+        ),
+    }
+}
+
+async fn check_dbus() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    match Connection::session().await {
+        Ok(_) => checks.push(Check::pass("dbus", "connected to the session bus")),
+        Err(e) => checks.push(Check::warn(
+            "dbus",
+            format!("could not connect to the session bus: {e}"),
+            "Run the daemon inside your graphical session (e.g. as a `systemctl --user` service), so it can be exported as org.bar_daemon.Daemon1",
+        )),
+    }
+
+    let subject = TUPLE_NAMES[TupleName::FanProfile as usize];
+    match asusd_running().await {
+        Ok(true) => checks.push(Check::pass(subject, "asusd is running on the system bus")),
+        Ok(false) => checks.push(Check::fail(
+            subject,
+            "asusd is not running on the system bus, so `asusctl` can't change the fan profile",
+            "Start asusd with `sudo systemctl enable --now asusd`",
+        )),
+        Err(e) => checks.push(Check::fail(
+            subject,
+            format!("could not connect to the system bus: {e}"),
+            "Make sure dbus is running",
+        )),
+    }
+
+    checks
+}
+
+async fn asusd_running() -> zbus::Result<bool> {
+    let connection = Connection::system().await?;
+    let proxy = DBusProxy::new(&connection).await?;
+
+    for name in ASUSD_NAMES {
+        if proxy.name_has_owner(BusName::try_from(*name)?).await? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// # Documentation
+/// Check that the config file at `config_path` parses, and that its values can be used
+#[must_use]
+pub fn check_config(config_path: &Path) -> Vec<Check> {
+    if !config_path.exists() {
+        return vec![Check::pass(
+            "config",
+            format!(
+                "{} does not exist, so the default config will be copied to it",
+                config_path.display()
+            ),
+        )];
+    }
+
+    match parse_config_file(config_path) {
+        Ok(config) => {
+            let problems = config.problems();
+            if problems.is_empty() {
+                return vec![Check::pass("config", format!("{} is valid", config_path.display()))];
+            }
+
+            problems
+                .into_iter()
+                .map(|problem| Check::warn("config", problem, format!("Change the value in {}", config_path.display())))
+                .collect()
+        }
+        Err(e) => vec![Check::fail(
+            "config",
+            format!(
+                "{} can't be used, so the daemon and CLI won't start: {e}",
+                config_path.display()
+            ),
+            "Fix the config file, using the example config in the README",
+        )],
+    }
+}
+
+/// # Documentation
+/// Check that the socket at `socket_path`, and its directory, can only be used by this user, and whether a daemon is listening on it
+pub async fn check_socket(socket_path: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    // The directory is created by the daemon, so only check it when it exists
+    if let Some((dir, metadata)) = socket_path
+        .parent()
+        .and_then(|dir| Some((dir, fs::symlink_metadata(dir).ok()?)))
+    {
+        let mode = metadata.permissions().mode() & 0o777;
+
+        if !metadata.file_type().is_dir() {
+            checks.push(Check::fail(
+                "socket",
+                format!("{} is not a directory (e.g. a symlink)", dir.display()),
+                "Use a socket path in a directory which belongs to you (e.g. the default one in $XDG_RUNTIME_DIR)",
+            ));
+        } else if metadata.uid() != current_uid() {
+            checks.push(Check::fail(
+                "socket",
+                format!("{} belongs to another user", dir.display()),
+                "Use a socket path in a directory which belongs to you (e.g. the default one in $XDG_RUNTIME_DIR)",
+            ));
+        } else if mode != 0o700 {
+            // The daemon refuses to bind its socket in the directory
+            checks.push(Check::fail(
+                "socket",
+                format!("{} is not private (Mode {mode:o})", dir.display()),
+                format!("chmod 700 {}", dir.display()),
+            ));
+        } else {
+            checks.push(Check::pass("socket", format!("{} is private (Mode {mode:o})", dir.display())));
+        }
+    }
+
+    let start_hint = "Start the daemon with `systemctl --user start bar_daemon` or `bar_daemon daemon`";
+
+    match fs::symlink_metadata(socket_path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => checks.push(Check::fail(
+            "socket",
+            format!("{} exists and is not a socket", socket_path.display()),
+            "Move the file, or use another socket path with `--socket` or `socket_path` in the config",
+        )),
+        Ok(metadata) => {
+            let mode = metadata.permissions().mode() & 0o777;
+            if mode & 0o077 != 0 {
+                checks.push(Check::warn(
+                    "socket",
+                    format!("{} can be connected to by other users (Mode {mode:o})", socket_path.display()),
+                    "Restart the daemon, which only lets you connect to its socket",
+                ));
+            }
+
+            match UnixStream::connect(socket_path).await {
+                Ok(_) => checks.push(Check::pass(
+                    "socket",
+                    format!("daemon is listening on {}", socket_path.display()),
+                )),
+                Err(e) => checks.push(Check::warn(
+                    "socket",
+                    format!("{} exists, but no daemon is listening on it: {e}", socket_path.display()),
+                    start_hint,
+                )),
+            }
+        }
+        Err(_) => checks.push(Check::warn(
+            "socket",
+            format!("no daemon is listening on {}", socket_path.display()),
+            start_hint,
+        )),
+    }
+
+    checks
+}
+
+impl std::fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let outcome = match check.outcome {
+                Outcome::Pass => "PASS",
+                Outcome::Warn => "WARN",
+                Outcome::Fail => "FAIL",
+            };
+
+            writeln!(f, "[{outcome}] {:<12} {}", check.subject, check.message)?;
+
+            if let Some(hint) = &check.hint {
+                writeln!(f, "       {:<12} -> {hint}", "")?;
+            }
+        }
+
+        let count = |outcome| self.checks.iter().filter(|check| check.outcome == outcome).count();
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{} passed, {} warnings, {} failed",
+            count(Outcome::Pass),
+            count(Outcome::Warn),
+            count(Outcome::Fail)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    };
+
+    use super::{Outcome, check_config, check_socket, find_device, find_in_path};
+    use crate::socket::bind_socket;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bar_daemon_doctor_test_{}_{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::create_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn commands_must_be_executable() {
+        let dir = test_dir("path");
+        let _ = fs::write(dir.join("wpctl"), "");
+        let _ = fs::write(dir.join("acpi"), "");
+        let _ = fs::set_permissions(dir.join("wpctl"), fs::Permissions::from_mode(0o755));
+
+        let path = std::env::join_paths([Path::new("/nonexistent"), &dir]).unwrap_or_default();
+        assert_eq!(find_in_path("wpctl", &path), Some(dir.join("wpctl")));
+        assert_eq!(find_in_path("acpi", &path), None);
+        assert_eq!(find_in_path("free", &path), None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn devices_are_found_in_backlight_or_leds() {
+        let sysfs = test_dir("sysfs");
+        let _ = fs::create_dir_all(sysfs.join("class/leds/asus::kbd_backlight"));
+
+        assert_eq!(
+            find_device(&sysfs, "asus::kbd_backlight"),
+            Some(sysfs.join("class/leds/asus::kbd_backlight"))
+        );
+        assert_eq!(find_device(&sysfs, "nvidia_wmi_ec_backlight"), None);
+
+        let _ = fs::remove_dir_all(&sysfs);
+    }
+
+    #[test]
+    fn config_is_validated() {
+        let dir = test_dir("config");
+        let outcomes = |path: &Path| check_config(path).iter().map(|check| check.outcome).collect::<Vec<_>>();
+
+        // A missing config uses the defaults
+        assert_eq!(outcomes(&dir.join("missing.toml")), [Outcome::Pass]);
+
+        let _ = fs::write(dir.join("invalid.toml"), "polling_rate = \"fast\"");
+        assert_eq!(outcomes(&dir.join("invalid.toml")), [Outcome::Fail]);
+
+        let _ = fs::write(dir.join("unusable.toml"), "polling_rate = 0\ncommand_timeout = 0");
+        assert_eq!(outcomes(&dir.join("unusable.toml")), [Outcome::Warn, Outcome::Warn]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn socket_permissions_are_checked() {
+        let dir = test_dir("socket");
+        let socket_path = dir.join("private").join("bar_daemon.sock");
+
+        // No daemon is listening yet
+        let checks = check_socket(&socket_path).await;
+        assert!(checks.iter().all(|check| check.outcome != Outcome::Fail), "{checks:?}");
+
+        let _listener = bind_socket(&socket_path);
+        let checks = check_socket(&socket_path).await;
+        assert!(checks.iter().all(|check| check.outcome == Outcome::Pass), "{checks:?}");
+
+        // A directory which other users can access fails, since the daemon won't use it
+        let _ = fs::set_permissions(dir.join("private"), fs::Permissions::from_mode(0o755));
+        let checks = check_socket(&socket_path).await;
+        assert_eq!(checks.first().map(|check| check.outcome), Some(Outcome::Fail));
+
+        // A file which isn't a socket fails
+        let file_path = dir.join("private").join("not_a_socket");
+        let _ = fs::write(&file_path, "");
+        let checks = check_socket(&file_path).await;
+        assert!(checks.iter().any(|check| check.outcome == Outcome::Fail), "{checks:?}");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,8 +9,26 @@ pub enum DaemonError {
     #[error("Postcard Serialize/Deserialize Error:\t\"{0}\"")]
     PostcardError(#[from] postcard::Error),
 
-    #[error("Command '{name}' With Args '{args:?}' Could Not Run:\t\"{e}\"")]
-    CommandError { name: String, args: Vec<String>, e: String },
+    #[error("Command '{name}' With Args '{args:?}' Could Not Run:\t\"{e}\"\t(stderr: \"{stderr}\")")]
+    CommandError {
+        name: String,
+        args: Vec<String>,
+        e: String,
+        stderr: String,
+    },
+
+    #[error("Command '{name}' With Args '{args:?}' Timed Out After {timeout:?}")]
+    CommandTimeout {
+        name: String,
+        args: Vec<String>,
+        timeout: std::time::Duration,
+    },
+
+    #[error("Command '{name}' Could Not Be Found, Is It Installed?")]
+    CommandNotFound { name: String },
+
+    #[error("Device Could Not Be Found:\t\"{0}\"")]
+    DeviceAbsent(String),
 
     #[error("Bytes Could Not Convert To String:\t\"{0}\"")]
     IntegerFromByteString(#[from] std::string::FromUtf8Error),
@@ -26,6 +45,9 @@ pub enum DaemonError {
     #[error("String could not parse enough arguments:\t\"{0}\"")]
     ParseError(String),
 
+    #[error("TOML Could Not Be Parsed:\t\"{0}\"")]
+    TomlError(#[from] toml::de::Error),
+
     #[error("Serde JSON Serialization Failed:\t\"{0}\"")]
     JsonError(#[from] serde_json::Error),
 
@@ -44,6 +66,130 @@ pub enum DaemonError {
     #[error("Could not read/write to path:\t\"{0}\"")]
     PathRwError(String),
 
+    #[error("Item can't be set:\t\"{0}\"")]
+    ReadOnlyItem(String),
+
+    #[error("Another daemon is already using the socket '{0}' (Use --replace to replace it)")]
+    AlreadyRunning(String),
+
+    #[error("The HTTP server on '{0}' needs a token, since any local process or website could use it without one")]
+    HttpTokenMissing(String),
+
+    #[error("Could not write output:\t\"{0}\"")]
+    OutputError(std::io::Error),
+
+    #[error("Daemon closed the connection without replying")]
+    ConnectionClosed,
+
+    #[error("Request is longer than {0} bytes")]
+    MessageTooLarge(usize),
+
+    #[error("No value of type '{0}' has been read yet, so it can't be changed relative to its current value")]
+    NoKnownValue(String),
+
     #[error("Monitored value of type '{0}' could not be read after {1} attempts")]
     MonitoredReadAttemptFail(String, u32),
 }
+
+/// # Documentation
+/// The category of an error which is sent back to clients in `DaemonReply::Error`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorCode {
+    /// Any error which doesn't fit into another category
+    Internal,
+    /// The daemon isn't running, or couldn't be connected to
+    DaemonUnreachable,
+    /// The message sent to the daemon couldn't be understood
+    InvalidMessage,
+    /// The value given to a `set` couldn't be parsed
+    InvalidValue,
+    /// A relative `set` was requested for a value which has never been read
+    NoKnownValue,
+    /// A command needed for the value isn't installed
+    CommandMissing,
+    /// A command needed for the value failed
+    CommandFailed,
+    /// The device which the value belongs to isn't present
+    DeviceAbsent,
+}
+
+impl ErrorCode {
+    /// # Documentation
+    /// The exit code which the CLI uses when a request fails with this error
+    #[must_use]
+    pub const fn exit_code(self) -> u8 {
+        match self {
+            Self::Internal => 1,
+            Self::DaemonUnreachable => 3,
+            Self::InvalidMessage => 4,
+            Self::InvalidValue => 5,
+            Self::NoKnownValue => 6,
+            Self::CommandMissing => 7,
+            Self::CommandFailed => 8,
+            Self::DeviceAbsent => 9,
+        }
+    }
+}
+
+impl From<&DaemonError> for ErrorCode {
+    fn from(e: &DaemonError) -> Self {
+        match e {
+            DaemonError::SocketError(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                Self::DaemonUnreachable
+            }
+            DaemonError::ConnectionClosed => Self::DaemonUnreachable,
+            DaemonError::PostcardError(_) | DaemonError::MessageTooLarge(_) | DaemonError::ReadOnlyItem(_) => {
+                Self::InvalidMessage
+            }
+            DaemonError::ParseError(_)
+            | DaemonError::IntegerFromByteString(_)
+            | DaemonError::IntegerFromString(_)
+            | DaemonError::BoolFromString(_)
+            | DaemonError::StringToFloatError(_)
+            | DaemonError::IntError(_) => Self::InvalidValue,
+            DaemonError::NoKnownValue(_) => Self::NoKnownValue,
+            DaemonError::CommandNotFound { .. } => Self::CommandMissing,
+            DaemonError::CommandError { .. } | DaemonError::CommandTimeout { .. } => Self::CommandFailed,
+            DaemonError::DeviceAbsent(_) => Self::DeviceAbsent,
+            _ => Self::Internal,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn exit_codes_are_distinct() {
+    let codes = [
+        ErrorCode::Internal,
+        ErrorCode::DaemonUnreachable,
+        ErrorCode::InvalidMessage,
+        ErrorCode::InvalidValue,
+        ErrorCode::NoKnownValue,
+        ErrorCode::CommandMissing,
+        ErrorCode::CommandFailed,
+        ErrorCode::DeviceAbsent,
+    ]
+    .map(ErrorCode::exit_code);
+
+    for (i, code) in codes.iter().enumerate() {
+        assert!(
+            *code != 0 && *code != 2,
+            "Exit code {code} is reserved (Success or usage error)"
+        );
+        assert!(
+            !codes[i + 1..].contains(code),
+            "Exit code {code} is used more than once: {codes:?}"
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use clap::Subcommand;
+
+use crate::protocol::{DaemonItem, DaemonMessage};
+
+use super::FanProfileItem;
+
+#[derive(Subcommand)]
+pub enum FanProfileGetCommands {
+    #[command(alias = "prof", alias = "p")]
+    Profile,
+    #[command(alias = "i")]
+    Icon,
+}
+
+#[derive(Subcommand)]
+pub enum FanProfileSetCommands {
+    #[command(alias = "prof", alias = "p")]
+    Profile {
+        #[arg()]
+        value: String,
+    },
+}
+
+#[must_use]
+pub const fn match_get_commands(commands: &FanProfileGetCommands) -> DaemonMessage {
+    DaemonMessage::Get {
+        item: match commands {
+            FanProfileGetCommands::Profile => DaemonItem::FanProfile(FanProfileItem::Profile),
+            FanProfileGetCommands::Icon => DaemonItem::FanProfile(FanProfileItem::Icon),
+        },
+    }
+}
+
+#[must_use]
+pub fn match_set_commands(commands: FanProfileSetCommands) -> DaemonMessage {
+    match commands {
+        FanProfileSetCommands::Profile { value } => DaemonMessage::Set {
+            item: DaemonItem::FanProfile(FanProfileItem::Profile),
+            value,
+        },
+    }
+}
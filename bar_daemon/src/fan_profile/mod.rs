@@ -1,10 +1,11 @@
 use source::{FAN_STATE_STRINGS, FanProfileSource, default_source};
-use value::FanState;
 
-pub use value::{
-    FanProfile, FanProfileGetCommands, FanProfileItem, FanProfileSetCommands, evaluate_item, match_get_commands,
-    match_set_commands,
-};
+#[cfg(feature = "daemon")]
+pub use commands::{FanProfileGetCommands, FanProfileSetCommands, match_get_commands, match_set_commands};
+pub use source::COMMAND;
+pub use value::{FanProfile, FanProfileItem, FanState, evaluate_item};
 
+#[cfg(feature = "daemon")]
+mod commands;
 mod source;
 mod value;
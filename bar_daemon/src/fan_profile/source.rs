@@ -1,11 +1,12 @@
+use std::sync::Arc;
+
 use tracing::instrument;
 
 use crate::{
-    command,
+    command::{CommandRunner, default_runner},
     error::DaemonError,
-    monitored::Monitored,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
-    snapshot::{current_snapshot, update_snapshot},
+    observed::Observed::{self, Valid},
+    snapshot::{known_value, require_known, update_snapshot},
 };
 
 use super::{FanProfile, FanState};
@@ -20,17 +21,36 @@ pub trait FanProfileSource {
     fn set_profile(&self, profile_str: &str) -> impl std::future::Future<Output = Result<(), DaemonError>> + Send;
 }
 
+/// Command which the fan profile is read and set with
+pub const COMMAND: &str = "asusctl";
+
 // -------------- Default Source ---------------
 
 #[must_use]
 pub fn default_source() -> impl FanProfileSource {
-    AsusctlFanProfile
+    AsusctlFanProfile::new(default_runner())
 }
 
-// ---------------- Wpctl Source ---------------
+// --------------- Asusctl Source --------------
 
 #[derive(Debug)]
-pub struct AsusctlFanProfile;
+pub struct AsusctlFanProfile {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl AsusctlFanProfile {
+    #[must_use]
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    async fn read_inner(&self) -> Result<FanProfile, DaemonError> {
+        // Read the profile from the output of asusctl
+        let profile = get_asusctl_profile(self.runner.as_ref()).await?;
+
+        Ok(FanProfile { profile })
+    }
+}
 
 impl FanProfileSource for AsusctlFanProfile {
     /// # Errors
@@ -40,15 +60,8 @@ impl FanProfileSource for AsusctlFanProfile {
     /// Returns an error if the profile string can't be converted to ``FanState``
     #[instrument]
     async fn read(&self) -> Result<Observed<FanProfile>, DaemonError> {
-        fn read_inner() -> Result<FanProfile, DaemonError> {
-            // Read the profile from the output of asusctl
-            let profile = get_asusctl_profile()?;
-
-            Ok(FanProfile { profile })
-        }
-
         // Set as unavailable if the inner function threw an error
-        let fan_profile: Observed<_> = read_inner().into();
+        let fan_profile: Observed<_> = self.read_inner().await.into();
 
         // Update snapshot
         let _update = update_snapshot(fan_profile.clone()).await;
@@ -58,47 +71,26 @@ impl FanProfileSource for AsusctlFanProfile {
 
     /// # Errors
     /// Returns an error if the given value is not a valid profile
+    /// Returns an error if the profile is cycled and the profile has never been read
     /// Returns an error if the set command can't be ran
     #[instrument]
     async fn set_profile(&self, profile_str: &str) -> Result<(), DaemonError> {
-        let fan_profile = match current_snapshot().await.fan_profile {
-            Valid(fan_profile) => Valid(fan_profile),
-            Unavailable | Recovering => FanProfile::latest().await?,
-        };
-
-        let new_profile_idx;
-
-        let new_profile = if let Some(index) = FAN_STATE_STRINGS.iter().position(|&profile| profile == profile_str.trim()) {
-            // Set the new_profile_id
-            new_profile_idx = index;
-
-            // A new profile has been set
-            profile_str.trim()
+        // Only cycling the profile needs the current profile
+        let known = if FAN_STATE_STRINGS.contains(&profile_str.trim()) {
+            None
         } else {
-            let profile = fan_profile.clone().unwrap_or_default().profile;
-
-            // Profile is set via cyclic function
-            match profile_str {
-                "next" => {
-                    // Calculate the new profile's index
-                    new_profile_idx = (profile as usize + 1) % FAN_STATE_STRINGS.len();
-
-                    FAN_STATE_STRINGS[new_profile_idx]
-                }
-                "prev" => {
-                    new_profile_idx = (profile as usize).checked_sub(1).unwrap_or(FAN_STATE_STRINGS.len() - 1);
-
-                    FAN_STATE_STRINGS[new_profile_idx]
-                }
-                incorrect => return Err(DaemonError::ParseError(incorrect.to_string())),
-            }
+            known_value(self.read()).await
         };
 
+        let new_profile_idx = get_profile_idx_from_str(profile_str, known.as_ref())?;
+
         // Set the profile using asusctl
-        command::run("asusctl", &["profile", "set", new_profile])?;
+        self.runner
+            .run(COMMAND, &["profile", "set", FAN_STATE_STRINGS[new_profile_idx]])
+            .await?;
 
         // Update snapshot
-        let _update = update_snapshot(fan_profile.map(|_| FanProfile {
+        let _update = update_snapshot(Valid(FanProfile {
             profile: new_profile_idx.into(),
         }))
         .await;
@@ -107,9 +99,9 @@ impl FanProfileSource for AsusctlFanProfile {
     }
 }
 
-fn get_asusctl_output() -> Result<String, DaemonError> {
+async fn get_asusctl_output(runner: &dyn CommandRunner) -> Result<String, DaemonError> {
     // Get the profile output from asusctl
-    command::run("asusctl", &["profile", "get"])
+    runner.run(COMMAND, &["profile", "get"]).await
 }
 
 fn get_asusctl_split(output: &str) -> Result<&str, DaemonError> {
@@ -119,10 +111,10 @@ fn get_asusctl_split(output: &str) -> Result<&str, DaemonError> {
         .ok_or_else(|| DaemonError::ParseError(output.to_string()))
 }
 
-#[instrument]
-fn get_asusctl_profile() -> Result<FanState, DaemonError> {
+#[instrument(skip(runner))]
+async fn get_asusctl_profile(runner: &dyn CommandRunner) -> Result<FanState, DaemonError> {
     // Find the correct line where the fan profile is
-    let output = get_asusctl_output()?;
+    let output = get_asusctl_output(runner).await?;
     let output_line = get_asusctl_split(&output)?;
 
     // Match the profile string
@@ -139,3 +131,92 @@ fn get_asusctl_profile() -> Result<FanState, DaemonError> {
         },
     )
 }
+
+// ------------- Helper Functions --------------
+
+/// # Documentation
+/// Get the index (into `FAN_STATE_STRINGS`) of the profile which `profile_str` sets, cycling (next, prev) from `known`
+fn get_profile_idx_from_str(profile_str: &str, known: Option<&FanProfile>) -> Result<usize, DaemonError> {
+    if let Some(index) = FAN_STATE_STRINGS.iter().position(|&profile| profile == profile_str.trim()) {
+        // A new profile has been set
+        return Ok(index);
+    }
+
+    // Profile is set via cyclic function
+    match profile_str {
+        "next" => Ok((require_known(known)?.profile as usize + 1) % FAN_STATE_STRINGS.len()),
+        "prev" => Ok((require_known(known)?.profile as usize)
+            .checked_sub(1)
+            .unwrap_or(FAN_STATE_STRINGS.len() - 1)),
+        incorrect => Err(DaemonError::ParseError(incorrect.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{AsusctlFanProfile, FanProfile, FanProfileSource, FanState, get_profile_idx_from_str};
+    use crate::{command::fake::FakeRunner, error::DaemonError};
+
+    fn source(output: &str) -> AsusctlFanProfile {
+        AsusctlFanProfile::new(Arc::new(FakeRunner::new().with_output("asusctl profile get", output)))
+    }
+
+    #[tokio::test]
+    async fn reads_each_profile() {
+        for (name, profile) in [
+            ("Performance", FanState::Performance),
+            ("Balanced", FanState::Balanced),
+            ("Quiet", FanState::Quiet),
+        ] {
+            let output = format!("Active profile: {name}\nProfile on AC: Performance\nProfile on Battery: Quiet");
+
+            assert_eq!(source(&output).read_inner().await.ok(), Some(FanProfile { profile }));
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_profile_is_a_parse_error() {
+        let fan_profile = source("Active profile: Turbo").read_inner().await;
+        assert!(
+            matches!(fan_profile, Err(DaemonError::ParseError(ref profile)) if profile == "Turbo"),
+            "{fan_profile:?}"
+        );
+
+        let fan_profile = source("").read_inner().await;
+        assert!(matches!(fan_profile, Err(DaemonError::ParseError(_))), "{fan_profile:?}");
+    }
+
+    #[tokio::test]
+    async fn set_runs_asusctl() {
+        let runner = Arc::new(FakeRunner::new().with_output("asusctl profile set Quiet", ""));
+        let source = AsusctlFanProfile::new(runner.clone());
+
+        assert!(source.set_profile("Quiet").await.is_ok());
+        assert_eq!(runner.invocations(), ["asusctl profile set Quiet"]);
+    }
+
+    #[test]
+    fn profiles_cycle_from_known_profile() {
+        let quiet = FanProfile {
+            profile: FanState::Quiet,
+        };
+        let performance = FanProfile {
+            profile: FanState::Performance,
+        };
+
+        assert_eq!(get_profile_idx_from_str("Balanced", None).ok(), Some(1));
+        assert_eq!(get_profile_idx_from_str("next", Some(&quiet)).ok(), Some(0));
+        assert_eq!(get_profile_idx_from_str("prev", Some(&performance)).ok(), Some(2));
+        assert_eq!(get_profile_idx_from_str("prev", Some(&quiet)).ok(), Some(1));
+        assert!(matches!(
+            get_profile_idx_from_str("next", None),
+            Err(DaemonError::NoKnownValue(_))
+        ));
+        assert!(matches!(
+            get_profile_idx_from_str("Turbo", None),
+            Err(DaemonError::ParseError(_))
+        ));
+    }
+}
@@ -1,17 +1,19 @@
-use clap::Subcommand;
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
 use crate::{
     ICON_END, ICON_EXT, NOTIFICATION_ID, command,
     config::get_config,
-    daemon::{DaemonItem, DaemonMessage, DaemonReply},
     error::DaemonError,
     impl_monitored,
     monitored::{Monitored, MonitoredUpdate},
     notification::Notify,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
+    observed::{
+        Observed::{self, Failed, Recovering, Unavailable, Valid},
+        UnavailableReason,
+    },
     polled::Polled,
+    protocol::{DaemonItem, DaemonReply},
     snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot},
     tuples::ToTuples,
 };
@@ -20,7 +22,7 @@ use super::{FAN_STATE_STRINGS, FanProfileSource, default_source};
 
 const NOTIFICATION_OFFSET: u32 = 3;
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Ord, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, PartialOrd, Ord, Eq)]
 pub enum FanState {
     Performance = 0,
     Balanced = 1,
@@ -38,21 +40,10 @@ impl From<usize> for FanState {
     }
 }
 
-#[derive(Subcommand)]
-pub enum FanProfileGetCommands {
-    #[command(alias = "prof", alias = "p")]
-    Profile,
-    #[command(alias = "i")]
-    Icon,
-}
-
-#[derive(Subcommand)]
-pub enum FanProfileSetCommands {
-    #[command(alias = "prof", alias = "p")]
-    Profile {
-        #[arg()]
-        value: String,
-    },
+impl std::fmt::Display for FanState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", FAN_STATE_STRINGS[*self as usize])
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -62,7 +53,17 @@ pub enum FanProfileItem {
 }
 
 #[derive(
-    Clone, Debug, Default, PartialEq, PartialOrd, Ord, Eq, bar_daemon_derive::Polled, bar_daemon_derive::IntoSnapshotEvent,
+    Serialize,
+    Deserialize,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    PartialOrd,
+    Ord,
+    Eq,
+    bar_daemon_derive::Polled,
+    bar_daemon_derive::IntoSnapshotEvent,
 )]
 pub struct FanProfile {
     pub profile: FanState,
@@ -106,7 +107,7 @@ impl Notify<Self> for FanProfile {
     /// Returns an error if the requested value could not be parsed
     #[instrument]
     async fn notify(update: MonitoredUpdate<Self>) -> Result<(), DaemonError> {
-        fn do_notification(new: &FanProfile) -> Result<(), DaemonError> {
+        async fn do_notification(new: &FanProfile) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -120,12 +121,13 @@ impl Notify<Self> for FanProfile {
                     (NOTIFICATION_ID + NOTIFICATION_OFFSET).to_string().as_str(),
                     format!("Fan Profile: {}", FAN_STATE_STRINGS[new.profile as usize]).as_str(),
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
 
-        fn do_notification_unavailable() -> Result<(), DaemonError> {
+        async fn do_notification_unavailable(reason: Option<&UnavailableReason>) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -138,16 +140,18 @@ impl Notify<Self> for FanProfile {
                     "-r",
                     (NOTIFICATION_ID + NOTIFICATION_OFFSET).to_string().as_str(),
                     "Fan Profile Unavailable",
+                    reason.map(ToString::to_string).unwrap_or_default().as_str(),
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
 
         // If the new values are valid
         match update.new {
-            Valid(new) => do_notification(&new)?,
-            Unavailable | Recovering => do_notification_unavailable()?,
+            Valid(new) => do_notification(&new).await?,
+            Unavailable(_) | Recovering | Failed { .. } => do_notification_unavailable(update.new.reason()).await?,
         }
 
         Ok(())
@@ -173,13 +177,12 @@ pub async fn evaluate_item(
         // Get value (Try getting latest once if its unavailable)
         let profile = match current_snapshot().await.fan_profile {
             Valid(fan_profile) => Valid(fan_profile),
-            Unavailable | Recovering => FanProfile::latest().await?,
+            Unavailable(_) | Recovering | Failed { .. } => FanProfile::latest().await?,
         }
-        .map(|fan_profile| FAN_STATE_STRINGS[fan_profile.profile as usize])
-        .to_string();
+        .map(|fan_profile| FAN_STATE_STRINGS[fan_profile.profile as usize]);
 
         match fan_profile_item {
-            FanProfileItem::Profile => DaemonReply::Value { item, value: profile },
+            FanProfileItem::Profile => DaemonReply::from_observed(item, profile),
             FanProfileItem::Icon => DaemonReply::Value {
                 item,
                 value: FanProfile::get_icon(),
@@ -187,23 +190,3 @@ pub async fn evaluate_item(
         }
     })
 }
-
-#[must_use]
-pub const fn match_get_commands(commands: &FanProfileGetCommands) -> DaemonMessage {
-    DaemonMessage::Get {
-        item: match commands {
-            FanProfileGetCommands::Profile => DaemonItem::FanProfile(FanProfileItem::Profile),
-            FanProfileGetCommands::Icon => DaemonItem::FanProfile(FanProfileItem::Icon),
-        },
-    }
-}
-
-#[must_use]
-pub fn match_set_commands(commands: FanProfileSetCommands) -> DaemonMessage {
-    match commands {
-        FanProfileSetCommands::Profile { value } => DaemonMessage::Set {
-            item: DaemonItem::FanProfile(FanProfileItem::Profile),
-            value,
-        },
-    }
-}
@@ -0,0 +1,309 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use tokio::sync::{Notify, mpsc};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    config::get_config,
+    error::DaemonError,
+    history::memory::{HISTORY, HistoryEntry},
+    tuples::TupleName,
+};
+
+const HISTORY_DIR: &str = "bar_daemon";
+const HISTORY_NAME: &str = "history";
+
+/// Changes waiting to be written by the history writer (`None` when the history isn't written to a file)
+static PENDING: Mutex<Option<mpsc::UnboundedSender<(TupleName, HistoryEntry)>>> = Mutex::new(None);
+
+/// # Documentation
+/// The history file used when the config doesn't give one.
+/// This is `$XDG_STATE_HOME/bar_daemon/history`, or `~/.local/state/bar_daemon/history` when `$XDG_STATE_HOME` isn't set
+#[must_use]
+pub fn default_history_path() -> PathBuf {
+    let state_dir = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| PathBuf::from(home).join(".local/state"))
+        })
+        .unwrap_or_else(std::env::temp_dir);
+
+    state_dir.join(HISTORY_DIR).join(HISTORY_NAME)
+}
+
+/// # Documentation
+/// The history file from the config, or the default history file
+#[must_use]
+pub fn history_path() -> PathBuf {
+    get_config().history.path.unwrap_or_else(default_history_path)
+}
+
+fn path_error(path: &Path, e: &std::io::Error) -> DaemonError {
+    DaemonError::PathRwError(format!("{}: {e}", path.display()))
+}
+
+/// # Documentation
+/// Read the changes in the history file at `path`, which is a postcard COBS frame for each change.
+/// Frames which can't be decoded (e.g. one which was partly written when the daemon was killed) are skipped
+///
+/// # Errors
+/// Returns an error if the file exists but can't be read
+#[instrument]
+pub fn read_history_file(path: &Path) -> Result<Vec<(TupleName, HistoryEntry)>, DaemonError> {
+    let mut bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(path_error(path, &e)),
+    };
+
+    let mut skipped = 0;
+    let entries = bytes
+        .split_mut(|byte| *byte == 0)
+        .filter(|frame| !frame.is_empty())
+        .filter_map(|frame| postcard::from_bytes_cobs(frame).inspect_err(|_| skipped += 1).ok())
+        .collect();
+
+    if skipped > 0 {
+        warn!("Skipped {skipped} changes in {} which could not be decoded", path.display());
+    }
+
+    Ok(entries)
+}
+
+/// # Documentation
+/// Replace the history file at `path` with `entries`, keeping the previous file as `<path>.1`.
+/// Returns the size of the new file
+///
+/// # Errors
+/// Returns an error if the directory of `path` can't be created
+/// Returns an error if a change can't be serialized
+/// Returns an error if the new file can't be written, or can't replace the previous file
+pub fn write_history_file(path: &Path, entries: &[(TupleName, HistoryEntry)]) -> Result<u64, DaemonError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| path_error(dir, &e))?;
+    }
+
+    let mut bytes = Vec::new();
+    for entry in entries {
+        bytes.extend(postcard::to_stdvec_cobs(entry)?);
+    }
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let mut previous = path.as_os_str().to_owned();
+    previous.push(".1");
+
+    fs::write(&temporary, &bytes).map_err(|e| path_error(path, &e))?;
+    match fs::rename(path, &previous) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(path_error(path, &e)),
+        _ => {}
+    }
+    fs::rename(&temporary, path).map_err(|e| path_error(path, &e))?;
+
+    Ok(bytes.len() as u64)
+}
+
+/// # Documentation
+/// Add the changes in the history file at `path` to the history, returning how many of its changes are still kept
+///
+/// # Errors
+/// Returns an error if the file exists but can't be read
+pub fn restore_history(path: &Path) -> Result<usize, DaemonError> {
+    let entries = read_history_file(path)?;
+    let mut history = HISTORY.lock().unwrap_or_else(PoisonError::into_inner);
+
+    history.restore(entries, SystemTime::now());
+
+    Ok(history.entries().count())
+}
+
+/// # Documentation
+/// Queue a change which was added to the history, to be written by the history writer (If it is running)
+pub(super) fn persist(module: TupleName, entry: HistoryEntry) {
+    if let Some(pending) = PENDING.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+        // Drop since only returns Err when the writer has stopped
+        let _ = pending.send((module, entry));
+    }
+}
+
+/// # Documentation
+/// The history file which changes are appended to
+struct HistoryFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    compact_at: u64,
+}
+
+impl HistoryFile {
+    /// # Documentation
+    /// Replace the file at `path` with only the changes which are kept, and open it to append to.
+    /// The file is next compacted once it is `max_file_size`, or twice its compacted size if that is larger
+    fn compact(path: PathBuf, max_file_size: u64) -> Result<Self, DaemonError> {
+        // Copy the changes, so the history isn't locked while the file is written.
+        // Changes which were queued before the copy are appended again, but repeated values are removed when restored
+        let entries = HISTORY
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entries()
+            .map(|(module, entry)| (module, entry.clone()))
+            .collect::<Vec<_>>();
+
+        let size = write_history_file(&path, &entries)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| path_error(&path, &e))?;
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            compact_at: max_file_size.max(size.saturating_mul(2)),
+        })
+    }
+
+    /// # Documentation
+    /// Append a change, compacting the file once it has grown too large
+    fn append(&mut self, change: &(TupleName, HistoryEntry), max_file_size: u64) -> Result<(), DaemonError> {
+        let frame = postcard::to_stdvec_cobs(change)?;
+
+        self.file.write_all(&frame).map_err(|e| path_error(&self.path, &e))?;
+        self.size += frame.len() as u64;
+
+        if self.size > self.compact_at {
+            info!("Compacting history file {}", self.path.display());
+            *self = Self::compact(self.path.clone(), max_file_size)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// # Documentation
+/// Restore the history from the file at `path`, then write each change which is added to the history to it until shutdown.
+/// Changes are queued and written by this task, so updating a value never waits for the file
+pub fn spawn_history_writer(path: PathBuf, max_file_size: u64, shutdown_notify: Arc<Notify>) {
+    // The file isn't written when it can't be read, so the changes in it aren't lost
+    match restore_history(&path) {
+        Ok(restored) => info!("Restored {restored} changes from {}", path.display()),
+        Err(e) => {
+            warn!("History could not be restored, and will not be written: {e}");
+            return;
+        }
+    }
+
+    // Changes which were restored, but are no longer kept, are removed from the file first
+    let mut file = match HistoryFile::compact(path, max_file_size) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("History will not be written: {e}");
+            return;
+        }
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    *PENDING.lock().unwrap_or_else(PoisonError::into_inner) = Some(tx);
+
+    tokio::spawn(async move {
+        info!("Writing history to {}", file.path.display());
+
+        loop {
+            tokio::select! {
+                Some(change) = rx.recv() => {
+                    if let Err(e) = file.append(&change, max_file_size) {
+                        warn!("Change could not be written to the history file: {e}");
+                    }
+                }
+                () = shutdown_notify.notified() => break,
+            }
+        }
+
+        // Stop queueing changes, then write those which were queued before shutdown
+        PENDING.lock().unwrap_or_else(PoisonError::into_inner).take();
+        while let Ok(change) = rx.try_recv() {
+            if let Err(e) = file.append(&change, max_file_size) {
+                warn!("Change could not be written to the history file: {e}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{read_history_file, write_history_file};
+    use crate::{
+        history::{History, HistoryEntry},
+        module_value::ModuleValue,
+        tuples::TupleName,
+        volume::Volume,
+    };
+
+    fn volume_entry(time: SystemTime, percent: u32) -> (TupleName, HistoryEntry) {
+        (
+            TupleName::Volume,
+            HistoryEntry {
+                time,
+                sequence: u64::from(percent),
+                value: Ok(ModuleValue::Volume(Volume { percent, mute: false })),
+            },
+        )
+    }
+
+    #[test]
+    fn history_file_is_restored() {
+        let dir = std::env::temp_dir().join(format!("bar_daemon_history_test_{}", std::process::id()));
+        let path = dir.join("history");
+        let now = SystemTime::now();
+
+        let entries = [
+            volume_entry(now - Duration::from_hours(3), 10),
+            volume_entry(now - Duration::from_mins(2), 20),
+            volume_entry(now - Duration::from_mins(1), 30),
+        ];
+        let size = write_history_file(&path, &entries).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(fs::metadata(&path).map(|metadata| metadata.len()).ok(), Some(size));
+
+        // A frame which was partly written is skipped, and the other changes are kept
+        let mut bytes = fs::read(&path).unwrap_or_default();
+        bytes.extend(&postcard::to_stdvec_cobs(&volume_entry(now, 40)).unwrap_or_default()[..5]);
+        fs::write(&path, bytes).unwrap_or_else(|e| panic!("{e}"));
+
+        let read = read_history_file(&path).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(read, entries);
+
+        // Changes which are too old are not restored
+        let mut history = History::new(Duration::from_hours(1), 100);
+        history.restore(read, now);
+        let restored = history.entries().map(|(_, entry)| entry.sequence).collect::<Vec<_>>();
+        assert_eq!(restored, [20, 30]);
+
+        // The previous file is kept when the file is replaced
+        let _ = write_history_file(&path, &entries[1..]).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(read_history_file(&dir.join("history.1")).map(|read| read.len()).ok(), Some(3));
+        assert_eq!(read_history_file(&path).map(|read| read.len()).ok(), Some(2));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn missing_history_file_is_empty() {
+        let path = std::env::temp_dir().join("bar_daemon_history_test_missing");
+
+        assert_eq!(read_history_file(&path).ok(), Some(Vec::new()));
+    }
+}
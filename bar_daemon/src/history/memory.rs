@@ -0,0 +1,294 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{LazyLock, Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery::Battery,
+    bluetooth::Bluetooth,
+    brightness::Brightness,
+    config::get_config,
+    fan_profile::FanProfile,
+    history::file::persist,
+    module_value::{ModuleState, ModuleValue},
+    observed::UnavailableReason,
+    ram::Ram,
+    snapshot::SnapshotEvent,
+    tuples::{TUPLE_NAMES, ToTuples, TupleName},
+    volume::Volume,
+};
+
+/// # Documentation
+/// A module's value after one of its changes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// When the change was made
+    pub time: SystemTime,
+    /// Sequence number of the change (See `Snapshot::sequence`), which starts again when the daemon is restarted
+    pub sequence: u64,
+    pub value: Result<ModuleValue, UnavailableReason>,
+}
+
+/// # Documentation
+/// The recent changes of every module, oldest first, which are removed once they are older than `max_age` or there are more than `max_entries`
+#[derive(Debug, Clone)]
+pub struct History {
+    modules: [VecDeque<HistoryEntry>; TUPLE_NAMES.len()],
+    max_age: Duration,
+    max_entries: usize,
+}
+
+impl History {
+    #[must_use]
+    pub fn new(max_age: Duration, max_entries: usize) -> Self {
+        Self {
+            modules: Default::default(),
+            max_age,
+            max_entries,
+        }
+    }
+
+    /// # Documentation
+    /// Add a change to `module`'s history, unless its value is the same as the last change (e.g. when the value only became stale).
+    /// Returns whether the change was added
+    pub fn push(&mut self, module: TupleName, entry: HistoryEntry) -> bool {
+        let now = entry.time;
+        let entries = &mut self.modules[module as usize];
+
+        if entries.back().is_some_and(|last| last.value == entry.value) {
+            return false;
+        }
+        entries.push_back(entry);
+
+        self.prune(module, now);
+
+        true
+    }
+
+    /// # Documentation
+    /// Remove the oldest changes of `module` which are older than `max_age` at `now`, or past `max_entries`
+    pub fn prune(&mut self, module: TupleName, now: SystemTime) {
+        let entries = &mut self.modules[module as usize];
+
+        while entries.len() > self.max_entries
+            || entries
+                .front()
+                .is_some_and(|oldest| now.duration_since(oldest.time).is_ok_and(|age| age > self.max_age))
+        {
+            entries.pop_front();
+        }
+    }
+
+    /// # Documentation
+    /// Add changes which were kept before (e.g. by a previous daemon), removing those which are no longer kept at `now`
+    pub fn restore(&mut self, entries: impl IntoIterator<Item = (TupleName, HistoryEntry)>, now: SystemTime) {
+        for (module, entry) in entries {
+            self.push(module, entry);
+        }
+
+        for module in (0..TUPLE_NAMES.len()).filter_map(|index| TupleName::try_from(index).ok()) {
+            self.prune(module, now);
+        }
+    }
+
+    /// # Documentation
+    /// Every kept change, with the module it belongs to
+    pub fn entries(&self) -> impl Iterator<Item = (TupleName, &HistoryEntry)> {
+        self.modules
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entries)| Some((TupleName::try_from(index).ok()?, entries)))
+            .flat_map(|(module, entries)| entries.iter().map(move |entry| (module, entry)))
+    }
+
+    /// # Documentation
+    /// The changes of `module` which were made at or after `since`, oldest first
+    #[must_use]
+    pub fn since(&self, module: TupleName, since: SystemTime) -> Vec<HistoryEntry> {
+        self.modules[module as usize]
+            .iter()
+            .filter(|entry| entry.time >= since)
+            .cloned()
+            .collect()
+    }
+}
+
+pub(super) static HISTORY: LazyLock<Mutex<History>> = LazyLock::new(|| {
+    let config = get_config().history;
+
+    Mutex::new(History::new(Duration::from_millis(config.max_age), config.max_entries))
+});
+
+/// # Documentation
+/// Add the change in `event` to the history of the module which changed
+pub fn record_event(event: &SnapshotEvent) {
+    let state = ModuleState::from(event.clone());
+    let entry = HistoryEntry {
+        time: SystemTime::now(),
+        sequence: state.sequence,
+        value: state.value,
+    };
+
+    let added = HISTORY
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(state.module, entry.clone());
+
+    // Only changes which were kept are written to the history file
+    if added {
+        persist(state.module, entry);
+    }
+}
+
+/// # Documentation
+/// The changes of `module` within the last `since` (Every kept change when `since` is `None`), oldest first
+#[must_use]
+pub fn module_history(module: TupleName, since: Option<Duration>) -> Vec<HistoryEntry> {
+    let since = since
+        .and_then(|since| SystemTime::now().checked_sub(since))
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    HISTORY.lock().unwrap_or_else(PoisonError::into_inner).since(module, since)
+}
+
+/// # Documentation
+/// Parse a duration such as `90s`, `15m`, `1h`, or `2d` (A number without a unit is in seconds)
+///
+/// # Errors
+/// Returns an error if the number or unit can't be parsed
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (number, unit) = age.split_at(split);
+
+    let number = number.parse::<u64>().map_err(|e| format!("Invalid duration '{age}': {e}"))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        other => return Err(format!("Invalid unit '{other}' in duration '{age}', use s, m, h, or d")),
+    };
+
+    Ok(Duration::from_secs(number.saturating_mul(seconds)))
+}
+
+/// # Documentation
+/// Format `entries` of `module` as CSV, with the time (In seconds since the Unix epoch) then the value's tuples (`?` when unavailable)
+#[must_use]
+pub fn history_csv(module: TupleName, entries: &[HistoryEntry]) -> String {
+    let names = match module {
+        TupleName::Volume => Volume::to_tuple_names(),
+        TupleName::Brightness => Brightness::to_tuple_names(),
+        TupleName::Bluetooth => Bluetooth::to_tuple_names(),
+        TupleName::Battery => Battery::to_tuple_names(),
+        TupleName::Ram => Ram::to_tuple_names(),
+        TupleName::FanProfile => FanProfile::to_tuple_names(),
+    };
+
+    let mut csv = format!("time,{}\n", names.join(","));
+
+    for entry in entries {
+        let time = entry
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0.0, |since_epoch| since_epoch.as_secs_f64());
+        let values = match &entry.value {
+            Ok(value) => value.to_tuples().into_iter().map(|(_, value)| value).collect(),
+            Err(_) => vec![String::from("?"); names.len()],
+        };
+
+        let _ = writeln!(csv, "{time:.3},{}", values.join(","));
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{History, HistoryEntry, history_csv, parse_age};
+    use crate::{
+        module_value::ModuleValue,
+        observed::{UnavailableKind, UnavailableReason},
+        ram::Ram,
+        tuples::TupleName,
+    };
+
+    fn ram_entry(time: SystemTime, used: u64) -> HistoryEntry {
+        HistoryEntry {
+            time,
+            sequence: used,
+            value: Ok(ModuleValue::Ram(Ram {
+                total: 100,
+                used,
+                percent: used as u32,
+            })),
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut history = History::new(Duration::from_secs(100), 3);
+
+        for used in 0..5 {
+            history.push(TupleName::Ram, ram_entry(start + Duration::from_secs(used), used));
+        }
+        let kept = history.since(TupleName::Ram, SystemTime::UNIX_EPOCH);
+        assert_eq!(kept.iter().map(|entry| entry.sequence).collect::<Vec<_>>(), [2, 3, 4]);
+
+        // Entries older than max_age are removed when a new entry is added
+        history.push(TupleName::Ram, ram_entry(start + Duration::from_secs(103), 10));
+        let kept = history.since(TupleName::Ram, SystemTime::UNIX_EPOCH);
+        assert_eq!(kept.iter().map(|entry| entry.sequence).collect::<Vec<_>>(), [3, 4, 10]);
+
+        // Repeated values aren't kept, and other modules are separate
+        assert!(!history.push(TupleName::Ram, ram_entry(start + Duration::from_secs(104), 10)));
+        assert_eq!(history.since(TupleName::Ram, SystemTime::UNIX_EPOCH).len(), 3);
+        assert!(history.since(TupleName::Battery, SystemTime::UNIX_EPOCH).is_empty());
+
+        assert_eq!(history.since(TupleName::Ram, start + Duration::from_secs(4)).len(), 2);
+    }
+
+    #[test]
+    fn ages_are_parsed() {
+        assert_eq!(parse_age("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_age("15m"), Ok(Duration::from_mins(15)));
+        assert_eq!(parse_age("1h"), Ok(Duration::from_hours(1)));
+        assert_eq!(parse_age("2d"), Ok(Duration::from_hours(48)));
+        assert!(parse_age("1w").is_err());
+        assert!(parse_age("h").is_err());
+    }
+
+    #[test]
+    fn csv_has_a_column_per_tuple() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_500);
+        let entries = [
+            ram_entry(time, 25),
+            HistoryEntry {
+                time,
+                sequence: 26,
+                value: Err(UnavailableReason::new(UnavailableKind::CommandMissing, "free")),
+            },
+        ];
+
+        let csv = history_csv(TupleName::Ram, &entries);
+        let lines = csv.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3, "{csv}");
+        assert!(lines[0].starts_with("time,"), "{csv}");
+        assert!(lines[1].starts_with("1.500,"), "{csv}");
+        assert!(lines[2].ends_with(",?"), "{csv}");
+        assert!(
+            lines
+                .iter()
+                .all(|line| line.split(',').count() == lines[0].split(',').count()),
+            "{csv}"
+        );
+    }
+}
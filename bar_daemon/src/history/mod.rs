@@ -0,0 +1,7 @@
+pub use file::{
+    default_history_path, history_path, read_history_file, restore_history, spawn_history_writer, write_history_file,
+};
+pub use memory::{History, HistoryEntry, history_csv, module_history, parse_age, record_event};
+
+mod file;
+mod memory;
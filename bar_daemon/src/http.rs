@@ -0,0 +1,724 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, UnixListener},
+    sync::{Notify, broadcast::error::RecvError},
+    time::timeout,
+};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    battery::BatteryItem,
+    bluetooth::BluetoothItem,
+    brightness::BrightnessItem,
+    config::HttpConfig,
+    daemon::match_set_command,
+    error::{DaemonError, ErrorCode},
+    fan_profile::FanProfileItem,
+    json::tuples_to_json,
+    listener::SharedClients,
+    metrics::gather_metrics,
+    protocol::DaemonItem,
+    ram::RamItem,
+    snapshot::subscribe_snapshot,
+    socket::{bind_socket, is_same_user},
+    tuples::{TUPLE_NAMES, TupleName, get_all_tuples, tuple_name_to_tuples},
+    volume::VolumeItem,
+};
+
+/// Largest request line and headers which are accepted
+const MAX_HEAD_SIZE: u64 = 8192;
+/// Most headers which are accepted in one request
+const MAX_HEADERS: usize = 64;
+/// Time a client has to send a whole request, so stalled connections don't stay open
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest request body which is accepted
+const MAX_BODY_SIZE: usize = 1024;
+/// Time between comments sent on idle event streams, so disconnected clients are noticed
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Hosts which requests may be sent to, besides the address which the server listens on
+const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
+
+/// # Documentation
+/// Listener for the HTTP server, on either a TCP address or a Unix socket
+#[derive(Debug)]
+pub enum HttpListener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+/// # Documentation
+/// Bind the HTTP server's listener to the address in `config` (`host:port`, or `unix:<path>`)
+///
+/// # Errors
+/// Returns an error if the address is `host:port` and `config` has no token, since any local process could use the server
+/// Returns an error if the listener can't be bound
+#[instrument]
+pub async fn bind_http(config: &HttpConfig) -> Result<HttpListener, DaemonError> {
+    Ok(match config.address.strip_prefix("unix:") {
+        Some(path) => HttpListener::Unix(bind_socket(Path::new(path))?, PathBuf::from(path)),
+        None if config.token.is_none() => return Err(DaemonError::HttpTokenMissing(config.address.clone())),
+        None => HttpListener::Tcp(TcpListener::bind(&config.address).await?),
+    })
+}
+
+/// # Documentation
+/// Spawn a task which serves HTTP requests on `listener` until shutdown, requiring `token` if it is given
+pub fn spawn_http_server(listener: HttpListener, token: Option<String>, clients: SharedClients, shutdown_notify: Arc<Notify>) {
+    let token: Option<Arc<str>> = token.map(Arc::from);
+
+    tokio::spawn(async move {
+        info!("HTTP server listening on {listener:?}");
+
+        loop {
+            tokio::select! {
+                accept_result = accept(&listener, token.as_ref(), &clients, &shutdown_notify) => {
+                    if let Err(e) = accept_result {
+                        warn!("HTTP connection could not be accepted: {e}");
+                    }
+                }
+                () = shutdown_notify.notified() => break,
+            }
+        }
+
+        if let HttpListener::Unix(_, path) = &listener {
+            let _ = std::fs::remove_file(path);
+        }
+
+        info!("HTTP server shutdown successfuly");
+    });
+}
+
+async fn accept(
+    listener: &HttpListener,
+    token: Option<&Arc<str>>,
+    clients: &SharedClients,
+    shutdown_notify: &Arc<Notify>,
+) -> Result<(), DaemonError> {
+    match listener {
+        HttpListener::Tcp(listener) => {
+            let (stream, _) = listener.accept().await?;
+            let bound = stream.local_addr().ok();
+            spawn_connection(stream, bound, token.cloned(), clients.clone(), shutdown_notify.clone());
+        }
+        HttpListener::Unix(listener, _) => {
+            let (stream, _) = listener.accept().await?;
+
+            // Only the user running the daemon may use it
+            if !is_same_user(&stream) {
+                warn!("Refused HTTP connection from a client belonging to another user");
+                return Ok(());
+            }
+
+            spawn_connection(stream, None, token.cloned(), clients.clone(), shutdown_notify.clone());
+        }
+    }
+
+    Ok(())
+}
+
+fn spawn_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    stream: S,
+    bound: Option<SocketAddr>,
+    token: Option<Arc<str>>,
+    clients: SharedClients,
+    shutdown_notify: Arc<Notify>,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = handle_connection(stream, bound, token.as_deref(), &clients, shutdown_notify).await {
+            debug!("HTTP connection closed: {e}");
+        }
+    });
+}
+
+/// # Documentation
+/// A parsed HTTP request (Header names are lowercase)
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+/// # Documentation
+/// A response to a request, which is JSON unless it is the metrics
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    const fn ok(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    const fn metrics(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+
+    fn error(status: u16, code: &str, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: json!({ "code": code, "message": message }).to_string(),
+        }
+    }
+
+    fn from_error(e: &DaemonError) -> Self {
+        let code = ErrorCode::from(e);
+        let status = match code {
+            ErrorCode::InvalidMessage | ErrorCode::InvalidValue => 400,
+            ErrorCode::NoKnownValue => 409,
+            ErrorCode::CommandMissing | ErrorCode::CommandFailed | ErrorCode::DeviceAbsent => 503,
+            ErrorCode::Internal | ErrorCode::DaemonUnreachable => 500,
+        };
+
+        Self::error(status, &code.to_string(), &e.to_string())
+    }
+}
+
+/// # Documentation
+/// What a request asks for
+#[derive(Debug)]
+pub enum Route {
+    AllModules,
+    Module(TupleName),
+    Set(DaemonItem),
+    Events,
+    /// Prometheus metrics of the values and the daemon's health
+    Metrics,
+}
+
+/// # Documentation
+/// Answer one request on `stream`, where `bound` is the TCP address which the request was sent to (`None` for a Unix socket)
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    bound: Option<SocketAddr>,
+    token: Option<&str>,
+    clients: &SharedClients,
+    shutdown_notify: Arc<Notify>,
+) -> Result<(), DaemonError> {
+    let mut stream = BufReader::new(stream);
+
+    let response = match timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Err(_) => Response::error(408, "RequestTimeout", "The request wasn't received in time"),
+        Ok(Ok(request)) if !is_local_request(&request, bound) => {
+            warn!("Refused HTTP request from a browser, or to another host");
+            Response::error(403, "Forbidden", "Requests from browsers, or to other hosts, are refused")
+        }
+        Ok(Ok(request)) if !is_authorized(&request, token) => Response::error(401, "Unauthorized", "A valid token is required"),
+        Ok(Ok(request)) => match route(&request.method, &request.path) {
+            Ok(Route::Events) => return stream_events(&mut stream, &shutdown_notify).await,
+            Ok(Route::Metrics) => Response::metrics(gather_metrics(clients).await),
+            Ok(route) => respond(route, &request.body).await,
+            Err(response) => response,
+        },
+        Ok(Err(DaemonError::ConnectionClosed)) => return Ok(()),
+        Ok(Err(e)) => Response::error(400, "BadRequest", &e.to_string()),
+    };
+
+    write_response(&mut stream, &response).await
+}
+
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut BufReader<S>) -> Result<Request, DaemonError> {
+    // Read the request line and headers, up to the blank line which ends them
+    let mut head = String::new();
+    let mut head_reader = (&mut *stream).take(MAX_HEAD_SIZE);
+    let mut header_count = 0;
+
+    loop {
+        if head_reader.read_line(&mut head).await? == 0 {
+            return if head.is_empty() {
+                Err(DaemonError::ConnectionClosed)
+            } else {
+                Err(DaemonError::ParseError(String::from(
+                    "Request head is incomplete or too large",
+                )))
+            };
+        }
+
+        if head.ends_with("\r\n\r\n") || head.ends_with("\n\n") {
+            break;
+        }
+
+        // The first line is the request line, every later one is a header
+        header_count += 1;
+        if header_count > MAX_HEADERS + 1 {
+            return Err(DaemonError::ParseError(format!(
+                "Request has more than {MAX_HEADERS} headers"
+            )));
+        }
+    }
+
+    let mut request = parse_head(&head)?;
+
+    let content_length = match request.headers.get("content-length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|e| DaemonError::ParseError(format!("Content-Length '{length}': {e}")))?,
+        None => 0,
+    };
+
+    if content_length > MAX_BODY_SIZE {
+        return Err(DaemonError::ParseError(format!(
+            "Request body is larger than {MAX_BODY_SIZE} bytes"
+        )));
+    }
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await?;
+    request.body = String::from_utf8(body).map_err(|e| DaemonError::ParseError(e.to_string()))?;
+
+    Ok(request)
+}
+
+/// # Documentation
+/// Parse the request line and headers of a request
+///
+/// # Errors
+/// Returns an error if the request line isn't `METHOD TARGET VERSION`
+pub fn parse_head(head: &str) -> Result<Request, DaemonError> {
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(DaemonError::ParseError(format!("Invalid request line '{request_line}'")));
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        headers: lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect(),
+        body: String::new(),
+    })
+}
+
+/// # Documentation
+/// Check that the request wasn't sent by a website, so it can't be forged (CSRF) or sent after rebinding a name to this address.
+/// Requests with an `Origin` header are refused, since browsers send it, and the `Host` must be a local host or `bound`
+#[must_use]
+pub fn is_local_request(request: &Request, bound: Option<SocketAddr>) -> bool {
+    if request.headers.contains_key("origin") {
+        return false;
+    }
+
+    let Some(host) = request.headers.get("host") else {
+        // Browsers always send the host, but other clients of a Unix socket needn't
+        return bound.is_none();
+    };
+
+    // Remove the port, which is after the brackets of an IPv6 address
+    let name = match host.split_once(']') {
+        Some((address, _)) => &host[..=address.len()],
+        None => host.split_once(':').map_or(host.as_str(), |(name, _)| name),
+    };
+
+    LOCAL_HOSTS.iter().any(|local| name.eq_ignore_ascii_case(local))
+        || bound.is_some_and(|bound| *host == bound.to_string() || name == bound.ip().to_string())
+}
+
+/// # Documentation
+/// Check that the request gives the token, as `Authorization: Bearer <token>` or `?token=<token>` (For `EventSource`, which can't set headers)
+#[must_use]
+pub fn is_authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+
+    let given = request
+        .headers
+        .get("authorization")
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .or_else(|| request.query.get("token").map(String::as_str));
+
+    // Compare every byte, so the time taken doesn't reveal how much of the token was correct
+    given.is_some_and(|given| {
+        given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    })
+}
+
+/// # Documentation
+/// Find what a request asks for from its method and path
+///
+/// # Errors
+/// Returns a 404 response if nothing is at the path, or a 405 response if the method can't be used for the path
+pub fn route(method: &str, path: &str) -> Result<Route, Response> {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+    let (route, allowed_method) = match segments.as_slice() {
+        ["modules"] => (Some(Route::AllModules), "GET"),
+        ["modules", module] => (parse_module(module).map(Route::Module), "GET"),
+        ["modules", module, item] => (
+            parse_module(module)
+                .and_then(|module| parse_item(module, item))
+                .map(Route::Set),
+            "POST",
+        ),
+        ["events"] => (Some(Route::Events), "GET"),
+        ["metrics"] => (Some(Route::Metrics), "GET"),
+        _ => (None, "GET"),
+    };
+
+    match route {
+        Some(_) if method != allowed_method => Err(Response::error(
+            405,
+            "MethodNotAllowed",
+            &format!("Use {allowed_method} for {path}"),
+        )),
+        Some(route) => Ok(route),
+        None => Err(Response::error(404, "NotFound", &format!("Nothing at {path}"))),
+    }
+}
+
+fn parse_module(module: &str) -> Option<TupleName> {
+    module.parse().ok()
+}
+
+fn parse_item(module: TupleName, item: &str) -> Option<DaemonItem> {
+    Some(match (module, item) {
+        (TupleName::Volume, "percent") => DaemonItem::Volume(VolumeItem::Percent),
+        (TupleName::Volume, "mute") => DaemonItem::Volume(VolumeItem::Mute),
+        (TupleName::Brightness, "monitor") => DaemonItem::Brightness(BrightnessItem::Monitor),
+        (TupleName::Brightness, "keyboard") => DaemonItem::Brightness(BrightnessItem::Keyboard),
+        (TupleName::Bluetooth, "state") => DaemonItem::Bluetooth(BluetoothItem::State),
+        (TupleName::Battery, "state") => DaemonItem::Battery(BatteryItem::State),
+        (TupleName::Battery, "percent") => DaemonItem::Battery(BatteryItem::Percent),
+        (TupleName::Battery, "time") => DaemonItem::Battery(BatteryItem::Time),
+        (TupleName::Ram, "total") => DaemonItem::Ram(RamItem::Total),
+        (TupleName::Ram, "used") => DaemonItem::Ram(RamItem::Used),
+        (TupleName::Ram, "percent") => DaemonItem::Ram(RamItem::Percent),
+        (TupleName::FanProfile, "profile") => DaemonItem::FanProfile(FanProfileItem::Profile),
+        _ => return None,
+    })
+}
+
+async fn respond(route: Route, body: &str) -> Response {
+    let result = match route {
+        Route::AllModules => get_all_tuples().await.and_then(tuples_to_json),
+        Route::Module(tuple_name) => tuple_name_to_tuples(&tuple_name)
+            .await
+            .and_then(|tuples| Ok(serde_json::to_string(&tuples.into_iter().collect::<HashMap<_, _>>())?)),
+        Route::Set(item) => {
+            // An empty body toggles values which can be toggled, like `bar_daemon set volume mute`
+            let value = match body.trim() {
+                "" => String::from("toggle"),
+                value => value.to_string(),
+            };
+
+            match_set_command(item, value.clone())
+                .await
+                .map(|_| json!({ "value": value }).to_string())
+        }
+        Route::Events | Route::Metrics => Err(DaemonError::ParseError(String::from(
+            "Events and metrics aren't JSON responses",
+        ))),
+    };
+
+    match result {
+        Ok(body) => Response::ok(body),
+        Err(e) => {
+            warn!("HTTP request failed: {e}");
+            Response::from_error(&e)
+        }
+    }
+}
+
+async fn write_response<S: AsyncWrite + Unpin>(stream: &mut S, response: &Response) -> Result<(), DaemonError> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+
+    let authenticate = if response.status == 401 {
+        "WWW-Authenticate: Bearer\r\n"
+    } else {
+        ""
+    };
+
+    let head = format!(
+        "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{authenticate}Connection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// # Documentation
+/// Stream Server-Sent Events to the client, starting with a `snapshot` event of all modules, then an event named after each module which changes
+async fn stream_events<S: AsyncWrite + Unpin>(stream: &mut S, shutdown_notify: &Notify) -> Result<(), DaemonError> {
+    // Subscribe before reading the snapshot, so no changes are missed in between
+    let mut snapshot_rx = subscribe_snapshot();
+
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .await?;
+    write_event(stream, "snapshot", &tuples_to_json(get_all_tuples().await?)?).await?;
+
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            event = snapshot_rx.recv() => match event {
+                Ok(event) => {
+                    let (tuple_name, tuples) = event.into_tuples();
+                    let name = TUPLE_NAMES[tuple_name as usize];
+
+                    write_event(stream, name, &tuples_to_json(vec![(name.to_string(), tuples)])?).await?;
+                }
+                Err(RecvError::Lagged(n)) => {
+                    // Events were missed, so send every module again
+                    warn!("HTTP event stream missed {n} snapshot events");
+                    write_event(stream, "snapshot", &tuples_to_json(get_all_tuples().await?)?).await?;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = keep_alive.tick() => {
+                stream.write_all(b": keep-alive\n\n").await?;
+                stream.flush().await?;
+            }
+            () = shutdown_notify.notified() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn write_event<S: AsyncWrite + Unpin>(stream: &mut S, name: &str, data: &str) -> Result<(), DaemonError> {
+    stream
+        .write_all(format!("event: {name}\ndata: {data}\n\n").as_bytes())
+        .await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::{Mutex, Notify},
+    };
+
+    use super::{
+        HttpListener, MAX_HEADERS, Route, bind_http, handle_connection, is_authorized, is_local_request, parse_head, route,
+    };
+    use crate::{config::HttpConfig, protocol::DaemonItem, tuples::TupleName, volume::VolumeItem};
+
+    #[test]
+    fn head_is_parsed() {
+        let request =
+            parse_head("POST /modules/volume/percent?token=abc HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n");
+        assert!(request.is_ok(), "{request:?}");
+        let request = request.unwrap_or_default();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/modules/volume/percent");
+        assert_eq!(request.query.get("token").map(String::as_str), Some("abc"));
+        assert_eq!(request.headers.get("content-length").map(String::as_str), Some("2"));
+
+        assert!(parse_head("nonsense\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn token_is_required_when_configured() {
+        let mut request = parse_head("GET /modules HTTP/1.1\r\n\r\n").unwrap_or_default();
+
+        assert!(is_authorized(&request, None));
+        assert!(!is_authorized(&request, Some("secret")));
+
+        request
+            .headers
+            .insert(String::from("authorization"), String::from("Bearer secret"));
+        assert!(is_authorized(&request, Some("secret")));
+        assert!(!is_authorized(&request, Some("secret2")));
+
+        request.headers.clear();
+        request.query.insert(String::from("token"), String::from("secret"));
+        assert!(is_authorized(&request, Some("secret")));
+    }
+
+    #[test]
+    fn requests_from_browsers_and_to_other_hosts_are_refused() {
+        let bound = "192.168.1.2:7878".parse().ok();
+        let with_headers = |headers: &str| parse_head(&format!("GET /modules HTTP/1.1\r\n{headers}\r\n")).unwrap_or_default();
+
+        assert!(is_local_request(&with_headers("Host: localhost:7878\r\n"), bound));
+        assert!(is_local_request(&with_headers("Host: 127.0.0.1\r\n"), bound));
+        assert!(is_local_request(&with_headers("Host: [::1]:7878\r\n"), bound));
+        assert!(is_local_request(&with_headers("Host: 192.168.1.2:7878\r\n"), bound));
+
+        // A name which was rebound to the address, or any request with an origin
+        assert!(!is_local_request(&with_headers("Host: attacker.example:7878\r\n"), bound));
+        assert!(!is_local_request(
+            &with_headers("Host: localhost.attacker.example\r\n"),
+            bound
+        ));
+        assert!(!is_local_request(
+            &with_headers("Host: localhost:7878\r\nOrigin: http://attacker.example\r\n"),
+            bound
+        ));
+        assert!(!is_local_request(&with_headers("Origin: null\r\n"), None));
+
+        // Only clients of a Unix socket may leave out the host
+        assert!(!is_local_request(&with_headers(""), bound));
+        assert!(is_local_request(&with_headers(""), None));
+    }
+
+    #[tokio::test]
+    async fn tcp_address_needs_a_token() {
+        let config = HttpConfig {
+            address: String::from("127.0.0.1:0"),
+            token: None,
+        };
+        assert!(bind_http(&config).await.is_err());
+
+        let config = HttpConfig {
+            token: Some(String::from("secret")),
+            ..config
+        };
+        assert!(matches!(bind_http(&config).await, Ok(HttpListener::Tcp(_))));
+    }
+
+    #[test]
+    fn paths_are_routed() {
+        assert!(matches!(route("GET", "/modules"), Ok(Route::AllModules)));
+        assert!(matches!(
+            route("GET", "/modules/fan_profile"),
+            Ok(Route::Module(TupleName::FanProfile))
+        ));
+        assert!(matches!(
+            route("POST", "/modules/volume/mute"),
+            Ok(Route::Set(DaemonItem::Volume(VolumeItem::Mute)))
+        ));
+        assert!(matches!(route("GET", "/events"), Ok(Route::Events)));
+        assert!(matches!(route("GET", "/metrics"), Ok(Route::Metrics)));
+
+        assert!(matches!(route("POST", "/modules/volume"), Err(response) if response.status == 405));
+        assert!(matches!(route("GET", "/modules/speakers"), Err(response) if response.status == 404));
+        assert!(matches!(route("POST", "/modules/volume/colour"), Err(response) if response.status == 404));
+    }
+
+    async fn request(raw: &str, token: Option<&'static str>) -> String {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(async move {
+            let clients = Arc::new(Mutex::new(HashMap::new()));
+            handle_connection(server, None, token, &clients, Arc::new(Notify::new())).await
+        });
+
+        client.write_all(raw.as_bytes()).await.unwrap_or_else(|e| panic!("{e}"));
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap_or_else(|e| panic!("{e}"));
+        assert!(matches!(handle.await, Ok(Ok(()))));
+
+        response
+    }
+
+    #[tokio::test]
+    async fn errors_are_json_responses() {
+        let response = request("GET /modules HTTP/1.1\r\n\r\n", Some("secret")).await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{response}");
+        assert!(response.contains("WWW-Authenticate: Bearer\r\n"), "{response}");
+
+        let response = request(
+            "GET /nothing HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+            Some("secret"),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{response}");
+
+        // Setting a read-only value is rejected by match_set_command
+        let response = request("POST /modules/ram/used HTTP/1.1\r\nContent-Length: 2\r\n\r\n10", None).await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+        assert!(response.contains(r#""code":"InvalidMessage""#), "{response}");
+
+        // Browsers are refused even with the token
+        let response = request(
+            "GET /modules HTTP/1.1\r\nHost: localhost\r\nOrigin: http://attacker.example\r\nAuthorization: Bearer secret\r\n\r\n",
+            Some("secret"),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{response}");
+        assert!(response.contains(r#""code":"Forbidden""#), "{response}");
+
+        let response = request("GET /modules HTTP/1.1\r\nHost: attacker.example\r\n\r\n", None).await;
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{response}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_and_oversized_requests_are_refused() {
+        // The blank line ending the head is never sent
+        let response = request("GET /modules HTTP/1.1\r\n", None).await;
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "{response}");
+        assert!(response.contains(r#""code":"RequestTimeout""#), "{response}");
+
+        let headers = "X-Padding: 1\r\n".repeat(MAX_HEADERS + 1);
+        let response = request(&format!("GET /modules HTTP/1.1\r\n{headers}\r\n"), None).await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+        assert!(response.contains("more than 64 headers"), "{response}");
+
+        let headers = "X-Padding: 1\r\n".repeat(MAX_HEADERS);
+        let response = request(&format!("GET /modules HTTP/1.1\r\n{headers}\r\n"), None).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn metrics_are_plain_text() {
+        let response = request("GET /metrics HTTP/1.1\r\n\r\n", None).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"), "{response}");
+        assert!(
+            response.contains("# TYPE bar_daemon_listeners gauge\nbar_daemon_listeners 0\n"),
+            "{response}"
+        );
+    }
+}
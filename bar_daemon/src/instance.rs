@@ -0,0 +1,132 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{net::UnixStream, time::Instant};
+use tracing::{info, instrument};
+
+use crate::{
+    daemon::send_daemon_message_to,
+    error::DaemonError,
+    protocol::{DaemonMessage, DaemonReply},
+    socket::create_socket_dir,
+};
+
+/// Time given to a replaced daemon to shut down
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(10);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// # Documentation
+/// Lock on the pidfile which is held while a daemon is using the socket, so only one daemon can use each socket
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// # Documentation
+    /// Lock the pidfile and write this process's id to it, returns `None` if another process has it locked
+    ///
+    /// # Errors
+    /// Returns an error if the pidfile can't be opened, locked or written to
+    pub fn try_acquire(pidfile_path: &Path) -> Result<Option<Self>, DaemonError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(pidfile_path)
+            .map_err(|e| DaemonError::PathRwError(format!("{}: {e}", pidfile_path.display())))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => return Err(DaemonError::PathRwError(format!("{}: {e}", pidfile_path.display()))),
+        }
+
+        // Only truncate once the lock is held, so the pid of the running daemon is never removed
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+
+        Ok(Some(Self { _file: file }))
+    }
+}
+
+/// # Documentation
+/// The pidfile which is locked by the daemon using `socket_path`
+#[must_use]
+pub fn pidfile_path(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("pid")
+}
+
+/// # Documentation
+/// Make sure no other daemon is using `socket_path`, returning the lock which keeps it that way.
+/// If `replace` is set, a running daemon is asked to shut down, otherwise it is left running
+///
+/// # Errors
+/// Returns an error if another daemon is using the socket (and `replace` isn't set, or it didn't shut down in time)
+/// Returns an error if the pidfile can't be used
+#[instrument]
+pub async fn take_over_socket(socket_path: &Path, replace: bool) -> Result<InstanceLock, DaemonError> {
+    // A daemon is running if something accepts connections on the socket
+    if UnixStream::connect(socket_path).await.is_ok() {
+        if !replace {
+            return Err(DaemonError::AlreadyRunning(socket_path.display().to_string()));
+        }
+
+        info!("Asking the running daemon to shut down");
+
+        match send_daemon_message_to(socket_path, DaemonMessage::Shutdown).await? {
+            DaemonReply::ShuttingDown => {}
+            reply => return Err(DaemonError::ParseError(format!("{reply:?}"))),
+        }
+    }
+
+    // Wait for the lock, the replaced daemon releases it once it has shut down
+    create_socket_dir(socket_path)?;
+    let start = Instant::now();
+    let pidfile_path = pidfile_path(socket_path);
+
+    loop {
+        if let Some(lock) = InstanceLock::try_acquire(&pidfile_path)? {
+            return Ok(lock);
+        }
+
+        if !replace || start.elapsed() > REPLACE_TIMEOUT {
+            return Err(DaemonError::AlreadyRunning(socket_path.display().to_string()));
+        }
+
+        tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::InstanceLock;
+
+    #[test]
+    fn lock_is_exclusive_until_dropped() {
+        let pidfile_path = std::env::temp_dir().join(format!("bar_daemon_lock_test_{}.pid", std::process::id()));
+
+        let lock = InstanceLock::try_acquire(&pidfile_path);
+        assert!(matches!(lock, Ok(Some(_))), "{lock:?}");
+        assert_eq!(
+            fs::read_to_string(&pidfile_path).ok(),
+            Some(format!("{}\n", std::process::id()))
+        );
+
+        assert!(matches!(InstanceLock::try_acquire(&pidfile_path), Ok(None)));
+
+        drop(lock);
+        assert!(matches!(InstanceLock::try_acquire(&pidfile_path), Ok(Some(_))));
+
+        let _ = fs::remove_file(&pidfile_path);
+    }
+}
@@ -0,0 +1,65 @@
+#![warn(clippy::all)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::nursery)]
+#![warn(clippy::unwrap_used)]
+#![warn(clippy::expect_used)]
+#![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::cast_precision_loss)]
+#![allow(clippy::cast_sign_loss)]
+#![allow(clippy::option_if_let_else)]
+#![allow(clippy::similar_names)]
+#![allow(clippy::implicit_hasher)]
+
+// TODO Implement dbus events for polled values
+
+pub mod backoff;
+pub mod battery;
+pub mod bluetooth;
+pub mod brightness;
+pub mod changed;
+#[cfg(feature = "daemon")]
+pub mod cli;
+pub mod command;
+pub mod config;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+#[cfg(feature = "daemon")]
+pub mod dbus_listener;
+#[cfg(feature = "daemon")]
+pub mod dbus_service;
+#[cfg(feature = "daemon")]
+pub mod doctor;
+pub mod error;
+pub mod fan_profile;
+pub mod history;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "daemon")]
+pub mod instance;
+pub mod json;
+pub mod listener;
+pub mod log_linear;
+#[cfg(feature = "daemon")]
+pub mod logging;
+pub mod metrics;
+pub mod module_value;
+pub mod monitored;
+pub mod notification;
+pub mod observed;
+pub mod polled;
+pub mod protocol;
+pub mod ram;
+pub mod shutdown;
+pub mod simulate;
+pub mod snapshot;
+pub mod socket;
+pub mod status;
+pub mod systemd;
+pub mod trigger;
+pub mod tuples;
+pub mod volume;
+
+pub const ICON_END: &str = "-symbolic";
+pub const ICON_EXT: &str = ""; // ".svg"
+
+pub const NOTIFICATION_ID: u32 = 42069;
@@ -1,36 +1,224 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::{
+        Arc, PoisonError,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
 
+use serde::{Deserialize, Serialize};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
     net::UnixStream,
-    sync::{Mutex, Notify, broadcast},
+    sync::{Mutex, Notify, broadcast, broadcast::error::RecvError},
+    task::JoinHandle,
 };
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 use uuid::Uuid;
 
 use crate::{
-    daemon::{DaemonMessage, SOCKET_PATH},
+    backoff::{Backoff, BackoffPolicy},
+    config::{ListenerConfig, SlowListenerPolicy, get_config},
     error::DaemonError,
     json::tuples_to_json,
-    snapshot::SnapshotEvent,
-    tuples::{TUPLE_NAMES, TupleName, get_all_tuples},
+    module_value::ModuleState,
+    observed::{UnavailableKind, UnavailableReason},
+    protocol::{DaemonMessage, write_message},
+    snapshot::{Snapshot, SnapshotEvent, current_snapshot},
+    socket::socket_path,
+    tuples::TUPLE_NAMES,
 };
 
+/// A frame written to listeners, which is shared by every client it is queued for
+pub type Frame = Arc<[u8]>;
+
+/// # Documentation
+/// A listener, whose frames are written to its stream by its own task so one slow listener can't hold up the others
 #[derive(Debug)]
 pub struct Client {
     pub id: Uuid,
-    pub stream: UnixStream,
+    pub format: ClientFormat,
+    queue: Arc<ClientQueue>,
+    writer: JoinHandle<()>,
+}
+
+impl Client {
+    /// # Documentation
+    /// Create a client which writes `initial`, then every frame queued for it, to `stream`
+    #[must_use]
+    pub fn spawn(stream: UnixStream, format: ClientFormat, initial: Frame) -> Self {
+        let id = Uuid::new_v4();
+        let queue = Arc::new(ClientQueue::default());
+        queue.frames.lock().unwrap_or_else(PoisonError::into_inner).push_back(initial);
+
+        let writer = tokio::spawn(write_queued(id, stream, queue.clone()));
+
+        Self {
+            id,
+            format,
+            queue,
+            writer,
+        }
+    }
+
+    /// # Documentation
+    /// Whether the client's stream can still be written to
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        !self.queue.closed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // Stop writing, which closes the stream
+        self.writer.abort();
+    }
+}
+
+/// Frames waiting to be written to a client
+#[derive(Debug, Default)]
+struct ClientQueue {
+    frames: std::sync::Mutex<VecDeque<Frame>>,
+    ready: Notify,
+    closed: AtomicBool,
+}
+
+/// What happened when a frame was queued for a client
+#[derive(Debug, PartialEq, Eq)]
+enum Queued {
+    Added,
+    /// The queue was full, so this many frames were dropped
+    Dropped(usize),
+    /// The queue was full, and the client should be disconnected
+    Full,
+}
+
+impl ClientQueue {
+    /// Queue `frame`, following `policy` if `capacity` frames are already waiting. `latest` is the latest state of every module
+    fn push(&self, frame: &Frame, latest: &Frame, capacity: usize, policy: SlowListenerPolicy) -> Queued {
+        let mut frames = self.frames.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let queued = if frames.len() < capacity.max(1) {
+            frames.push_back(frame.clone());
+            Queued::Added
+        } else {
+            match policy {
+                SlowListenerPolicy::Latest => {
+                    // The latest state includes this frame, and replaces every frame which is waiting
+                    let dropped = frames.len();
+                    frames.clear();
+                    frames.push_back(latest.clone());
+                    Queued::Dropped(dropped)
+                }
+                SlowListenerPolicy::DropOldest => {
+                    frames.pop_front();
+                    frames.push_back(frame.clone());
+                    Queued::Dropped(1)
+                }
+                SlowListenerPolicy::Disconnect => return Queued::Full,
+            }
+        };
+
+        drop(frames);
+        self.ready.notify_one();
+
+        queued
+    }
+}
+
+/// Write the frames queued for a client until its stream can't be written to
+async fn write_queued(id: Uuid, mut stream: UnixStream, queue: Arc<ClientQueue>) {
+    loop {
+        let frame = queue.frames.lock().unwrap_or_else(PoisonError::into_inner).pop_front();
+
+        match frame {
+            Some(frame) => {
+                if let Err(e) = stream.write_all(&frame).await {
+                    info!("Client {id} disconnected: {e}");
+                    break;
+                }
+            }
+            None => queue.ready.notified().await,
+        }
+    }
+
+    queue.closed.store(true, Ordering::Relaxed);
 }
 
+static DROPPED_FRAMES: AtomicU64 = AtomicU64::new(0);
+static SLOW_DISCONNECTS: AtomicU64 = AtomicU64::new(0);
+static LAGGED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// # Documentation
+/// Counts of listeners which fell behind since the daemon started
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerMetrics {
+    /// Frames which were dropped because a listener's queue was full
+    pub dropped_frames: u64,
+    /// Listeners which were disconnected because their queue was full
+    pub slow_disconnects: u64,
+    /// Snapshot events which the client handler missed because it fell behind the broadcast, after which every module was sent again
+    pub lagged_events: u64,
+}
+
+#[must_use]
+pub fn listener_metrics() -> ListenerMetrics {
+    ListenerMetrics {
+        dropped_frames: DROPPED_FRAMES.load(Ordering::Relaxed),
+        slow_disconnects: SLOW_DISCONNECTS.load(Ordering::Relaxed),
+        lagged_events: LAGGED_EVENTS.load(Ordering::Relaxed),
+    }
+}
+
+/// # Documentation
+/// How changes are written to a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientFormat {
+    /// A line of JSON tuples of every module (`DaemonMessage::Listen`)
+    Json,
+    /// A postcard COBS frame of the `ModuleState` which changed (`DaemonMessage::Subscribe`)
+    Frames,
+}
+
+/// Delays between attempts to reconnect to the daemon after it stops
+const RECONNECT_POLICY: BackoffPolicy = BackoffPolicy {
+    initial_delay: 250,
+    multiplier: 2.0,
+    max_delay: 10_000,
+    jitter: 0.1,
+};
+
 /// # Errors
-/// Returns an error if ``SOCKET_PATH`` cannot be found
-/// Returns an error if ``UnixListener`` cannot be bound
-/// Returns an error if ``DaemonMessage`` could not be created from bytes
-/// Returns an error if socket cannot be read
-/// Returns an error if socket could not be wrote to
+/// Returns an error if the JSON tuples cannot be created
+/// Returns an error if stdout could not be wrote to
 #[instrument]
 pub async fn listen() -> Result<(), DaemonError> {
-    match listen_inner().await {
+    listen_to(&socket_path()).await
+}
+
+/// # Documentation
+/// Listen to the daemon at `socket_path`, writing the JSON tuples to stdout whenever they change.
+/// If the daemon isn't running, or stops, every value is written as `?` and the daemon is reconnected to when it starts again
+///
+/// # Errors
+/// Returns an error if the JSON tuples cannot be created
+/// Returns an error if stdout could not be wrote to
+#[instrument]
+pub async fn listen_to(socket_path: &Path) -> Result<(), DaemonError> {
+    listen_to_writer(socket_path, &mut tokio::io::stdout()).await
+}
+
+/// # Documentation
+/// Listen to the daemon at `socket_path`, writing a line of JSON tuples to `writer` whenever they change (See `listen_to()`)
+///
+/// # Errors
+/// Returns an error if the JSON tuples cannot be created
+/// Returns an error if `writer` could not be wrote to
+#[instrument(skip(writer))]
+pub async fn listen_to_writer<W: AsyncWrite + Unpin>(socket_path: &Path, writer: &mut W) -> Result<(), DaemonError> {
+    match listen_inner(socket_path, writer).await {
         Ok(()) => Ok(()),
         Err(e) => {
             error!("{e}");
@@ -39,36 +227,155 @@ pub async fn listen() -> Result<(), DaemonError> {
     }
 }
 
-async fn listen_inner() -> Result<(), DaemonError> {
-    if !Path::new(SOCKET_PATH).exists() {
-        error!("Socket not found ('{SOCKET_PATH}'). Is the daemon running?");
-        return Err(DaemonError::PathRwError(SOCKET_PATH.to_string()));
+async fn listen_inner<W: AsyncWrite + Unpin>(socket_path: &Path, writer: &mut W) -> Result<(), DaemonError> {
+    let mut backoff = Backoff::new(RECONNECT_POLICY);
+    let mut unavailable_written = false;
+
+    loop {
+        let mut connected = false;
+
+        match follow_daemon(socket_path, writer, &mut connected).await {
+            Ok(()) => warn!("Daemon closed the connection, reconnecting"),
+            // Only errors from the daemon's side are recovered from
+            Err(e @ (DaemonError::JsonError(_) | DaemonError::OutputError(_))) => return Err(e),
+            Err(e) if connected => warn!("Connection to daemon lost, reconnecting: {e}"),
+            Err(e) if !unavailable_written => {
+                warn!(
+                    "Could not connect to the daemon at '{}', retrying: {e}",
+                    socket_path.display()
+                );
+            }
+            Err(e) => debug!("Could not connect to the daemon: {e}"),
+        }
+
+        if connected {
+            backoff.reset();
+            unavailable_written = false;
+        }
+
+        // Show that values are unknown until the daemon is back, instead of leaving the last values
+        if !unavailable_written {
+            write_line(writer, &unavailable_json()?).await?;
+            unavailable_written = true;
+        }
+
+        tokio::time::sleep(backoff.next_delay()).await;
     }
+}
 
-    let mut stream = UnixStream::connect(SOCKET_PATH).await?;
+/// Connect to the daemon, then write the full state which it sends followed by each change, until the connection is closed
+async fn follow_daemon<W: AsyncWrite + Unpin>(
+    socket_path: &Path,
+    writer: &mut W,
+    connected: &mut bool,
+) -> Result<(), DaemonError> {
+    let mut stream = UnixStream::connect(socket_path).await?;
 
     // Tell the daemon that this client wants to listen
-    stream.write_all(&postcard::to_stdvec(&DaemonMessage::Listen)?).await?;
+    write_message(&mut stream, &DaemonMessage::Listen).await?;
 
-    // Get the initial tuples, as JSON, and write to stdout
-    let json = tuples_to_json(get_all_tuples().await?)?;
-    println!("{json}");
+    // Read the lines which the daemon sends, starting with its current state
+    let mut lines = BufReader::new(stream).lines();
 
-    // Read the lines which the client sends
-    let reader = BufReader::new(stream);
-    let mut lines = reader.lines();
-
-    while let Ok(Some(line)) = lines.next_line().await {
-        println!("{line}");
+    while let Some(line) = lines.next_line().await? {
+        write_line(writer, &line).await?;
+        *connected = true;
     }
 
     Ok(())
 }
 
+async fn write_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> Result<(), DaemonError> {
+    let write = async {
+        writer.write_all(line.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+        writer.flush().await
+    };
+
+    write.await.map_err(DaemonError::OutputError)
+}
+
+/// # Documentation
+/// The JSON tuples which the daemon sends to a new listener, with a `_frame` describing the snapshot's `sequence`
+///
+/// # Errors
+/// Returns an error if the tuples can't be converted to JSON
+pub fn snapshot_json(snapshot: Snapshot) -> Result<String, DaemonError> {
+    let sequence = snapshot.sequence;
+
+    let mut tuples = snapshot.into_tuples();
+    tuples.push(frame_tuples("snapshot", Some(sequence)));
+
+    tuples_to_json(tuples)
+}
+
+/// # Documentation
+/// The JSON tuples written by listeners while the daemon can't be reached, where every value is `?`
+///
+/// # Errors
+/// Returns an error if the tuples can't be converted to JSON
+pub fn unavailable_json() -> Result<String, DaemonError> {
+    let reason = UnavailableReason::new(UnavailableKind::DaemonUnavailable, "The daemon is not running");
+
+    let mut tuples = Snapshot::unavailable(&reason).into_tuples();
+    tuples.push(frame_tuples("unavailable", None));
+
+    tuples_to_json(tuples)
+}
+
+/// Describes what kind of frame the JSON is, and the sequence number of the last change which it includes
+fn frame_tuples(kind: &str, sequence: Option<u64>) -> (String, Vec<(String, String)>) {
+    let mut tuples = vec![(String::from("kind"), kind.to_string())];
+
+    if let Some(sequence) = sequence {
+        tuples.push((String::from("sequence"), sequence.to_string()));
+    }
+
+    (String::from("_frame"), tuples)
+}
+
 pub type SharedClients = Arc<Mutex<HashMap<Uuid, Client>>>;
 
+/// The latest state of every module, kept by the client handler to create each frame
+struct ListenerState {
+    tuples: Vec<(String, Vec<(String, String)>)>,
+    state_frames: Vec<Frame>,
+    /// Sequence number of the last change which the state includes
+    sequence: u64,
+}
+
+impl ListenerState {
+    fn from_snapshot(snapshot: Snapshot) -> Result<Self, DaemonError> {
+        let sequence = snapshot.sequence;
+
+        let state_frames = ModuleState::all_from_snapshot(snapshot.clone())
+            .iter()
+            .map(|state| Ok(Frame::from(postcard::to_stdvec_cobs(state)?)))
+            .collect::<Result<Vec<_>, DaemonError>>()?;
+
+        Ok(Self {
+            tuples: snapshot.into_tuples(),
+            state_frames,
+            sequence,
+        })
+    }
+
+    /// The JSON tuples of every module, with a `_frame` of the given `kind`
+    fn json_frame(&self, kind: &str) -> Result<Frame, DaemonError> {
+        let mut tuples = self.tuples.clone();
+        tuples.push(frame_tuples(kind, Some(self.sequence)));
+
+        Ok((tuples_to_json(tuples)? + "\n").into_bytes().into())
+    }
+
+    /// The `ModuleState` frames of every module
+    fn states_frame(&self) -> Frame {
+        self.state_frames.concat().into()
+    }
+}
+
 /// # Errors
-/// Returns an error if ``SOCKET_PATH`` cannot be found
+/// Returns an error if the socket path cannot be found
 /// Returns an error if ``UnixListener`` cannot be bound
 /// Returns an error if ``DaemonMessage`` could not be created from bytes
 /// Returns an error if socket cannot be read
@@ -79,50 +386,45 @@ pub async fn handle_clients(
     snapshot_rx: &mut broadcast::Receiver<SnapshotEvent>,
     shutdown_notify: Arc<Notify>,
 ) -> Result<(), DaemonError> {
-    let tuples = Mutex::new(get_all_tuples().await?);
+    let config = get_config().listeners;
+    let mut state = ListenerState::from_snapshot(current_snapshot().await)?;
 
     loop {
         tokio::select! {
-            Ok(event)= snapshot_rx.recv() => {
-                debug!("SnapshotEvent Received: {event:?}");
-
-                let (index, new_tuples) = match event {
-                    SnapshotEvent::Battery(update) => (TupleName::Battery as usize, update.new.to_tuples()),
-                    SnapshotEvent::Bluetooth(update) => (TupleName::Bluetooth as usize, update.new.to_tuples()),
-                    SnapshotEvent::Brightness(update) => (TupleName::Brightness as usize, update.new.to_tuples()),
-                    SnapshotEvent::FanProfile(update) => (TupleName::FanProfile as usize, update.new.to_tuples()),
-                    SnapshotEvent::Ram(update) => (TupleName::Ram as usize, update.new.to_tuples()),
-                    SnapshotEvent::Volume(update) => (TupleName::Volume as usize, update.new.to_tuples()),
-                };
-
-                // Convert the updated tuples to JSON
-                let json = tuples_to_json({
-                   // Update the inner of the tuples Mutex
-                   let mut tuples_guard = tuples.lock().await;
-                   (*tuples_guard)[index] = (
-                        TUPLE_NAMES[index].to_string(),
-                        new_tuples,
-                    );
-
-                   tuples_guard.clone()
-                })? + "\n";
-
-                // Broadcast to each client
-                let mut to_remove = vec![];
-                for (id, client) in clients.lock().await.iter_mut() {
-                    if client.stream.try_write(json.as_bytes()).is_err() {
-                        info!("Client {id} disconnected");
-                        to_remove.push(*id);
+            event = snapshot_rx.recv() => match event {
+                Ok(event) => {
+                    debug!("SnapshotEvent Received: {event:?}");
+
+                    // Skip changes which were already included when the state was read again
+                    if event.sequence() <= state.sequence {
+                        continue;
                     }
-                }
+                    state.sequence = event.sequence();
+
+                    // Subscribers are sent the typed state of the module which changed
+                    let frame = Frame::from(postcard::to_stdvec_cobs(&ModuleState::from(event.clone()))?);
 
-                // Remove dead clients
-                for id in to_remove {
-                    clients.lock().await.remove(&id);
-                    info!("Client {id} removed");
+                    let (tuple_name, new_tuples) = event.into_tuples();
+                    let index = tuple_name as usize;
+                    state.state_frames[index] = frame.clone();
+                    state.tuples[index] = (TUPLE_NAMES[index].to_string(), new_tuples);
+
+                    // Every JSON frame is the latest state of every module
+                    let json = state.json_frame("update")?;
+                    queue_frames(&mut *clients.lock().await, &json, &frame, &state.states_frame(), &config);
                 }
+                Err(RecvError::Lagged(missed)) => {
+                    // Changes were missed, so every module is read from the snapshot and sent again
+                    warn!("Client handler missed {missed} snapshot events, sending every module to listeners again");
+                    LAGGED_EVENTS.fetch_add(missed, Ordering::Relaxed);
 
-            }
+                    state = ListenerState::from_snapshot(current_snapshot().await)?;
+
+                    let states = state.states_frame();
+                    queue_frames(&mut *clients.lock().await, &state.json_frame("resync")?, &states, &states, &config);
+                }
+                Err(RecvError::Closed) => break,
+            },
 
             () = shutdown_notify.notified() => {
                 info!("Client handler received shutdown notification");
@@ -135,3 +437,186 @@ pub async fn handle_clients(
 
     Ok(())
 }
+
+/// Queue the frame in each client's format, removing clients which have disconnected or can't keep up
+fn queue_frames(
+    clients: &mut HashMap<Uuid, Client>,
+    json: &Frame,
+    frame: &Frame,
+    latest_frames: &Frame,
+    config: &ListenerConfig,
+) {
+    let mut to_remove = vec![];
+
+    for (id, client) in clients.iter() {
+        if !client.is_connected() {
+            to_remove.push(*id);
+            continue;
+        }
+
+        let queued = match client.format {
+            ClientFormat::Json => client.queue.push(json, json, config.queue_size, config.slow_listener),
+            ClientFormat::Frames => client
+                .queue
+                .push(frame, latest_frames, config.queue_size, config.slow_listener),
+        };
+
+        match queued {
+            Queued::Added => {}
+            Queued::Dropped(dropped) => {
+                debug!("Client {id} is falling behind, dropped {dropped} frames");
+                DROPPED_FRAMES.fetch_add(dropped as u64, Ordering::Relaxed);
+            }
+            Queued::Full => {
+                warn!("Client {id} is not keeping up with changes, disconnecting");
+                SLOW_DISCONNECTS.fetch_add(1, Ordering::Relaxed);
+                to_remove.push(*id);
+            }
+        }
+    }
+
+    // Remove dead clients
+    for id in to_remove {
+        clients.remove(&id);
+        info!("Client {id} removed");
+    }
+}
+
+/// # Documentation
+/// A frame of the state of every module in `states`, as sent to a new subscriber
+///
+/// # Errors
+/// Returns an error if a state can't be serialized
+pub fn states_frame(states: &[ModuleState]) -> Result<Frame, DaemonError> {
+    let mut bytes = vec![];
+    for state in states {
+        bytes.extend(postcard::to_stdvec_cobs(state)?);
+    }
+
+    Ok(bytes.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader, Lines},
+        net::UnixStream,
+        sync::{Mutex, Notify, broadcast},
+    };
+
+    use super::{Client, ClientFormat, ClientQueue, Frame, Queued, handle_clients, listener_metrics};
+    use crate::{
+        config::SlowListenerPolicy,
+        monitored::MonitoredUpdate,
+        observed::Observed,
+        snapshot::{ModuleTimestamps, SnapshotEvent},
+    };
+
+    fn push(queue: &ClientQueue, frame: u8, policy: SlowListenerPolicy) -> Queued {
+        queue.push(&Frame::from([frame]), &Frame::from([0]), 2, policy)
+    }
+
+    fn waiting(queue: &ClientQueue) -> Vec<u8> {
+        queue
+            .frames
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .map(|frame| frame[0])
+            .collect()
+    }
+
+    #[test]
+    fn full_queue_follows_policy() {
+        let latest = ClientQueue::default();
+        assert_eq!(push(&latest, 1, SlowListenerPolicy::Latest), Queued::Added);
+        assert_eq!(push(&latest, 2, SlowListenerPolicy::Latest), Queued::Added);
+        assert_eq!(push(&latest, 3, SlowListenerPolicy::Latest), Queued::Dropped(2));
+        assert_eq!(waiting(&latest), [0]);
+
+        let drop_oldest = ClientQueue::default();
+        for frame in 1..=3 {
+            let _ = push(&drop_oldest, frame, SlowListenerPolicy::DropOldest);
+        }
+        assert_eq!(waiting(&drop_oldest), [2, 3]);
+
+        let disconnect = ClientQueue::default();
+        for frame in 1..=2 {
+            let _ = push(&disconnect, frame, SlowListenerPolicy::Disconnect);
+        }
+        assert_eq!(push(&disconnect, 3, SlowListenerPolicy::Disconnect), Queued::Full);
+        assert_eq!(waiting(&disconnect), [1, 2]);
+    }
+
+    fn ram_event(sequence: u64) -> SnapshotEvent {
+        SnapshotEvent::Ram(MonitoredUpdate {
+            old: Observed::Recovering,
+            new: Observed::Recovering,
+            sequence,
+            timestamps: ModuleTimestamps::default(),
+        })
+    }
+
+    async fn next_frame(lines: &mut Lines<BufReader<UnixStream>>) -> serde_json::Value {
+        let line = match tokio::time::timeout(Duration::from_secs(5), lines.next_line()).await {
+            Ok(Ok(Some(line))) => line,
+            result => panic!("Listener stopped receiving frames: {result:?}"),
+        };
+
+        serde_json::from_str(&line).unwrap_or_else(|e| panic!("Frame is not JSON ({e}): {line}"))
+    }
+
+    fn sequence_of(frame: &serde_json::Value) -> u64 {
+        match frame["_frame"]["sequence"].as_str().map(str::parse) {
+            Some(Ok(sequence)) => sequence,
+            _ => panic!("Frame has no sequence number: {frame}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn lagged_handler_resyncs_listeners() {
+        let (tx, mut rx) = broadcast::channel(64);
+        let (stream, listener) = UnixStream::pair().unwrap_or_else(|e| panic!("{e}"));
+        let mut lines = BufReader::new(listener).lines();
+
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let client = Client::spawn(stream, ClientFormat::Json, Frame::from(*b"{}\n"));
+        clients.lock().await.insert(client.id, client);
+
+        // Send more events than the broadcast holds before the handler reads any of them.
+        // They are older than the snapshot, so the handler skips those still in the broadcast after it resyncs
+        let lagged_before = listener_metrics().lagged_events;
+        for _ in 0..100 {
+            let _ = tx.send(ram_event(0));
+        }
+
+        let shutdown_notify = Arc::new(Notify::new());
+        let handler = tokio::spawn({
+            let shutdown_notify = shutdown_notify.clone();
+            async move { handle_clients(clients, &mut rx, shutdown_notify).await }
+        });
+
+        assert_eq!(next_frame(&mut lines).await, serde_json::json!({}));
+
+        // Listeners are sent every module again
+        let resync = next_frame(&mut lines).await;
+        assert_eq!(resync["_frame"]["kind"], "resync", "{resync}");
+        assert!(listener_metrics().lagged_events >= lagged_before + 36);
+
+        // Changes after the resync are sent as updates, in order
+        let mut sequence = sequence_of(&resync);
+        for offset in 1..=3 {
+            let _ = tx.send(ram_event(sequence_of(&resync) + offset));
+
+            let frame = next_frame(&mut lines).await;
+            assert_eq!(frame["_frame"]["kind"], "update", "{frame}");
+            assert!(sequence_of(&frame) > sequence, "{frame} doesn't follow {sequence}");
+            sequence = sequence_of(&frame);
+        }
+
+        shutdown_notify.notify_one();
+        assert!(matches!(handler.await, Ok(Ok(()))));
+    }
+}
@@ -3,57 +3,36 @@
 #![warn(clippy::nursery)]
 #![warn(clippy::unwrap_used)]
 #![warn(clippy::expect_used)]
-#![allow(clippy::cast_possible_truncation)]
-#![allow(clippy::cast_precision_loss)]
-#![allow(clippy::cast_sign_loss)]
-#![allow(clippy::option_if_let_else)]
-#![allow(clippy::similar_names)]
-#![allow(clippy::implicit_hasher)]
 
-use crate::{cli::evaluate_cli, error::DaemonError, logging::init_logging};
+use std::process::ExitCode;
 
-// TODO Implement some kind of "last-known value" system for the 'set' functions (currently uses unwrap_or_default)
-// TODO Call read_until_valid() again after a long wait if the maximum number of attempts have been reached.
-// TODO Implement dbus events for polled values
+use bar_daemon::{
+    cli::evaluate_cli,
+    error::{DaemonError, ErrorCode},
+    logging::init_logging,
+    systemd::SystemdEnv,
+};
 
-pub mod battery;
-pub mod bluetooth;
-pub mod brightness;
-pub mod changed;
-pub mod cli;
-pub mod command;
-pub mod config;
-pub mod daemon;
-pub mod dbus_listener;
-pub mod error;
-pub mod fan_profile;
-pub mod json;
-pub mod listener;
-pub mod log_linear;
-pub mod logging;
-pub mod monitored;
-pub mod notification;
-pub mod observed;
-pub mod polled;
-pub mod ram;
-pub mod shutdown;
-pub mod snapshot;
-pub mod trigger;
-pub mod tuples;
-pub mod volume;
+fn main() -> ExitCode {
+    // SAFETY: The runtime hasn't been built yet, so this is the only thread
+    let systemd = unsafe { SystemdEnv::take() };
 
-pub const ICON_END: &str = "-symbolic";
-pub const ICON_EXT: &str = ""; // ".svg"
-
-pub const NOTIFICATION_ID: u32 = 42069;
-
-#[tokio::main]
-async fn main() -> Result<(), DaemonError> {
     // Start the logging process
     init_logging();
 
-    // Evaluate cli commands
-    evaluate_cli().await?;
-
-    Ok(())
+    // Evaluate cli commands, exiting with a code which describes the error if there was one
+    let result = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(DaemonError::from)
+        .and_then(|runtime| runtime.block_on(evaluate_cli(systemd)));
+
+    match result {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("Error: {e}");
+
+            ExitCode::from(ErrorCode::from(&e).exit_code())
+        }
+    }
 }
@@ -0,0 +1,457 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{
+    error::{DaemonError, ErrorCode},
+    listener::SharedClients,
+    observed::Observed::Valid,
+    snapshot::{Snapshot, current_snapshot},
+    status::{DaemonStatus, daemon_status},
+    tuples::TUPLE_NAMES,
+};
+
+/// # Documentation
+/// Reads of a module's source since the daemon started
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReadStats {
+    pub reads: u64,
+    /// Reads which didn't give a `Valid` value
+    pub failures: u64,
+    /// Total time taken by every read
+    pub seconds: f64,
+}
+
+/// Counters of the daemon's health since it started, which are exported alongside the values
+#[derive(Debug, Default)]
+struct Counters {
+    reads: BTreeMap<&'static str, ReadStats>,
+    /// Attempts made by `read_until_valid` for each module
+    retry_attempts: BTreeMap<&'static str, u64>,
+    /// Times which `read_until_valid` ran out of quick attempts for each module (`MonitoredReadAttemptFail`)
+    read_attempt_fails: BTreeMap<&'static str, u64>,
+    /// Failed commands, by command name and `ErrorCode`
+    command_failures: BTreeMap<(String, ErrorCode), u64>,
+}
+
+static COUNTERS: LazyLock<Mutex<Counters>> = LazyLock::new(|| Mutex::new(Counters::default()));
+
+fn with_counters<T>(f: impl FnOnce(&mut Counters) -> T) -> T {
+    f(&mut COUNTERS.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// # Documentation
+/// Count a read of `module`'s source which took `duration`
+pub fn record_read(module: &'static str, duration: Duration, valid: bool) {
+    with_counters(|counters| {
+        let stats = counters.reads.entry(module).or_default();
+        stats.reads += 1;
+        stats.failures += u64::from(!valid);
+        stats.seconds += duration.as_secs_f64();
+    });
+}
+
+/// # Documentation
+/// Count an attempt by `read_until_valid` to read `module`
+pub fn record_retry_attempt(module: &'static str) {
+    with_counters(|counters| *counters.retry_attempts.entry(module).or_default() += 1);
+}
+
+/// # Documentation
+/// Count `read_until_valid` running out of quick attempts to read `module`
+pub fn record_read_attempt_fail(module: &'static str) {
+    with_counters(|counters| *counters.read_attempt_fails.entry(module).or_default() += 1);
+}
+
+/// # Documentation
+/// Count a command which failed with `e`
+pub fn record_command_failure(command: &str, e: &DaemonError) {
+    with_counters(|counters| {
+        *counters
+            .command_failures
+            .entry((command.to_string(), ErrorCode::from(e)))
+            .or_default() += 1;
+    });
+}
+
+/// # Documentation
+/// Get the metrics of the current values and the daemon's health, in the Prometheus text format
+pub async fn gather_metrics(clients: &SharedClients) -> String {
+    let status = daemon_status(clients).await;
+
+    render_metrics(&current_snapshot().await, &status)
+}
+
+/// # Documentation
+/// Format the values in `snapshot`, and the daemon's health from `status` and the counters, in the Prometheus text format.
+/// Values which aren't `Valid` are left out, `bar_daemon_module_valid` shows which those are
+#[must_use]
+pub fn render_metrics(snapshot: &Snapshot, status: &DaemonStatus) -> String {
+    let mut out = String::new();
+
+    render_values(&mut out, snapshot);
+    render_module_health(&mut out, status);
+    render_counters(&mut out);
+    render_daemon_health(&mut out, status);
+
+    out
+}
+
+fn render_values(out: &mut String, snapshot: &Snapshot) {
+    if let Valid(volume) = &snapshot.volume {
+        gauge(
+            out,
+            "volume_percent",
+            "Volume of the default sink in percent",
+            f64::from(volume.percent),
+        );
+        gauge(
+            out,
+            "volume_muted",
+            "Whether the default sink is muted",
+            bool_value(volume.mute),
+        );
+    }
+    if let Valid(brightness) = &snapshot.brightness {
+        gauge(
+            out,
+            "brightness_monitor_percent",
+            "Monitor brightness in percent",
+            f64::from(brightness.monitor),
+        );
+        gauge(
+            out,
+            "brightness_keyboard_percent",
+            "Keyboard brightness in percent",
+            f64::from(brightness.keyboard),
+        );
+    }
+    if let Valid(bluetooth) = &snapshot.bluetooth {
+        gauge(
+            out,
+            "bluetooth_enabled",
+            "Whether bluetooth is on",
+            bool_value(bluetooth.state),
+        );
+    }
+    if let Valid(battery) = &snapshot.battery {
+        gauge(
+            out,
+            "battery_percent",
+            "Battery charge in percent",
+            f64::from(battery.percent),
+        );
+        metric(
+            out,
+            "battery_state",
+            "State of the battery (1 for the current state)",
+            "gauge",
+            [(label("state", &battery.state.to_string()), 1.0)],
+        );
+        if let Some(time) = battery.time {
+            gauge(
+                out,
+                "battery_time_remaining_seconds",
+                "Estimated time until the battery is empty or full",
+                time.remaining.as_secs_f64(),
+            );
+            gauge(
+                out,
+                "battery_time_confident",
+                "Whether the time estimate is confident",
+                bool_value(time.confident),
+            );
+        }
+    }
+    if let Valid(ram) = &snapshot.ram {
+        gauge(out, "ram_used_bytes", "RAM in use", ram.used as f64);
+        gauge(out, "ram_total_bytes", "Total RAM", ram.total as f64);
+    }
+    if let Valid(fan_profile) = &snapshot.fan_profile {
+        metric(
+            out,
+            "fan_profile",
+            "Fan profile (1 for the current profile)",
+            "gauge",
+            [(label("profile", &fan_profile.profile.to_string()), 1.0)],
+        );
+    }
+}
+
+fn render_module_health(out: &mut String, status: &DaemonStatus) {
+    let module_label = |module: &crate::status::ModuleStatus| label("module", TUPLE_NAMES[module.module as usize]);
+    metric(
+        out,
+        "module_valid",
+        "Whether the module's value is Valid",
+        "gauge",
+        status
+            .modules
+            .iter()
+            .map(|module| (module_label(module), bool_value(module.state == "Valid"))),
+    );
+    metric(
+        out,
+        "module_stale",
+        "Whether the module's polled value hasn't been read for longer than `stale_after`",
+        "gauge",
+        status
+            .modules
+            .iter()
+            .map(|module| (module_label(module), bool_value(module.timestamps.stale))),
+    );
+}
+
+/// Counters of reads and commands, which are only shown for modules and commands which have been counted
+fn render_counters(out: &mut String) {
+    let (reads, retry_attempts, read_attempt_fails, command_failures) = with_counters(|counters| {
+        (
+            counters.reads.clone(),
+            counters.retry_attempts.clone(),
+            counters.read_attempt_fails.clone(),
+            counters.command_failures.clone(),
+        )
+    });
+
+    // Summary of read durations, without quantiles
+    let _ = writeln!(
+        out,
+        "# HELP bar_daemon_source_read_duration_seconds Time taken to read each module's source"
+    );
+    let _ = writeln!(out, "# TYPE bar_daemon_source_read_duration_seconds summary");
+    for (module, stats) in &reads {
+        let labels = label("module", module);
+        let _ = writeln!(out, "bar_daemon_source_read_duration_seconds_sum{labels} {}", stats.seconds);
+        let _ = writeln!(out, "bar_daemon_source_read_duration_seconds_count{labels} {}", stats.reads);
+    }
+    metric(
+        out,
+        "source_read_failures_total",
+        "Reads of each module's source which didn't give a Valid value",
+        "counter",
+        reads
+            .iter()
+            .map(|(module, stats)| (label("module", module), stats.failures as f64)),
+    );
+    metric(
+        out,
+        "read_until_valid_attempts_total",
+        "Attempts made to read modules which were unavailable",
+        "counter",
+        retry_attempts.iter().map(|(module, n)| (label("module", module), *n as f64)),
+    );
+    metric(
+        out,
+        "read_attempt_failures_total",
+        "Times which a module could not be read after every quick attempt, and was marked as Failed",
+        "counter",
+        read_attempt_fails
+            .iter()
+            .map(|(module, n)| (label("module", module), *n as f64)),
+    );
+    metric(
+        out,
+        "command_failures_total",
+        "Commands which failed, by the category of error",
+        "counter",
+        command_failures
+            .iter()
+            .map(|((command, code), n)| (format!("{{command=\"{}\",code=\"{code}\"}}", escape(command)), *n as f64)),
+    );
+}
+
+fn render_daemon_health(out: &mut String, status: &DaemonStatus) {
+    let metrics = &status.listener_metrics;
+    gauge(
+        out,
+        "uptime_seconds",
+        "Time since the daemon started",
+        status.uptime.as_secs_f64(),
+    );
+    gauge(
+        out,
+        "listeners",
+        "Clients connected with `listen` or `subscribe`",
+        status.listeners as f64,
+    );
+    counter(
+        out,
+        "listener_dropped_frames_total",
+        "Frames which were dropped because a listener's queue was full",
+        metrics.dropped_frames,
+    );
+    counter(
+        out,
+        "listener_slow_disconnects_total",
+        "Listeners which were disconnected because their queue was full",
+        metrics.slow_disconnects,
+    );
+    counter(
+        out,
+        "listener_lagged_events_total",
+        "Snapshot events which were missed because listeners fell behind",
+        metrics.lagged_events,
+    );
+}
+
+fn metric(out: &mut String, name: &str, help: &str, kind: &str, samples: impl IntoIterator<Item = (String, f64)>) {
+    let _ = writeln!(out, "# HELP bar_daemon_{name} {help}");
+    let _ = writeln!(out, "# TYPE bar_daemon_{name} {kind}");
+
+    for (labels, value) in samples {
+        let _ = writeln!(out, "bar_daemon_{name}{labels} {value}");
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    metric(out, name, help, "gauge", [(String::new(), value)]);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    metric(out, name, help, "counter", [(String::new(), value as f64)]);
+}
+
+fn label(name: &str, value: &str) -> String {
+    format!("{{{name}=\"{}\"}}", escape(value))
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+const fn bool_value(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+/// # Documentation
+/// Write the metrics to `path` every `interval` until shutdown, for `node_exporter`'s textfile collector
+pub fn spawn_textfile_writer(path: PathBuf, interval: Duration, clients: SharedClients, shutdown_notify: Arc<Notify>) {
+    tokio::spawn(async move {
+        info!("Writing metrics to {}", path.display());
+
+        let mut interval = tokio::time::interval(interval.max(Duration::from_millis(100)));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = write_textfile(&path, &gather_metrics(&clients).await) {
+                        warn!("Metrics could not be written: {e}");
+                    }
+                }
+                () = shutdown_notify.notified() => break,
+            }
+        }
+    });
+}
+
+/// # Documentation
+/// Replace the file at `path` with `metrics`, writing to a temporary file first so a partial file is never collected
+///
+/// # Errors
+/// Returns an error if the temporary file can't be written, or can't replace the file at `path`
+pub fn write_textfile(path: &Path, metrics: &str) -> Result<(), DaemonError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    std::fs::write(&temporary, metrics).map_err(|e| DaemonError::PathRwError(format!("{}: {e}", path.display())))?;
+    std::fs::rename(&temporary, path).map_err(|e| DaemonError::PathRwError(format!("{}: {e}", path.display())))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{record_command_failure, record_read, render_metrics};
+    use crate::{
+        error::DaemonError,
+        listener::ListenerMetrics,
+        observed::{
+            Observed::{Unavailable, Valid},
+            UnavailableKind, UnavailableReason,
+        },
+        ram::Ram,
+        snapshot::Snapshot,
+        status::DaemonStatus,
+        volume::Volume,
+    };
+
+    fn status() -> DaemonStatus {
+        DaemonStatus {
+            version: String::from("0.0.0"),
+            uptime: Duration::from_secs(90),
+            modules: Vec::new(),
+            listeners: 2,
+            listener_metrics: ListenerMetrics {
+                dropped_frames: 0,
+                slow_disconnects: 1,
+                lagged_events: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn valid_values_are_gauges() {
+        let snapshot = Snapshot {
+            volume: Valid(Volume { percent: 40, mute: true }),
+            ram: Valid(Ram {
+                total: 16_000,
+                used: 4_000,
+                percent: 25,
+            }),
+            battery: Unavailable(UnavailableReason::new(UnavailableKind::CommandMissing, "acpi")),
+            ..Snapshot::default()
+        };
+
+        let metrics = render_metrics(&snapshot, &status());
+
+        assert!(
+            metrics.contains("# TYPE bar_daemon_volume_percent gauge\nbar_daemon_volume_percent 40\n"),
+            "{metrics}"
+        );
+        assert!(metrics.contains("bar_daemon_volume_muted 1\n"), "{metrics}");
+        assert!(metrics.contains("bar_daemon_ram_used_bytes 4000\n"), "{metrics}");
+        assert!(metrics.contains("bar_daemon_listeners 2\n"), "{metrics}");
+        assert!(
+            metrics.contains("bar_daemon_listener_slow_disconnects_total 1\n"),
+            "{metrics}"
+        );
+
+        // Values which aren't known are left out, instead of being shown as 0
+        assert!(!metrics.contains("bar_daemon_battery_percent"), "{metrics}");
+    }
+
+    #[test]
+    fn reads_and_failures_are_counted() {
+        record_read("metrics_test", Duration::from_millis(500), true);
+        record_read("metrics_test", Duration::from_millis(250), false);
+        record_command_failure("metrics\"test", &DaemonError::CommandNotFound { name: String::new() });
+
+        let metrics = render_metrics(&Snapshot::default(), &status());
+
+        assert!(
+            metrics.contains("bar_daemon_source_read_duration_seconds_sum{module=\"metrics_test\"} 0.75\n"),
+            "{metrics}"
+        );
+        assert!(
+            metrics.contains("bar_daemon_source_read_duration_seconds_count{module=\"metrics_test\"} 2\n"),
+            "{metrics}"
+        );
+        assert!(
+            metrics.contains("bar_daemon_source_read_failures_total{module=\"metrics_test\"} 1\n"),
+            "{metrics}"
+        );
+        assert!(
+            metrics.contains("bar_daemon_command_failures_total{command=\"metrics\\\"test\",code=\"CommandMissing\"} 1\n"),
+            "{metrics}"
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery::Battery,
+    bluetooth::Bluetooth,
+    brightness::Brightness,
+    error::DaemonError,
+    fan_profile::FanProfile,
+    monitored::Monitored,
+    observed::{
+        Observed::{self, Valid},
+        UnavailableKind, UnavailableReason,
+    },
+    ram::Ram,
+    snapshot::{ModuleTimestamps, Snapshot, SnapshotEvent, current_snapshot},
+    tuples::{ToTuples, TupleName},
+    volume::Volume,
+};
+
+/// # Documentation
+/// The value of one module, for clients which want typed values instead of tuples
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ModuleValue {
+    Volume(Volume),
+    Brightness(Brightness),
+    Bluetooth(Bluetooth),
+    Battery(Battery),
+    Ram(Ram),
+    FanProfile(FanProfile),
+}
+
+impl ModuleValue {
+    /// # Documentation
+    /// The value as tuples, in the same format as `get`
+    #[must_use]
+    pub fn to_tuples(&self) -> Vec<(String, String)> {
+        match self {
+            Self::Volume(volume) => volume.to_tuples(),
+            Self::Brightness(brightness) => brightness.to_tuples(),
+            Self::Bluetooth(bluetooth) => bluetooth.to_tuples(),
+            Self::Battery(battery) => battery.to_tuples(),
+            Self::Ram(ram) => ram.to_tuples(),
+            Self::FanProfile(fan_profile) => fan_profile.to_tuples(),
+        }
+    }
+}
+
+/// # Documentation
+/// The value of a module, or the reason it is unavailable
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ModuleState {
+    pub module: TupleName,
+    pub value: Result<ModuleValue, UnavailableReason>,
+    /// Sequence number of the change which gave this state (See `Snapshot::sequence`), so gaps between states can be found
+    pub sequence: u64,
+    /// When the value was last read, and whether it is stale
+    pub timestamps: ModuleTimestamps,
+}
+
+impl ModuleState {
+    /// # Documentation
+    /// Create the state of `module` from its observed value, using `into_value` to wrap a `Valid` value
+    pub fn from_observed<T>(
+        module: TupleName,
+        sequence: u64,
+        timestamps: ModuleTimestamps,
+        observed: Observed<T>,
+        into_value: fn(T) -> ModuleValue,
+    ) -> Self {
+        let value = match observed {
+            Valid(value) => Ok(into_value(value)),
+            other => Err(other
+                .reason()
+                .cloned()
+                .unwrap_or_else(|| UnavailableReason::new(UnavailableKind::Other, "Value is being read again"))),
+        };
+
+        Self {
+            module,
+            value,
+            sequence,
+            timestamps,
+        }
+    }
+
+    /// # Documentation
+    /// The state of every module in `snapshot`
+    #[must_use]
+    pub fn all_from_snapshot(snapshot: Snapshot) -> Vec<Self> {
+        let sequence = snapshot.sequence;
+        let times = snapshot.timestamps;
+
+        vec![
+            Self::from_observed(
+                TupleName::Volume,
+                sequence,
+                times.volume,
+                snapshot.volume,
+                ModuleValue::Volume,
+            ),
+            Self::from_observed(
+                TupleName::Brightness,
+                sequence,
+                times.brightness,
+                snapshot.brightness,
+                ModuleValue::Brightness,
+            ),
+            Self::from_observed(
+                TupleName::Bluetooth,
+                sequence,
+                times.bluetooth,
+                snapshot.bluetooth,
+                ModuleValue::Bluetooth,
+            ),
+            Self::from_observed(
+                TupleName::Battery,
+                sequence,
+                times.battery,
+                snapshot.battery,
+                ModuleValue::Battery,
+            ),
+            Self::from_observed(TupleName::Ram, sequence, times.ram, snapshot.ram, ModuleValue::Ram),
+            Self::from_observed(
+                TupleName::FanProfile,
+                sequence,
+                times.fan_profile,
+                snapshot.fan_profile,
+                ModuleValue::FanProfile,
+            ),
+        ]
+    }
+}
+
+impl From<SnapshotEvent> for ModuleState {
+    fn from(event: SnapshotEvent) -> Self {
+        let (sequence, timestamps) = (event.sequence(), event.timestamps());
+
+        match event {
+            SnapshotEvent::Volume(update) => {
+                Self::from_observed(TupleName::Volume, sequence, timestamps, update.new, ModuleValue::Volume)
+            }
+            SnapshotEvent::Brightness(update) => Self::from_observed(
+                TupleName::Brightness,
+                sequence,
+                timestamps,
+                update.new,
+                ModuleValue::Brightness,
+            ),
+            SnapshotEvent::Bluetooth(update) => {
+                Self::from_observed(TupleName::Bluetooth, sequence, timestamps, update.new, ModuleValue::Bluetooth)
+            }
+            SnapshotEvent::Battery(update) => {
+                Self::from_observed(TupleName::Battery, sequence, timestamps, update.new, ModuleValue::Battery)
+            }
+            SnapshotEvent::Ram(update) => Self::from_observed(TupleName::Ram, sequence, timestamps, update.new, ModuleValue::Ram),
+            SnapshotEvent::FanProfile(update) => Self::from_observed(
+                TupleName::FanProfile,
+                sequence,
+                timestamps,
+                update.new,
+                ModuleValue::FanProfile,
+            ),
+        }
+    }
+}
+
+/// # Documentation
+/// Get the state of a module, reading it in the same way as `DaemonMessage::Get`
+///
+/// # Errors
+/// Returns an error if the module's value can't be read
+pub async fn module_state(module: TupleName) -> Result<ModuleState, DaemonError> {
+    let snapshot = current_snapshot().await;
+    let sequence = snapshot.sequence;
+    let times = snapshot.timestamps;
+
+    // Use latest() for polled values, and for values which haven't been read successfully yet
+    Ok(match module {
+        TupleName::Volume => ModuleState::from_observed(
+            module,
+            sequence,
+            times.volume,
+            latest_if_invalid(snapshot.volume).await?,
+            ModuleValue::Volume,
+        ),
+        TupleName::Brightness => ModuleState::from_observed(
+            module,
+            sequence,
+            times.brightness,
+            latest_if_invalid(snapshot.brightness).await?,
+            ModuleValue::Brightness,
+        ),
+        TupleName::Bluetooth => ModuleState::from_observed(
+            module,
+            sequence,
+            times.bluetooth,
+            latest_if_invalid(snapshot.bluetooth).await?,
+            ModuleValue::Bluetooth,
+        ),
+        TupleName::Battery => ModuleState::from_observed(
+            module,
+            sequence,
+            times.battery,
+            Battery::latest().await?,
+            ModuleValue::Battery,
+        ),
+        TupleName::Ram => ModuleState::from_observed(module, sequence, times.ram, Ram::latest().await?, ModuleValue::Ram),
+        TupleName::FanProfile => ModuleState::from_observed(
+            module,
+            sequence,
+            times.fan_profile,
+            FanProfile::latest().await?,
+            ModuleValue::FanProfile,
+        ),
+    })
+}
+
+async fn latest_if_invalid<M: Monitored>(observed: Observed<M>) -> Result<Observed<M>, DaemonError> {
+    match observed {
+        Valid(value) => Ok(Valid(value)),
+        _ => M::latest().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModuleState, ModuleValue};
+    use crate::{
+        observed::{Observed::Valid, UnavailableKind},
+        snapshot::Snapshot,
+        volume::Volume,
+    };
+
+    #[test]
+    fn states_round_trip_through_postcard() {
+        let snapshot = Snapshot {
+            volume: Valid(Volume { percent: 40, mute: true }),
+            ..Snapshot::default()
+        };
+
+        let states = ModuleState::all_from_snapshot(snapshot);
+        assert_eq!(states[0].value, Ok(ModuleValue::Volume(Volume { percent: 40, mute: true })));
+        assert!(matches!(&states[1].value, Err(reason) if reason.kind == UnavailableKind::NotRead));
+
+        let bytes = postcard::to_stdvec(&states).unwrap_or_default();
+        let decoded = postcard::from_bytes::<Vec<ModuleState>>(&bytes);
+        assert_eq!(decoded.ok(), Some(states));
+    }
+}
@@ -1,15 +1,22 @@
+use std::time::SystemTime;
+
 use tracing::{debug, instrument};
 
 use crate::{
     changed::{Changed, ChangedConstructor},
     error::DaemonError,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
-    snapshot::{IntoSnapshotEvent, Snapshot, broadcast_snapshot_event},
+    observed::Observed::{self, Unavailable, Valid},
+    snapshot::{IntoSnapshotEvent, ModuleTimestamps, Snapshot, broadcast_snapshot_event},
 };
 
 pub trait Monitored: std::fmt::Debug + Sized + Clone + Send + PartialEq + Eq + 'static {
+    /// Name of the module which the value belongs to, as in `TUPLE_NAMES`
+    const MODULE: &'static str;
+
     fn get(snapshot: &Snapshot) -> Observed<Self>;
     fn set(snapshot: &mut Snapshot, new: Observed<Self>);
+    fn last_known(snapshot: &Snapshot) -> Option<Self>;
+    fn timestamps(snapshot: &mut Snapshot) -> &mut ModuleTimestamps;
 
     fn latest() -> impl std::future::Future<Output = Result<Observed<Self>, DaemonError>> + Send;
 }
@@ -22,15 +29,34 @@ pub fn update_monitored<M: Monitored + IntoSnapshotEvent>(snapshot: &mut Snapsho
     // Get the old value from the snapshot
     let old = M::get(snapshot);
 
-    let update = MonitoredUpdate { old, new: new.clone() };
+    // Remember when the value was read (Recovering only shows that the value is being read again)
+    let timestamps = M::timestamps(snapshot);
+    let was_stale = timestamps.stale;
+    if !new.is_recovering() {
+        timestamps.read(new.is_valid(), SystemTime::now());
+    }
+
+    let mut update = MonitoredUpdate {
+        old,
+        new: new.clone(),
+        sequence: snapshot.sequence,
+        timestamps: *M::timestamps(snapshot),
+    };
 
-    // Check that the update changed the data, but don't allow updating to Unavailable from Recovering
-    if update.old != update.new && !(update.old == Recovering && update.new == Unavailable) {
+    // Check that the update changed the data, but don't allow updating to Unavailable while the value is being retried
+    let changed = update.old != update.new && !(update.old.is_retrying() && matches!(update.new, Unavailable(_)));
+    if changed {
         // Replace monitored value in the snapshot
         M::set(snapshot, new);
 
         // Log the update
         debug!("Monitored Value Updated: {update:?}");
+    }
+
+    // A value which is no longer stale is broadcast even if it didn't change
+    if changed || (was_stale && !update.timestamps.stale) {
+        snapshot.sequence += 1;
+        update.sequence = snapshot.sequence;
 
         // Broadcast update
         broadcast_snapshot_event(M::into_event(update.clone()));
@@ -45,23 +71,45 @@ pub fn update_monitored<M: Monitored + IntoSnapshotEvent>(snapshot: &mut Snapsho
 macro_rules! impl_monitored {
     ($type_name:ident, $field_name:ident, $module_name:ident) => {
         impl Monitored for $type_name {
+            const MODULE: &'static str = stringify!($module_name);
+
             fn get(snapshot: &Snapshot) -> Observed<Self> {
                 // Get the given field
                 snapshot.$field_name.clone()
             }
 
             fn set(snapshot: &mut Snapshot, new: Observed<Self>) {
+                // Remember the new value if it is Valid
+                if let $crate::observed::Observed::Valid(ref value) = new {
+                    snapshot.last_known.$field_name = Some(value.clone());
+                }
+
                 // Set the given field to the new value
                 snapshot.$field_name = new;
+            }
+
+            fn last_known(snapshot: &Snapshot) -> Option<Self> {
+                // Get the last Valid value of the given field
+                snapshot.last_known.$field_name.clone()
+            }
 
-                // Show that this snapshot happened now
-                snapshot.timestamp = std::time::Instant::now();
+            fn timestamps(snapshot: &mut Snapshot) -> &mut $crate::snapshot::ModuleTimestamps {
+                &mut snapshot.timestamps.$field_name
             }
 
             /// # Errors
             /// Returns an error if the latest value of `Monitored` can't be read due to parsing errors
             async fn latest() -> Result<Observed<Self>, DaemonError> {
-                match $crate::$module_name::source::default_source().read().await {
+                // Time the read, so slow sources show up in the metrics
+                let started = std::time::Instant::now();
+                let latest = $crate::$module_name::source::default_source().read().await;
+                $crate::metrics::record_read(
+                    Self::MODULE,
+                    started.elapsed(),
+                    matches!(latest, Ok($crate::observed::Observed::Valid(_))),
+                );
+
+                match latest {
                     Ok(latest) => Ok(latest),
                     Err(e) => {
                         error!("{e}");
@@ -77,6 +125,10 @@ macro_rules! impl_monitored {
 pub struct MonitoredUpdate<M: Monitored> {
     pub old: Observed<M>,
     pub new: Observed<M>,
+    /// `Snapshot::sequence` after this update, which is the update's own sequence number if it changed the snapshot
+    pub sequence: u64,
+    /// When the value was last read, including this update
+    pub timestamps: ModuleTimestamps,
 }
 
 impl<M> MonitoredUpdate<M>
@@ -88,6 +140,7 @@ where
         if let Self {
             old: Valid(old),
             new: Valid(new),
+            ..
         } = self
         {
             old.changed(new)
@@ -98,3 +151,111 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{Monitored, update_monitored};
+    use crate::{
+        error::DaemonError,
+        observed::{
+            Observed::{Unavailable, Valid},
+            UnavailableKind, UnavailableReason,
+        },
+        snapshot::{ModuleTimestamps, Snapshot, require_known},
+        volume::Volume,
+    };
+
+    #[test]
+    fn last_known_survives_unavailable() {
+        let mut snapshot = Snapshot::default();
+        assert_eq!(Volume::last_known(&snapshot), None);
+
+        let volume = Volume {
+            percent: 40,
+            mute: false,
+        };
+        let _update = update_monitored(&mut snapshot, Valid(volume.clone()));
+        let _update = update_monitored::<Volume>(
+            &mut snapshot,
+            Unavailable(UnavailableReason::new(UnavailableKind::CommandFailed, "wpctl")),
+        );
+
+        assert!(Volume::get(&snapshot).is_unavailable());
+        assert_eq!(Volume::last_known(&snapshot), Some(volume.clone()));
+        assert_eq!(require_known(Volume::last_known(&snapshot).as_ref()).ok(), Some(volume));
+    }
+
+    #[test]
+    fn relative_change_refused_without_known_value() {
+        let snapshot = Snapshot::default();
+
+        assert!(matches!(
+            require_known(Volume::last_known(&snapshot).as_ref()),
+            Err(DaemonError::NoKnownValue(_))
+        ));
+    }
+
+    #[test]
+    fn sequence_counts_changes() {
+        let mut snapshot = Snapshot::default();
+        let volume = Volume {
+            percent: 40,
+            mute: false,
+        };
+
+        let _update = update_monitored(&mut snapshot, Valid(volume.clone()));
+        let _update = update_monitored(&mut snapshot, Valid(volume));
+        assert_eq!(snapshot.sequence, 1, "An update which changed nothing was counted");
+
+        let _update = update_monitored(
+            &mut snapshot,
+            Valid(Volume {
+                percent: 50,
+                mute: false,
+            }),
+        );
+        assert_eq!(snapshot.sequence, 2);
+    }
+
+    #[test]
+    fn timestamps_follow_reads() {
+        let mut snapshot = Snapshot::default();
+        let volume = Volume {
+            percent: 40,
+            mute: false,
+        };
+
+        let valid = update_monitored(&mut snapshot, Valid(volume.clone())).timestamps;
+        assert!(
+            valid.last_updated.is_some() && valid.last_updated == valid.last_valid,
+            "{valid:?}"
+        );
+
+        // Unchanged values are still counted as read, and a stale value is fresh again
+        snapshot.timestamps.volume.stale = true;
+        let unchanged = update_monitored(&mut snapshot, Valid(volume));
+        assert!(!unchanged.timestamps.stale);
+        assert_eq!(unchanged.sequence, 2, "Value which is no longer stale wasn't broadcast");
+
+        let unavailable = update_monitored::<Volume>(
+            &mut snapshot,
+            Unavailable(UnavailableReason::new(UnavailableKind::CommandFailed, "wpctl")),
+        )
+        .timestamps;
+        assert!(unavailable.last_updated > unavailable.last_valid, "{unavailable:?}");
+        assert_eq!(unavailable.last_valid, unchanged.timestamps.last_valid);
+    }
+
+    #[test]
+    fn old_values_are_stale() {
+        let now = SystemTime::now();
+        let mut timestamps = ModuleTimestamps::default();
+        assert!(!timestamps.is_older_than(Duration::ZERO, now), "Unread value was stale");
+
+        timestamps.read(true, now - Duration::from_secs(20));
+        assert!(timestamps.is_older_than(Duration::from_secs(15), now));
+        assert!(!timestamps.is_older_than(Duration::from_secs(30), now));
+    }
+}
@@ -1,53 +1,85 @@
-use std::{any::type_name, time::Duration};
+use std::{
+    any::type_name,
+    collections::HashMap,
+    sync::{LazyLock, Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
 
-use Observed::{Recovering, Unavailable, Valid};
-use tokio::time::Interval;
+use Observed::{Failed, Recovering, Unavailable, Valid};
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
 use tracing::{info, instrument, warn};
 
 use crate::{
+    backoff::{Backoff, BackoffPolicy},
+    config::get_config,
     error::DaemonError,
+    metrics,
     monitored::Monitored,
     notification::Notify,
-    snapshot::{IntoSnapshotEvent, current_snapshot, update_snapshot},
+    snapshot::{IntoSnapshotEvent, update_snapshot},
     tuples::ToTuples,
 };
 
 const READ_ATTEMPTS: u32 = 10;
 const READ_ATTEMPT_INTERVAL: Duration = Duration::from_micros(500);
 
-/// # Errors
-/// Error if `M::latest().await` returns an Err
-#[instrument(skip(timer))]
-async fn read_until_valid<M: Monitored + IntoSnapshotEvent + Notify<M>>(
-    timer: &mut Interval,
-) -> Result<(Observed<M>, u32), DaemonError> {
-    let snapshot = current_snapshot().await;
-    let mut current: Observed<M> = M::get(&snapshot);
-
-    let mut attempts_num = 1;
-    while current.is_unavailable() {
-        // Only run READ_ATTEMPTS number of times
-        if attempts_num == READ_ATTEMPTS {
-            warn!("Did not read Valid within max number of attempts ({READ_ATTEMPTS}): {current:?}");
-            break;
-        }
+/// Task of each module which is reading it until it is Valid
+static RETRY_TASKS: LazyLock<Mutex<HashMap<&'static str, AbortHandle>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
 
-        // Get the latest value of this type
-        current = M::latest().await?;
+/// # Documentation
+/// Keep calling `read` until it returns `Valid`, returning the value and the number of attempts it took.
+/// The first `READ_ATTEMPTS` attempts are made in quick succession, after which `on_failed` is called once with a `Failed` value,
+/// then the attempts continue forever, waiting between each one according to `policy`
+async fn retry_until_valid<T: std::fmt::Debug, R, RFut, F, FFut>(mut read: R, mut on_failed: F, policy: BackoffPolicy) -> (T, u32)
+where
+    R: FnMut() -> RFut,
+    RFut: Future<Output = Result<Observed<T>, DaemonError>>,
+    F: FnMut(Observed<T>) -> FFut,
+    FFut: Future<Output = ()>,
+{
+    let mut timer = tokio::time::interval(READ_ATTEMPT_INTERVAL);
+    let mut backoff = Backoff::new(policy);
+    let mut since = None;
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+
+        // Get the latest value, remembering the reason if it wasn't Valid
+        let last_error = match read().await {
+            Ok(Valid(v)) => return (v, attempts),
+            Ok(other) => other.reason().cloned().unwrap_or_else(|| {
+                UnavailableReason::new(
+                    UnavailableKind::Other,
+                    format!("Value of type '{}' was {other:?}", type_name::<T>()),
+                )
+            }),
+            Err(e) => UnavailableReason::from(&e),
+        };
+
+        if attempts < READ_ATTEMPTS {
+            // Wait for the timer to tick before progressing the loop
+            timer.tick().await;
+            continue;
+        }
 
-        attempts_num += 1;
+        // The quick attempts have run out, so show that this value has failed
+        if since.is_none() {
+            warn!(
+                "{}",
+                DaemonError::MonitoredReadAttemptFail(type_name::<T>().to_string(), attempts)
+            );
 
-        // Wait for the timer to tick before progressing the loop
-        timer.tick().await;
-    }
+            let now = SystemTime::now();
+            since = Some(now);
+            on_failed(Failed { since: now, last_error }).await;
+        }
 
-    if current.is_valid() {
-        Ok((current, attempts_num))
-    } else {
-        Err(DaemonError::MonitoredReadAttemptFail(
-            type_name::<M>().to_string(),
-            attempts_num,
-        ))
+        // Wait for longer and longer between each attempt
+        let delay = backoff.next_delay();
+        info!("Retrying read of type '{}' in {delay:?}", type_name::<T>());
+        tokio::time::sleep(delay).await;
     }
 }
 
@@ -55,35 +87,190 @@ async fn read_until_valid<M: Monitored + IntoSnapshotEvent + Notify<M>>(
 /// Create a task which (asynchronously) keeps polling the latest value of this type, and updates the snapshot when it is Valid
 #[instrument]
 pub fn spawn_read_until_valid<M: Monitored + IntoSnapshotEvent + Notify<M>>() {
-    tokio::spawn(async {
+    let task = tokio::spawn(async {
         // Set the value as Recovering in the snapshot
         let _update = update_snapshot::<M>(Recovering).await;
         info!("Value of type {} set to 'Recovering'", type_name::<M>());
 
-        let mut timer = tokio::time::interval(READ_ATTEMPT_INTERVAL);
+        let (new, attempts) = retry_until_valid(
+            || {
+                metrics::record_retry_attempt(M::MODULE);
+                M::latest()
+            },
+            |failed| async {
+                metrics::record_read_attempt_fail(M::MODULE);
+                let _update = update_snapshot::<M>(failed).await;
+                info!("Value of type {} set to 'Failed'", type_name::<M>());
+            },
+            get_config().backoff,
+        )
+        .await;
+
+        info!("Read Until Available Returned: '{new:?}' after {attempts} attempts");
+    });
+
+    if replace_retry_task(M::MODULE, task.abort_handle()) {
+        info!("Previous task reading {} until it is Valid was aborted", type_name::<M>());
+    }
+}
 
-        match read_until_valid::<M>(&mut timer).await {
-            Ok((new, attempts)) => info!("Read Until Available Returned: '{new:?}' after {attempts} attempts"),
-            Err(e) => warn!("{e}"),
+/// # Documentation
+/// Remember the task which is retrying `module`, aborting the previous one if it is still running (e.g. when the value became Valid
+/// then Unavailable again while it was waiting between attempts), so only one task retries each module.
+/// Returns whether a task was aborted
+fn replace_retry_task(module: &'static str, task: AbortHandle) -> bool {
+    let previous = RETRY_TASKS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(module, task)
+        .filter(|previous| !previous.is_finished());
+
+    previous.map(|previous| previous.abort()).is_some()
+}
+
+/// # Documentation
+/// The category of problem which caused a value to be `Unavailable`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnavailableKind {
+    /// The value has not been read since the daemon started
+    NotRead,
+    /// The command used to read the value is not installed
+    CommandMissing,
+    /// The command used to read the value could not be run
+    CommandFailed,
+    /// The output of the command could not be parsed
+    ParseError,
+    /// The device which the value comes from is not present
+    DeviceAbsent,
+    /// The daemon isn't running, so a listener can't get any values
+    DaemonUnavailable,
+    Other,
+}
+
+impl std::fmt::Display for UnavailableKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::NotRead => "Not Read",
+                Self::CommandMissing => "Command Missing",
+                Self::CommandFailed => "Command Failed",
+                Self::ParseError => "Parse Error",
+                Self::DeviceAbsent => "Device Absent",
+                Self::DaemonUnavailable => "Daemon Unavailable",
+                Self::Other => "Other",
+            }
+        )
+    }
+}
+
+impl From<&DaemonError> for UnavailableKind {
+    fn from(e: &DaemonError) -> Self {
+        match e {
+            DaemonError::CommandNotFound { .. } => Self::CommandMissing,
+            DaemonError::CommandError { .. } | DaemonError::CommandTimeout { .. } => Self::CommandFailed,
+            DaemonError::DeviceAbsent(_) => Self::DeviceAbsent,
+            DaemonError::ParseError(_)
+            | DaemonError::IntegerFromByteString(_)
+            | DaemonError::IntegerFromString(_)
+            | DaemonError::BoolFromString(_)
+            | DaemonError::StringToFloatError(_)
+            | DaemonError::IntError(_) => Self::ParseError,
+            _ => Self::Other,
         }
-    });
+    }
+}
+
+/// # Documentation
+/// Why a value is `Unavailable`, and when it became `Unavailable`.
+/// Two reasons are equal if their `kind` and `message` are equal, so that repeated failures don't count as a change
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnavailableReason {
+    pub kind: UnavailableKind,
+    pub message: String,
+    pub since: SystemTime,
+}
+
+impl UnavailableReason {
+    #[must_use]
+    pub fn new<S: Into<String>>(kind: UnavailableKind, message: S) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            since: SystemTime::now(),
+        }
+    }
+
+    /// # Documentation
+    /// The reason used for values which have not been read yet
+    #[must_use]
+    pub fn not_read() -> Self {
+        Self::new(UnavailableKind::NotRead, "Value has not been read yet")
+    }
+}
+
+impl From<&DaemonError> for UnavailableReason {
+    fn from(e: &DaemonError) -> Self {
+        Self::new(UnavailableKind::from(e), e.to_string())
+    }
+}
+
+impl std::fmt::Display for UnavailableReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl PartialEq for UnavailableReason {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.message == other.message
+    }
+}
+
+impl Eq for UnavailableReason {}
+
+impl PartialOrd for UnavailableReason {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for UnavailableReason {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.kind, &self.message).cmp(&(other.kind, &other.message))
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Observed<T> {
     Valid(T),
-    Unavailable,
+    Unavailable(UnavailableReason),
     Recovering,
+    Failed {
+        since: SystemTime,
+        last_error: UnavailableReason,
+    },
 }
 
 impl<T: ToTuples> Observed<T> {
     pub fn to_tuples(self) -> Vec<(String, String)> {
         match self {
             Valid(v) => v.to_tuples(),
-            Unavailable | Recovering => {
+            Unavailable(_) | Recovering | Failed { .. } => {
                 // Generate a fake tuple with "?" instead of real data
                 let tuple_names = T::to_tuple_names();
-                tuple_names.into_iter().map(|name| (name, String::from("?"))).collect()
+                let mut tuples = tuple_names
+                    .into_iter()
+                    .map(|name| (name, String::from("?")))
+                    .collect::<Vec<_>>();
+
+                // Show why the data is missing
+                if let Some(reason) = self.reason() {
+                    tuples.push((String::from("_error"), reason.to_string()));
+                }
+
+                tuples
             }
         }
     }
@@ -93,7 +280,7 @@ impl<T: std::fmt::Debug> std::fmt::Display for Observed<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Valid(v) => write!(f, "{v:?}"),
-            Unavailable | Recovering => write!(f, "?"),
+            Unavailable(_) | Recovering | Failed { .. } => write!(f, "?"),
         }
     }
 }
@@ -106,7 +293,7 @@ impl<T> Observed<T> {
     pub fn unwrap(self) -> T {
         match self {
             Valid(v) => v,
-            Unavailable | Recovering => panic!("Called 'unwrap()' on 'Unavailable'"),
+            Unavailable(_) | Recovering | Failed { .. } => panic!("Called 'unwrap()' on 'Unavailable'"),
         }
     }
 
@@ -116,7 +303,7 @@ impl<T> Observed<T> {
     pub fn expect(self, msg: &str) -> T {
         match self {
             Valid(v) => v,
-            Unavailable | Recovering => panic!("{msg}"),
+            Unavailable(_) | Recovering | Failed { .. } => panic!("{msg}"),
         }
     }
 
@@ -124,7 +311,7 @@ impl<T> Observed<T> {
     pub fn unwrap_or(self, default: T) -> T {
         match self {
             Valid(v) => v,
-            Unavailable | Recovering => default,
+            Unavailable(_) | Recovering | Failed { .. } => default,
         }
     }
 
@@ -132,7 +319,7 @@ impl<T> Observed<T> {
     pub fn unwrap_or_else<F: Fn() -> T>(self, f: F) -> T {
         match self {
             Valid(v) => v,
-            Unavailable | Recovering => f(),
+            Unavailable(_) | Recovering | Failed { .. } => f(),
         }
     }
 
@@ -142,10 +329,20 @@ impl<T> Observed<T> {
     }
 
     /// # Documentation
-    /// This treats `Unavailable` and `Recovering` as the same
+    /// The reason this value is unavailable (`None` if the value is `Valid` or `Recovering`)
+    #[must_use]
+    pub const fn reason(&self) -> Option<&UnavailableReason> {
+        match self {
+            Unavailable(reason) | Failed { last_error: reason, .. } => Some(reason),
+            Valid(_) | Recovering => None,
+        }
+    }
+
+    /// # Documentation
+    /// This treats `Unavailable`, `Recovering`, and `Failed` as the same
     #[must_use]
     pub const fn is_unavailable(&self) -> bool {
-        matches!(self, Unavailable | Recovering)
+        matches!(self, Unavailable(_) | Recovering | Failed { .. })
     }
 
     #[must_use]
@@ -153,21 +350,33 @@ impl<T> Observed<T> {
         matches!(self, Recovering)
     }
 
+    #[must_use]
+    pub const fn is_failed(&self) -> bool {
+        matches!(self, Failed { .. })
+    }
+
+    /// # Documentation
+    /// Whether a task is currently trying to read this value until it is `Valid` (`Recovering` or `Failed`)
+    #[must_use]
+    pub const fn is_retrying(&self) -> bool {
+        matches!(self, Recovering | Failed { .. })
+    }
+
     #[must_use]
     pub fn is_valid_or<F: Fn() -> bool>(self, f: F) -> bool {
         match self {
             Valid(_) => true,
-            Unavailable | Recovering => f(),
+            Unavailable(_) | Recovering | Failed { .. } => f(),
         }
     }
 
     /// # Documentation
-    /// This treats `Unavailable` and `Recovering` as the same
+    /// This treats `Unavailable`, `Recovering`, and `Failed` as the same
     #[must_use]
     pub fn is_unavailable_or<F: Fn(T) -> bool>(self, f: F) -> bool {
         match self {
             Valid(v) => f(v),
-            Unavailable | Recovering => true,
+            Unavailable(_) | Recovering | Failed { .. } => true,
         }
     }
 
@@ -175,7 +384,7 @@ impl<T> Observed<T> {
     pub fn is_recovering_or<U: Fn() -> bool, V: Fn(T) -> bool>(self, valid_fn: V, unavailable_fn: U) -> bool {
         match self {
             Valid(v) => valid_fn(v),
-            Unavailable => unavailable_fn(),
+            Unavailable(_) | Failed { .. } => unavailable_fn(),
             Recovering => true,
         }
     }
@@ -186,7 +395,7 @@ impl<T: Default> Observed<T> {
     pub fn unwrap_or_default(self) -> T {
         match self {
             Valid(v) => v,
-            Unavailable | Recovering => T::default(),
+            Unavailable(_) | Recovering | Failed { .. } => T::default(),
         }
     }
 }
@@ -195,19 +404,19 @@ impl<T: Default> Observed<T> {
 
 impl<T> Observed<T> {
     #[must_use]
-    pub fn from_result<E: std::fmt::Display>(res: Result<T, E>) -> Self {
+    pub fn from_result(res: Result<T, DaemonError>) -> Self {
         match res {
             Ok(v) => Valid(v),
             Err(e) => {
                 warn!("{e}");
-                Unavailable
+                Unavailable(UnavailableReason::from(&e))
             }
         }
     }
 }
 
-impl<T, E: std::fmt::Display> From<Result<T, E>> for Observed<T> {
-    fn from(value: Result<T, E>) -> Self {
+impl<T> From<Result<T, DaemonError>> for Observed<T> {
+    fn from(value: Result<T, DaemonError>) -> Self {
         Self::from_result(value)
     }
 }
@@ -218,18 +427,19 @@ impl<T> Observed<T> {
     pub fn map<F: Fn(T) -> U, U>(self, f: F) -> Observed<U> {
         match self {
             Valid(v) => Valid(f(v)),
-            Unavailable => Unavailable,
+            Unavailable(reason) => Unavailable(reason),
             Recovering => Recovering,
+            Failed { since, last_error } => Failed { since, last_error },
         }
     }
 
     /// # Documentation
-    /// This treats `Unavailable` and `Recovering` as the same
+    /// This treats `Unavailable`, `Recovering`, and `Failed` as the same
     #[must_use]
     pub fn map_unavailable<F: Fn() -> T>(self, f: F) -> T {
         match self {
             Valid(v) => v,
-            Unavailable | Recovering => f(),
+            Unavailable(_) | Recovering | Failed { .. } => f(),
         }
     }
 
@@ -237,28 +447,191 @@ impl<T> Observed<T> {
     pub fn map_recovering<F: Fn() -> T>(self, f: F) -> Self {
         match self {
             Valid(v) => Valid(v),
-            Unavailable => Unavailable,
+            Unavailable(reason) => Unavailable(reason),
             Recovering => Valid(f()),
+            Failed { since, last_error } => Failed { since, last_error },
         }
     }
 
     /// # Documentation
-    /// This treats `Unavailable` and `Recovering` as the same
+    /// This treats `Unavailable`, `Recovering`, and `Failed` as the same
     #[must_use]
     pub fn map_or<F: Fn(T) -> U, U>(self, default: U, f: F) -> U {
         match self {
             Valid(v) => f(v),
-            Unavailable | Recovering => default,
+            Unavailable(_) | Recovering | Failed { .. } => default,
         }
     }
 
     /// # Documentation
-    /// This treats `Unavailable` and `Recovering` as the same
+    /// This treats `Unavailable`, `Recovering`, and `Failed` as the same
     #[must_use]
     pub fn map_or_else<F: Fn(T) -> U, D: Fn() -> U, U>(self, default: D, f: F) -> U {
         match self {
             Valid(v) => f(v),
-            Unavailable | Recovering => default(),
+            Unavailable(_) | Recovering | Failed { .. } => default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Mutex,
+            atomic::{AtomicU32, Ordering},
+        },
+        time::Duration,
+    };
+
+    use tokio::time::Instant;
+
+    use super::{
+        Observed::{self, Unavailable, Valid},
+        READ_ATTEMPTS, UnavailableKind, UnavailableReason, replace_retry_task, retry_until_valid,
+    };
+    use crate::{backoff::BackoffPolicy, volume::Volume};
+
+    const POLICY: BackoffPolicy = BackoffPolicy {
+        initial_delay: 1000,
+        multiplier: 2.0,
+        max_delay: 10_000,
+        jitter: 0.0,
+    };
+
+    /// # Documentation
+    /// Run `retry_until_valid` with a reader which only becomes `Valid` on the given attempt
+    async fn retry_valid_on(valid_attempt: u32) -> (u32, u32, Vec<Observed<u32>>) {
+        let reads = AtomicU32::new(0);
+        let failures = Mutex::new(Vec::new());
+
+        let (value, attempts) = retry_until_valid(
+            || {
+                let attempt = reads.fetch_add(1, Ordering::Relaxed) + 1;
+
+                async move {
+                    Ok(if attempt >= valid_attempt {
+                        Valid(attempt)
+                    } else {
+                        Unavailable(UnavailableReason::new(UnavailableKind::DeviceAbsent, "No device"))
+                    })
+                }
+            },
+            |failed| {
+                failures
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .push(failed);
+                async {}
+            },
+            POLICY,
+        )
+        .await;
+
+        (value, attempts, failures.into_inner().unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn only_one_task_retries_each_module() {
+        let first = tokio::spawn(std::future::pending::<()>());
+        let second = tokio::spawn(std::future::pending::<()>());
+        let other = tokio::spawn(std::future::pending::<()>());
+
+        assert!(!replace_retry_task("retry_test", first.abort_handle()));
+        assert!(!replace_retry_task("retry_test_other", other.abort_handle()));
+
+        // Retrying the module again aborts the task which was still running, but not other modules' tasks
+        assert!(replace_retry_task("retry_test", second.abort_handle()));
+        assert!(first.await.is_err_and(|e| e.is_cancelled()));
+        assert!(!second.is_finished() && !other.is_finished());
+
+        // A task which has finished isn't aborted
+        second.abort();
+        let _ = second.await;
+        let third = tokio::spawn(std::future::pending::<()>());
+        assert!(!replace_retry_task("retry_test", third.abort_handle()));
+
+        third.abort();
+        other.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn valid_within_quick_attempts_does_not_fail() {
+        let start = Instant::now();
+        let (value, attempts, failures) = retry_valid_on(3).await;
+
+        assert_eq!((value, attempts), (3, 3));
+        assert!(failures.is_empty(), "Value was marked as Failed: {failures:?}");
+        assert!(
+            start.elapsed() < Duration::from_millis(10),
+            "Quick attempts took {:?}",
+            start.elapsed()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_after_attempts_exhausted() {
+        let start = Instant::now();
+        let (value, attempts, failures) = retry_valid_on(READ_ATTEMPTS + 2).await;
+
+        assert_eq!((value, attempts), (READ_ATTEMPTS + 2, READ_ATTEMPTS + 2));
+
+        // Failed should only be reported once, with the reason for the last failure
+        assert_eq!(failures.len(), 1, "{failures:?}");
+        assert!(
+            matches!(&failures[0], Observed::Failed { last_error, .. } if last_error.kind == UnavailableKind::DeviceAbsent),
+            "{failures:?}"
+        );
+
+        // Two retries were needed after failing, waiting 1s then 2s
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_secs(3) && elapsed < Duration::from_millis(3100),
+            "Backoff took {elapsed:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_is_capped() {
+        let start = Instant::now();
+        let (_, _, failures) = retry_valid_on(READ_ATTEMPTS + 6).await;
+
+        assert_eq!(failures.len(), 1, "{failures:?}");
+
+        // Delays of 1s, 2s, 4s, 8s, then capped at 10s, 10s
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_secs(35) && elapsed < Duration::from_millis(35_100),
+            "Backoff took {elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn unavailable_tuples_show_reason() {
+        let reason = UnavailableReason::new(UnavailableKind::CommandMissing, "wpctl");
+        let tuples = Unavailable::<Volume>(reason).to_tuples();
+
+        assert!(
+            tuples
+                .iter()
+                .filter(|(name, _)| name != "_error")
+                .all(|(_, value)| value == "?"),
+            "{tuples:?}"
+        );
+        assert!(
+            tuples.contains(&(String::from("_error"), String::from("Command Missing: wpctl"))),
+            "{tuples:?}"
+        );
+    }
+
+    #[test]
+    fn reasons_equal_regardless_of_time() {
+        let first = UnavailableReason::new(UnavailableKind::ParseError, "Bad output");
+        let second = UnavailableReason {
+            since: first.since + Duration::from_mins(1),
+            ..first.clone()
+        };
+
+        assert_eq!(Unavailable::<u32>(first), Unavailable(second));
+    }
+}
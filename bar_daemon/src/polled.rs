@@ -1,4 +1,8 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
 
 use tracing::error;
 
@@ -6,10 +10,28 @@ use crate::{
     config::get_config,
     monitored::Monitored,
     notification::Notify,
-    snapshot::{IntoSnapshotEvent, update_snapshot},
+    snapshot::{IntoSnapshotEvent, mark_if_stale, update_snapshot},
     trigger::{DebouncedEventTrigger, HybridTrigger, IntervalTrigger, Trigger},
 };
 
+/// Time of the last iteration of each poller, used to check that none of them have become stuck
+static HEARTBEATS: LazyLock<Mutex<HashMap<&'static str, Instant>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn heartbeat(name: &'static str) {
+    if let Ok(mut heartbeats) = HEARTBEATS.lock() {
+        heartbeats.insert(name, Instant::now());
+    }
+}
+
+/// # Documentation
+/// Check that every poller has finished an iteration within `max_silence`
+#[must_use]
+pub fn pollers_alive(max_silence: Duration) -> bool {
+    HEARTBEATS
+        .lock()
+        .is_ok_and(|heartbeats| heartbeats.values().all(|last| last.elapsed() <= max_silence))
+}
+
 pub trait Polled: Monitored {
     // TODO Can add seperate polling rates for each polled value
     #[must_use]
@@ -23,7 +45,26 @@ pub fn spawn_poller<P: Polled + IntoSnapshotEvent + Notify<P>>(shutdown_notify:
     let trigger = IntervalTrigger::new(P::interval());
 
     // Spawn the polling loop, triggered by a timer
-    spawn_poll_on_trigger::<P, _>(trigger, shutdown_notify);
+    spawn_poll_on_trigger::<P, _>(trigger, shutdown_notify.clone());
+    spawn_stale_check::<P>(shutdown_notify);
+}
+
+/// # Documentation
+/// Create a task which marks the value as stale when its poller hasn't read it for longer than the config's `stale_after`.
+/// This is a separate task so a poller which is stuck (e.g. waiting for a command) can still be noticed
+pub fn spawn_stale_check<P: Polled + IntoSnapshotEvent>(shutdown_notify: Arc<tokio::sync::Notify>) {
+    let stale_after = Duration::from_millis(get_config().stale_after);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval((stale_after / 4).max(Duration::from_millis(100)));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => mark_if_stale::<P>(stale_after).await,
+                () = shutdown_notify.notified() => break,
+            }
+        }
+    });
 }
 
 pub fn spawn_poll_or_listen<P: Polled + IntoSnapshotEvent + Notify<P>>(
@@ -38,13 +79,17 @@ pub fn spawn_poll_or_listen<P: Polled + IntoSnapshotEvent + Notify<P>>(
     );
 
     // Spawn the polling loop, triggered by a timer
-    spawn_poll_on_trigger::<P, _>(trigger, shutdown_notify);
+    spawn_poll_on_trigger::<P, _>(trigger, shutdown_notify.clone());
+    spawn_stale_check::<P>(shutdown_notify);
 }
 
 pub fn spawn_poll_on_trigger<M: Monitored + IntoSnapshotEvent + Notify<M>, T: Trigger + 'static>(
     mut trigger: T,
     shutdown_notify: Arc<tokio::sync::Notify>,
 ) {
+    let name = std::any::type_name::<M>();
+    heartbeat(name);
+
     tokio::spawn(async move {
         loop {
             tokio::select! {
@@ -56,9 +101,14 @@ pub fn spawn_poll_on_trigger<M: Monitored + IntoSnapshotEvent + Notify<M>, T: Tr
                         Ok(new_value) => {let _update= update_snapshot(new_value).await;}
                         Err(e) => error!("Poll on Trigger Failed: {e}")
                     }
+
+                    heartbeat(name);
                 }
 
                 () = shutdown_notify.notified() => {
+                    if let Ok(mut heartbeats) = HEARTBEATS.lock() {
+                        heartbeats.remove(name);
+                    }
                     break;
                 }
             }
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    battery::BatteryItem,
+    bluetooth::BluetoothItem,
+    brightness::BrightnessItem,
+    error::{DaemonError, ErrorCode},
+    fan_profile::FanProfileItem,
+    history::HistoryEntry,
+    module_value::ModuleState,
+    observed::{
+        Observed::{self, Valid},
+        UnavailableReason,
+    },
+    ram::RamItem,
+    status::DaemonStatus,
+    tuples::{TUPLE_NAMES, TupleName},
+    volume::VolumeItem,
+};
+
+pub const BUFFER_SIZE: usize = 1024;
+
+/// A request to the daemon, sent as a postcard COBS frame by `write_message`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DaemonMessage {
+    Set {
+        item: DaemonItem,
+        value: String,
+    },
+    Get {
+        item: DaemonItem,
+    },
+    /// Get the typed value of a module
+    GetState {
+        module: TupleName,
+    },
+    Listen,
+    /// Like `Listen`, but the state of every module, then each change, is sent as a postcard COBS frame of `ModuleState`
+    Subscribe,
+    /// Get the health of the daemon and each of its modules
+    Status,
+    /// Get the changes of a module within the last `since` (Every kept change when `None`), which is sent as a postcard COBS frame
+    History {
+        module: TupleName,
+        since: Option<Duration>,
+    },
+    Shutdown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DaemonReply {
+    Value {
+        item: DaemonItem,
+        value: String,
+    },
+    Tuples {
+        item: DaemonItem,
+        tuples: Vec<(String, String)>,
+    },
+    AllTuples {
+        tuples: Vec<(String, Vec<(String, String)>)>,
+    },
+    Unavailable {
+        item: DaemonItem,
+        value: String,
+        reason: UnavailableReason,
+    },
+    State(ModuleState),
+    Status(DaemonStatus),
+    History {
+        module: TupleName,
+        entries: Vec<HistoryEntry>,
+    },
+    ShuttingDown,
+    Error {
+        code: ErrorCode,
+        module: Option<String>,
+        message: String,
+    },
+}
+
+impl DaemonReply {
+    /// # Documentation
+    /// Create a reply for a single value, with the reason the value is missing if it isn't `Valid`
+    #[must_use]
+    pub fn from_observed<T: std::fmt::Display>(item: DaemonItem, observed: Observed<T>) -> Self {
+        match observed {
+            Valid(value) => Self::Value {
+                item,
+                value: value.to_string(),
+            },
+            other => match other.reason() {
+                Some(reason) => Self::Unavailable {
+                    item,
+                    value: String::from("?"),
+                    reason: reason.clone(),
+                },
+                None => Self::Value {
+                    item,
+                    value: String::from("?"),
+                },
+            },
+        }
+    }
+
+    /// # Documentation
+    /// Create a reply which tells the client why their request failed
+    #[must_use]
+    pub fn from_error(module: Option<&str>, e: &DaemonError) -> Self {
+        Self::Error {
+            code: ErrorCode::from(e),
+            module: module.map(ToString::to_string),
+            message: e.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DaemonItem {
+    Volume(VolumeItem),
+    Brightness(BrightnessItem),
+    Bluetooth(BluetoothItem),
+    Battery(BatteryItem),
+    Ram(RamItem),
+    FanProfile(FanProfileItem),
+    All,
+}
+
+impl DaemonItem {
+    /// # Documentation
+    /// The name of the module which this item belongs to (`None` for `All`)
+    #[must_use]
+    pub fn module(&self) -> Option<&'static str> {
+        let tuple_name = match self {
+            Self::Volume(_) => TupleName::Volume,
+            Self::Brightness(_) => TupleName::Brightness,
+            Self::Bluetooth(_) => TupleName::Bluetooth,
+            Self::Battery(_) => TupleName::Battery,
+            Self::Ram(_) => TupleName::Ram,
+            Self::FanProfile(_) => TupleName::FanProfile,
+            Self::All => return None,
+        };
+
+        Some(TUPLE_NAMES[tuple_name as usize])
+    }
+}
+
+/// # Documentation
+/// Write a reply as a postcard COBS frame, so it can be read whole by `read_reply` however large it is
+///
+/// # Errors
+/// Returns an error if the reply can't be serialized
+/// Returns an error if `writer` could not be wrote to
+pub async fn write_reply<W: AsyncWrite + Unpin>(writer: &mut W, reply: &DaemonReply) -> Result<(), DaemonError> {
+    writer.write_all(&postcard::to_stdvec_cobs(reply)?).await?;
+
+    Ok(())
+}
+
+/// # Documentation
+/// Read a reply which the daemon sent with `write_reply`, reading until the zero byte which ends the frame
+///
+/// # Errors
+/// Returns an error if `reader` cannot be read
+/// Returns an error if the daemon closed the connection before the end of the frame
+/// Returns an error if the frame isn't a `DaemonReply`
+pub async fn read_reply<R: AsyncRead + Unpin>(reader: &mut R) -> Result<DaemonReply, DaemonError> {
+    let mut frame = Vec::new();
+    let mut buf = [0; BUFFER_SIZE];
+
+    // Nothing is sent after the frame until the next request, so every byte read belongs to it
+    while frame.last() != Some(&0) {
+        match reader.read(&mut buf).await? {
+            0 => return Err(DaemonError::ConnectionClosed),
+            n => frame.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    Ok(postcard::from_bytes_cobs(&mut frame)?)
+}
+
+/// # Documentation
+/// Write a request as a postcard COBS frame, so the daemon reads it whole however it arrives
+///
+/// # Errors
+/// Returns an error if the request can't be serialized
+/// Returns an error if `writer` could not be wrote to
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &DaemonMessage) -> Result<(), DaemonError> {
+    writer.write_all(&postcard::to_stdvec_cobs(message)?).await?;
+
+    Ok(())
+}
+
+/// # Documentation
+/// Read the next request which a client sent with `write_message`, or `None` if the client closed the connection between requests.
+/// Anything sent after the frame is kept in `reader`, for the next request
+///
+/// # Errors
+/// Returns an error if `reader` cannot be read
+/// Returns an error if the client closed the connection before the end of the frame
+/// Returns an error if the frame is longer than `BUFFER_SIZE`, which no request is
+/// Returns an error if the frame isn't a `DaemonMessage`
+pub async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<DaemonMessage>, DaemonError> {
+    let mut frame = Vec::new();
+    (&mut *reader).take(BUFFER_SIZE as u64).read_until(0, &mut frame).await?;
+
+    match frame.last() {
+        None => Ok(None),
+        Some(0) => Ok(Some(postcard::from_bytes_cobs(&mut frame)?)),
+        Some(_) if frame.len() < BUFFER_SIZE => Err(DaemonError::ConnectionClosed),
+        Some(_) => Err(DaemonError::MessageTooLarge(BUFFER_SIZE)),
+    }
+}
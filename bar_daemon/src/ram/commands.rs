@@ -0,0 +1,32 @@
+use clap::Subcommand;
+
+use crate::protocol::{DaemonItem, DaemonMessage};
+
+use super::RamItem;
+
+#[derive(Subcommand)]
+pub enum RamGetCommands {
+    #[command(alias = "tot", alias = "t")]
+    Total,
+    #[command(alias = "u")]
+    Used,
+    #[command(alias = "per", alias = "p")]
+    Percent,
+    #[command(alias = "i")]
+    Icon,
+}
+
+#[must_use]
+pub const fn match_get_commands(commands: &Option<RamGetCommands>) -> DaemonMessage {
+    DaemonMessage::Get {
+        item: match commands {
+            Some(commands) => match commands {
+                RamGetCommands::Total => DaemonItem::Ram(RamItem::Total),
+                RamGetCommands::Used => DaemonItem::Ram(RamItem::Used),
+                RamGetCommands::Percent => DaemonItem::Ram(RamItem::Percent),
+                RamGetCommands::Icon => DaemonItem::Ram(RamItem::Icon),
+            },
+            None => DaemonItem::Ram(RamItem::All),
+        },
+    }
+}
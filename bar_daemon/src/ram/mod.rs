@@ -1,4 +1,9 @@
-pub use value::{Ram, RamGetCommands, RamItem, evaluate_item, match_get_commands};
+#[cfg(feature = "daemon")]
+pub use commands::{RamGetCommands, match_get_commands};
+pub use source::COMMAND;
+pub use value::{Ram, RamItem, evaluate_item};
 
+#[cfg(feature = "daemon")]
+mod commands;
 mod source;
 mod value;
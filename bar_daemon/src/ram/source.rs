@@ -1,9 +1,9 @@
-use std::str::SplitWhitespace;
+use std::{str::SplitWhitespace, sync::Arc};
 
 use tracing::instrument;
 
 use crate::{
-    command,
+    command::{CommandRunner, default_runner},
     error::DaemonError,
     observed::Observed::{self},
     snapshot::update_snapshot,
@@ -16,17 +16,41 @@ pub trait RamSource {
     fn read(&self) -> impl std::future::Future<Output = Result<Observed<Ram>, DaemonError>> + Send;
 }
 
+/// Command which RAM usage is read with
+pub const COMMAND: &str = "free";
+
 // -------------- Default Source ---------------
 
 #[must_use]
 pub fn default_source() -> impl RamSource {
-    ProcpsRam
+    ProcpsRam::new(default_runner())
 }
 
 // ---------------- Procps Source --------------
 
 #[derive(Debug)]
-pub struct ProcpsRam;
+pub struct ProcpsRam {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl ProcpsRam {
+    #[must_use]
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    async fn read_inner(&self) -> Result<Ram, DaemonError> {
+        let output = get_procps_output(self.runner.as_ref()).await?;
+        let output_split = get_procps_output_split(&output)?;
+
+        let total = get_procps_total_from_split(output_split.clone())?;
+        let used = get_procps_used_from_split(output_split)?;
+
+        let percent = get_percent_from_used_total(used, total);
+
+        Ok(Ram { total, used, percent })
+    }
+}
 
 impl RamSource for ProcpsRam {
     /// # Errors
@@ -34,20 +58,8 @@ impl RamSource for ProcpsRam {
     /// Returns an error if values in the output of the command cannot be parsed
     #[instrument]
     async fn read(&self) -> Result<Observed<Ram>, DaemonError> {
-        fn read_inner() -> Result<Ram, DaemonError> {
-            let output = get_procps_output()?;
-            let output_split = get_procps_output_split(&output)?;
-
-            let total = get_procps_total_from_split(output_split.clone())?;
-            let used = get_procps_used_from_split(output_split)?;
-
-            let percent = get_percent_from_used_total(used, total);
-
-            Ok(Ram { total, used, percent })
-        }
-
         // Set as unavailable if the inner function threw an error
-        let ram: Observed<_> = read_inner().into();
+        let ram: Observed<_> = self.read_inner().await.into();
 
         // Update snapshot
         let _update = update_snapshot(ram.clone()).await;
@@ -56,9 +68,9 @@ impl RamSource for ProcpsRam {
     }
 }
 
-fn get_procps_output() -> Result<String, DaemonError> {
+async fn get_procps_output(runner: &dyn CommandRunner) -> Result<String, DaemonError> {
     // Get the output of free so it can be parsed
-    command::run("free", &["-b"])
+    runner.run(COMMAND, &["-b"]).await
 }
 
 fn get_procps_output_split(output: &str) -> Result<SplitWhitespace<'_>, DaemonError> {
@@ -101,3 +113,46 @@ fn get_procps_used_from_split(mut split: SplitWhitespace) -> Result<u64, DaemonE
 fn get_percent_from_used_total(used: u64, total: u64) -> u32 {
     ((used as f64 * 100.) / total as f64) as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ProcpsRam, Ram, get_percent_from_used_total};
+    use crate::{command::fake::FakeRunner, error::DaemonError};
+
+    const FREE_OUTPUT: &str = "               total        used        free      shared  buff/cache   available
+Mem:     16000000000  4000000000  8000000000   500000000  4000000000 12000000000
+Swap:     8000000000           0  8000000000";
+
+    fn source(output: &str) -> ProcpsRam {
+        ProcpsRam::new(Arc::new(FakeRunner::new().with_output("free -b", output)))
+    }
+
+    #[tokio::test]
+    async fn reads_total_and_used() {
+        assert_eq!(
+            source(FREE_OUTPUT).read_inner().await.ok(),
+            Some(Ram {
+                total: 16_000_000_000,
+                used: 4_000_000_000,
+                percent: 25,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_memory_line_is_a_parse_error() {
+        let ram = source("               total        used        free").read_inner().await;
+        assert!(matches!(ram, Err(DaemonError::ParseError(_))), "{ram:?}");
+
+        let ram = source("header\nMem: lots").read_inner().await;
+        assert!(matches!(ram, Err(DaemonError::IntegerFromString(_))), "{ram:?}");
+    }
+
+    #[test]
+    fn percent_is_rounded_down() {
+        assert_eq!(get_percent_from_used_total(1, 3), 33);
+        assert_eq!(get_percent_from_used_total(3, 3), 100);
+    }
+}
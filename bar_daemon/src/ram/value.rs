@@ -1,34 +1,21 @@
-use clap::Subcommand;
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
 use crate::{
     ICON_END, ICON_EXT,
-    daemon::{DaemonItem, DaemonMessage, DaemonReply},
     error::DaemonError,
     impl_monitored,
     monitored::{Monitored, MonitoredUpdate},
     notification::Notify,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
+    observed::Observed::{self, Failed, Recovering, Unavailable, Valid},
     polled::Polled,
-    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot},
+    protocol::{DaemonItem, DaemonReply},
+    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot, with_timestamps},
     tuples::ToTuples,
 };
 
 use super::source::RamSource;
 
-#[derive(Subcommand)]
-pub enum RamGetCommands {
-    #[command(alias = "tot", alias = "t")]
-    Total,
-    #[command(alias = "u")]
-    Used,
-    #[command(alias = "per", alias = "p")]
-    Percent,
-    #[command(alias = "i")]
-    Icon,
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum RamItem {
     Total,
@@ -39,7 +26,17 @@ pub enum RamItem {
 }
 
 #[derive(
-    Clone, Debug, Default, PartialEq, PartialOrd, Ord, Eq, bar_daemon_derive::Polled, bar_daemon_derive::IntoSnapshotEvent,
+    Serialize,
+    Deserialize,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    PartialOrd,
+    Ord,
+    Eq,
+    bar_daemon_derive::Polled,
+    bar_daemon_derive::IntoSnapshotEvent,
 )]
 pub struct Ram {
     pub total: u64,
@@ -97,53 +94,23 @@ impl Notify<Self> for Ram {}
 /// Returns an error if the requested value could not be evaluated
 #[instrument]
 pub async fn evaluate_item(item: DaemonItem, ram_item: &RamItem) -> Result<DaemonReply, DaemonError> {
-    Ok(
-        // Get value
-        match ram_item {
-            RamItem::Total => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.ram {
-                    Valid(ram) => ram.total.to_string(),
-                    Unavailable | Recovering => Ram::latest().await?.map(|ram| ram.total).to_string(),
-                },
-            },
-            RamItem::Used => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.ram {
-                    Valid(ram) => ram.used.to_string(),
-                    Unavailable | Recovering => Ram::latest().await?.map(|ram| ram.used).to_string(),
-                },
-            },
-            RamItem::Percent => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.ram {
-                    Valid(ram) => ram.percent.to_string(),
-                    Unavailable | Recovering => Ram::latest().await?.map(|ram| ram.percent).to_string(),
-                },
-            },
-            RamItem::Icon => DaemonReply::Value {
-                item,
-                value: Ram::get_icon(),
-            },
-            RamItem::All => DaemonReply::Tuples {
-                item,
-                tuples: Ram::latest().await?.to_tuples(),
-            },
-        },
-    )
-}
+    // Get value
+    let ram = match current_snapshot().await.ram {
+        Valid(ram) => Valid(ram),
+        Unavailable(_) | Recovering | Failed { .. } => Ram::latest().await?,
+    };
 
-#[must_use]
-pub const fn match_get_commands(commands: &Option<RamGetCommands>) -> DaemonMessage {
-    DaemonMessage::Get {
-        item: match commands {
-            Some(commands) => match commands {
-                RamGetCommands::Total => DaemonItem::Ram(RamItem::Total),
-                RamGetCommands::Used => DaemonItem::Ram(RamItem::Used),
-                RamGetCommands::Percent => DaemonItem::Ram(RamItem::Percent),
-                RamGetCommands::Icon => DaemonItem::Ram(RamItem::Icon),
-            },
-            None => DaemonItem::Ram(RamItem::All),
+    Ok(match ram_item {
+        RamItem::Total => DaemonReply::from_observed(item, ram.map(|ram| ram.total)),
+        RamItem::Used => DaemonReply::from_observed(item, ram.map(|ram| ram.used)),
+        RamItem::Percent => DaemonReply::from_observed(item, ram.map(|ram| ram.percent)),
+        RamItem::Icon => DaemonReply::Value {
+            item,
+            value: Ram::get_icon(),
         },
-    }
+        RamItem::All => DaemonReply::Tuples {
+            item,
+            tuples: with_timestamps::<Ram>(Ram::latest().await?.to_tuples()).await,
+        },
+    })
 }
@@ -0,0 +1,543 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{debug, error, info, instrument};
+
+use crate::{
+    battery::Battery,
+    bluetooth::Bluetooth,
+    brightness::{Brightness, MONITOR_ID},
+    command::{CommandRunner, set_default_runner},
+    error::DaemonError,
+    fan_profile::FanProfile,
+    log_linear::{linear_to_logarithmic, logarithmic_to_linear},
+    monitored::Monitored,
+    ram::Ram,
+    volume::Volume,
+};
+
+/// # Documentation
+/// A module which can be made unavailable by a scenario
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SimulatedModule {
+    Volume,
+    Brightness,
+    Bluetooth,
+    Battery,
+    Ram,
+    FanProfile,
+}
+
+/// # Documentation
+/// The simulated hardware, which the outputs of the simulated commands are created from
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SimulatedState {
+    /// Linear volume percentage
+    pub volume: u32,
+    pub mute: bool,
+    pub monitor: u32,
+    pub keyboard: u32,
+    pub bluetooth: bool,
+    /// Profile name as printed by asusctl (Performance, Balanced, Quiet)
+    pub fan_profile: String,
+    pub battery: f64,
+    /// State as printed by acpi (Charging, Discharging, Not charging)
+    pub battery_state: String,
+    /// Percent per minute which the battery charges or discharges by
+    pub battery_rate: f64,
+    /// Total RAM in bytes
+    pub ram_total: u64,
+    /// Used RAM in bytes
+    pub ram_used: u64,
+    /// Modules whose commands fail
+    pub unavailable: BTreeSet<SimulatedModule>,
+    /// Error which the commands of unavailable modules fail with
+    pub failure: String,
+}
+
+impl Default for SimulatedState {
+    fn default() -> Self {
+        Self {
+            volume: 50,
+            mute: false,
+            monitor: 80,
+            keyboard: 33,
+            bluetooth: true,
+            fan_profile: String::from("Balanced"),
+            battery: 80.0,
+            battery_state: String::from("Discharging"),
+            battery_rate: 1.0,
+            ram_total: 16_000_000_000,
+            ram_used: 4_000_000_000,
+            unavailable: BTreeSet::new(),
+            failure: String::from("Simulated failure"),
+        }
+    }
+}
+
+/// # Documentation
+/// A change to the simulated hardware which happens `at` seconds after the daemon starts
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ScenarioEvent {
+    pub at: f64,
+    pub volume: Option<u32>,
+    pub mute: Option<bool>,
+    pub monitor: Option<u32>,
+    pub keyboard: Option<u32>,
+    pub bluetooth: Option<bool>,
+    pub fan_profile: Option<String>,
+    pub battery: Option<f64>,
+    pub battery_state: Option<String>,
+    pub battery_rate: Option<f64>,
+    pub ram_used: Option<u64>,
+    /// Modules whose commands start failing
+    pub unavailable: Vec<SimulatedModule>,
+    /// Modules whose commands stop failing
+    pub available: Vec<SimulatedModule>,
+}
+
+/// # Documentation
+/// A scripted timeline of simulated hardware, used by `bar_daemon daemon --simulate <scenario.toml>`
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Scenario {
+    /// Seconds between updates of the simulated battery
+    pub tick: f64,
+    pub initial: SimulatedState,
+    pub events: Vec<ScenarioEvent>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            tick: 1.0,
+            initial: SimulatedState::default(),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl Scenario {
+    /// # Errors
+    /// Returns an error if the scenario file cannot be read
+    /// Returns an error if the scenario file is not a valid scenario
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, DaemonError> {
+        let scenario = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| DaemonError::PathRwError(format!("{}: {e}", path.as_ref().display())))?;
+
+        Self::parse(&scenario)
+    }
+
+    /// # Errors
+    /// Returns an error if the text is not a valid scenario
+    pub fn parse(scenario: &str) -> Result<Self, DaemonError> {
+        let mut scenario: Self = toml::from_str(scenario)?;
+
+        // Events are applied in the order they happen
+        scenario.events.sort_by(|a, b| a.at.total_cmp(&b.at));
+
+        Ok(scenario)
+    }
+}
+
+impl SimulatedState {
+    /// # Documentation
+    /// Apply an event to the state, returning the modules which it changed
+    pub fn apply(&mut self, event: &ScenarioEvent) -> BTreeSet<SimulatedModule> {
+        let mut changed = BTreeSet::new();
+
+        macro_rules! set_field {
+            ($field:ident, $module:ident) => {
+                if let Some(value) = event.$field.clone() {
+                    self.$field = value;
+                    changed.insert(SimulatedModule::$module);
+                }
+            };
+        }
+
+        set_field!(volume, Volume);
+        set_field!(mute, Volume);
+        set_field!(monitor, Brightness);
+        set_field!(keyboard, Brightness);
+        set_field!(bluetooth, Bluetooth);
+        set_field!(fan_profile, FanProfile);
+        set_field!(battery, Battery);
+        set_field!(battery_state, Battery);
+        set_field!(battery_rate, Battery);
+        set_field!(ram_used, Ram);
+
+        for module in &event.unavailable {
+            self.unavailable.insert(*module);
+            changed.insert(*module);
+        }
+
+        for module in &event.available {
+            self.unavailable.remove(module);
+            changed.insert(*module);
+        }
+
+        changed
+    }
+
+    /// # Documentation
+    /// Charge or discharge the battery for the time which has passed
+    pub fn advance(&mut self, elapsed: Duration) {
+        let change = self.battery_rate * elapsed.as_secs_f64() / 60.0;
+
+        match self.battery_state.as_str() {
+            "Discharging" => self.battery = (self.battery - change).max(0.0),
+            "Charging" => self.battery = (self.battery + change).min(100.0),
+            _ => {}
+        }
+    }
+
+    fn battery_output(&self) -> String {
+        let percent = self.battery.round() as u32;
+
+        // Get the time until the battery is empty or full, in the format which acpi uses
+        let time = |percent_left: f64| {
+            let seconds = (percent_left / self.battery_rate * 60.0).round() as u64;
+
+            format!("{:02}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+        };
+
+        match self.battery_state.as_str() {
+            "Charging" if percent >= 100 => String::from("Battery 0: Fully charged, 100%"),
+            "Charging" if self.battery_rate > 0.0 => {
+                format!(
+                    "Battery 0: Charging, {percent}%, {} until charged",
+                    time(100.0 - self.battery)
+                )
+            }
+            "Discharging" if self.battery_rate > 0.0 => {
+                format!("Battery 0: Discharging, {percent}%, {} remaining", time(self.battery))
+            }
+            state => format!("Battery 0: {state}, {percent}%"),
+        }
+    }
+
+    fn ram_output(&self) -> String {
+        let free = self.ram_total.saturating_sub(self.ram_used);
+
+        format!(
+            "               total        used        free      shared  buff/cache   available\nMem:     {} {} {free} 0 0 {free}\nSwap:    0 0 0",
+            self.ram_total, self.ram_used
+        )
+    }
+}
+
+/// # Documentation
+/// Runs commands against a `SimulatedState` instead of the system, notifications are logged instead of shown
+#[derive(Debug, Default)]
+pub struct SimulatedRunner {
+    state: Mutex<SimulatedState>,
+}
+
+impl SimulatedRunner {
+    #[must_use]
+    pub const fn new(state: SimulatedState) -> Self {
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// # Documentation
+    /// Change the simulated state, returning the result of `f`
+    pub fn update<T>(&self, f: impl FnOnce(&mut SimulatedState) -> T) -> T {
+        f(&mut self.state.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    #[must_use]
+    pub fn state(&self) -> SimulatedState {
+        self.update(|state| state.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl CommandRunner for SimulatedRunner {
+    #[allow(clippy::too_many_lines)]
+    async fn run(&self, name: &str, args: &[&str]) -> Result<String, DaemonError> {
+        let module = match name {
+            "wpctl" => SimulatedModule::Volume,
+            "brightnessctl" => SimulatedModule::Brightness,
+            "bluetooth" => SimulatedModule::Bluetooth,
+            "acpi" => SimulatedModule::Battery,
+            "free" => SimulatedModule::Ram,
+            "asusctl" => SimulatedModule::FanProfile,
+            "dunstify" => {
+                debug!("Simulated notification: {args:?}");
+                return Ok(String::new());
+            }
+            _ => return Err(DaemonError::CommandNotFound { name: name.to_string() }),
+        };
+
+        let command_error = |e: &str| DaemonError::CommandError {
+            name: name.to_string(),
+            args: args.iter().map(ToString::to_string).collect(),
+            e: e.to_string(),
+            stderr: String::new(),
+        };
+
+        let parse_percent = |percent: &str| percent.trim_end_matches('%').parse::<f64>();
+
+        self.update(|state| {
+            if state.unavailable.contains(&module) {
+                return Err(command_error(&state.failure));
+            }
+
+            Ok(match (name, args) {
+                ("wpctl", ["get-volume", _]) => format!(
+                    "Volume: {:.2}{}",
+                    linear_to_logarithmic(f64::from(state.volume)) / 100.0,
+                    if state.mute { " [MUTED]" } else { "" }
+                ),
+                ("wpctl", ["set-volume", _, percent]) => {
+                    state.volume = logarithmic_to_linear(parse_percent(percent)?).round() as u32;
+                    String::new()
+                }
+                ("wpctl", ["set-mute", _, mute]) => {
+                    state.mute = match *mute {
+                        "toggle" => !state.mute,
+                        mute => mute == "1",
+                    };
+                    String::new()
+                }
+                ("brightnessctl", ["-m", "-d", device_id, "i"]) => {
+                    let percent = if *device_id == MONITOR_ID {
+                        state.monitor
+                    } else {
+                        state.keyboard
+                    };
+
+                    format!("{device_id},backlight,{percent},{percent}%,100")
+                }
+                ("brightnessctl", ["-d", device_id, "s", percent]) => {
+                    let percent = parse_percent(percent)?.round() as u32;
+
+                    if *device_id == MONITOR_ID {
+                        state.monitor = percent;
+                    } else {
+                        state.keyboard = percent;
+                    }
+                    String::new()
+                }
+                ("bluetooth", []) => format!("bluetooth = {}", if state.bluetooth { "on" } else { "off" }),
+                ("bluetooth", [bluetooth]) => {
+                    state.bluetooth = match *bluetooth {
+                        "toggle" => !state.bluetooth,
+                        bluetooth => bluetooth == "on",
+                    };
+                    String::new()
+                }
+                ("asusctl", ["profile", "get"]) => format!("Active profile: {}", state.fan_profile),
+                ("asusctl", ["profile", "set", profile]) => {
+                    state.fan_profile = (*profile).to_string();
+                    String::new()
+                }
+                ("acpi", ["-b"]) => state.battery_output(),
+                ("free", ["-b"]) => state.ram_output(),
+                _ => return Err(command_error("Unknown arguments for simulated command")),
+            })
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        "simulated"
+    }
+}
+
+/// # Documentation
+/// Replace the command runner with a `SimulatedRunner`, and spawn a task which plays the scenario's timeline
+pub fn spawn_scenario(scenario: Scenario, shutdown_notify: Arc<tokio::sync::Notify>) {
+    let runner = Arc::new(SimulatedRunner::new(scenario.initial.clone()));
+
+    // Every source created from now on will use the simulated hardware
+    set_default_runner(runner.clone());
+
+    info!("Simulating hardware with {} scenario events", scenario.events.len());
+
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let mut last_tick = start;
+        let mut events = scenario.events.into_iter().peekable();
+
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(scenario.tick.max(0.01)));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let now = Instant::now();
+                    let elapsed = now.duration_since(start).as_secs_f64();
+
+                    // Move the battery on by the time since the last tick
+                    runner.update(|state| state.advance(now.duration_since(last_tick)));
+                    last_tick = now;
+
+                    // Apply every event which is due
+                    let mut changed = BTreeSet::new();
+                    while let Some(event) = events.next_if(|event| event.at <= elapsed) {
+                        changed.append(&mut runner.update(|state| state.apply(&event)));
+                    }
+
+                    // Read the changed modules so the changes are sent to listeners
+                    for module in changed {
+                        refresh(module).await;
+                    }
+                }
+                () = shutdown_notify.notified() => {
+                    info!("Scenario received shutdown notification");
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// # Documentation
+/// Read the latest value of a module, which updates the snapshot
+#[instrument]
+async fn refresh(module: SimulatedModule) {
+    let result = match module {
+        SimulatedModule::Volume => Volume::latest().await.map(|_| ()),
+        SimulatedModule::Brightness => Brightness::latest().await.map(|_| ()),
+        SimulatedModule::Bluetooth => Bluetooth::latest().await.map(|_| ()),
+        SimulatedModule::Battery => Battery::latest().await.map(|_| ()),
+        SimulatedModule::Ram => Ram::latest().await.map(|_| ()),
+        SimulatedModule::FanProfile => FanProfile::latest().await.map(|_| ()),
+    };
+
+    if let Err(e) = result {
+        error!("Simulated module {module:?} could not be refreshed: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Scenario, ScenarioEvent, SimulatedModule, SimulatedRunner, SimulatedState};
+    use crate::{command::CommandRunner, error::DaemonError};
+
+    #[test]
+    fn example_scenario_is_valid() {
+        let scenario = Scenario::parse(include_str!("../default/scenario.toml"));
+
+        assert!(
+            scenario.as_ref().is_ok_and(|scenario| !scenario.events.is_empty()),
+            "{scenario:?}"
+        );
+    }
+
+    #[test]
+    fn events_are_sorted_and_applied() {
+        let scenario = Scenario::parse(
+            r#"
+            [[events]]
+            at = 20
+            available = ["bluetooth"]
+
+            [[events]]
+            at = 10
+            volume = 70
+            unavailable = ["bluetooth"]
+            "#,
+        );
+        let Ok(scenario) = scenario else {
+            panic!("{scenario:?}");
+        };
+
+        assert_eq!(scenario.events.iter().map(|event| event.at).collect::<Vec<_>>(), [10.0, 20.0]);
+
+        let mut state = SimulatedState::default();
+        let changed = state.apply(&scenario.events[0]);
+
+        assert_eq!(state.volume, 70);
+        assert!(state.unavailable.contains(&SimulatedModule::Bluetooth));
+        assert_eq!(
+            changed.into_iter().collect::<Vec<_>>(),
+            [SimulatedModule::Volume, SimulatedModule::Bluetooth]
+        );
+
+        let _changed = state.apply(&scenario.events[1]);
+        assert!(state.unavailable.is_empty());
+    }
+
+    #[test]
+    fn battery_discharges_at_rate() {
+        let mut state = SimulatedState {
+            battery: 50.0,
+            battery_rate: 1.0,
+            ..SimulatedState::default()
+        };
+
+        state.advance(Duration::from_mins(10));
+        assert!((state.battery - 40.0).abs() < f64::EPSILON, "{}", state.battery);
+        assert_eq!(state.battery_output(), "Battery 0: Discharging, 40%, 00:40:00 remaining");
+
+        state.apply(&ScenarioEvent {
+            battery_state: Some(String::from("Charging")),
+            ..ScenarioEvent::default()
+        });
+        state.advance(Duration::from_mins(100));
+        assert_eq!(state.battery_output(), "Battery 0: Fully charged, 100%");
+    }
+
+    #[tokio::test]
+    async fn commands_read_and_change_state() {
+        let runner = SimulatedRunner::new(SimulatedState::default());
+
+        assert_eq!(
+            runner.run("wpctl", &["get-volume", "@DEFAULT_SINK@"]).await.ok().as_deref(),
+            Some("Volume: 0.71")
+        );
+
+        let _ = runner
+            .run("wpctl", &["set-volume", "@DEFAULT_SINK@", "83.66600265340756%"])
+            .await;
+        let _ = runner.run("wpctl", &["set-mute", "@DEFAULT_SINK@", "toggle"]).await;
+        let _ = runner.run("bluetooth", &["off"]).await;
+        let _ = runner.run("asusctl", &["profile", "set", "Quiet"]).await;
+
+        let state = runner.state();
+        assert_eq!(
+            (state.volume, state.mute, state.bluetooth, state.fan_profile.as_str()),
+            (70, true, false, "Quiet")
+        );
+
+        assert_eq!(runner.run("bluetooth", &[]).await.ok().as_deref(), Some("bluetooth = off"));
+        assert!(matches!(
+            runner.run("wpctl", &["status"]).await,
+            Err(DaemonError::CommandError { .. })
+        ));
+        assert!(matches!(
+            runner.run("pactl", &[]).await,
+            Err(DaemonError::CommandNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn unavailable_modules_fail() {
+        let runner = SimulatedRunner::new(SimulatedState::default());
+        runner.update(|state| {
+            state.apply(&ScenarioEvent {
+                unavailable: vec![SimulatedModule::Ram],
+                ..ScenarioEvent::default()
+            })
+        });
+
+        assert!(matches!(
+            runner.run("free", &["-b"]).await,
+            Err(DaemonError::CommandError { .. })
+        ));
+        assert!(runner.run("acpi", &["-b"]).await.is_ok());
+    }
+}
@@ -1,9 +1,10 @@
 use std::{
     any::type_name,
     sync::{Arc, LazyLock},
-    time::Instant,
+    time::{Duration, SystemTime},
 };
 
+use serde::{Deserialize, Serialize};
 use tokio::sync::{RwLock, broadcast};
 use tracing::{info, instrument, warn};
 
@@ -11,17 +12,92 @@ use crate::{
     battery::Battery,
     bluetooth::Bluetooth,
     brightness::Brightness,
+    error::DaemonError,
     fan_profile::FanProfile,
+    history::record_event,
     monitored::{Monitored, MonitoredUpdate, update_monitored},
     notification::Notify,
     observed::{
-        Observed::{self, Unavailable},
-        spawn_read_until_valid,
+        Observed::{self, Unavailable, Valid},
+        UnavailableReason, spawn_read_until_valid,
     },
     ram::Ram,
+    tuples::{TUPLE_NAMES, TupleName},
     volume::Volume,
 };
 
+/// # Documentation
+/// The most recent `Valid` value of each `Monitored` type, which is kept when the value becomes unavailable
+#[derive(Clone, Debug, Default)]
+pub struct LastKnown {
+    pub battery: Option<Battery>,
+    pub bluetooth: Option<Bluetooth>,
+    pub brightness: Option<Brightness>,
+    pub fan_profile: Option<FanProfile>,
+    pub ram: Option<Ram>,
+    pub volume: Option<Volume>,
+}
+
+/// # Documentation
+/// When a module's value was last read, and last read as `Valid`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ModuleTimestamps {
+    /// Last time the value was read, whether or not it changed
+    pub last_updated: Option<SystemTime>,
+    /// Last time the value was read as `Valid`
+    pub last_valid: Option<SystemTime>,
+    /// Whether the value's poller hasn't read it for longer than the config's `stale_after`
+    pub stale: bool,
+}
+
+impl ModuleTimestamps {
+    /// # Documentation
+    /// Remember that the value was read at `now`, which means it is no longer stale
+    pub const fn read(&mut self, valid: bool, now: SystemTime) {
+        self.last_updated = Some(now);
+        if valid {
+            self.last_valid = Some(now);
+        }
+        self.stale = false;
+    }
+
+    /// # Documentation
+    /// Whether the value was last read more than `stale_after` before `now` (Values which haven't been read can't be stale)
+    #[must_use]
+    pub fn is_older_than(&self, stale_after: Duration, now: SystemTime) -> bool {
+        self.last_updated
+            .is_some_and(|last_updated| now.duration_since(last_updated).is_ok_and(|age| age > stale_after))
+    }
+
+    /// # Documentation
+    /// The timestamps as tuples, which are added to the tuples of the module's value (Times are in seconds since the Unix epoch)
+    #[must_use]
+    pub fn to_tuples(&self) -> Vec<(String, String)> {
+        let seconds = |time: Option<SystemTime>| {
+            time.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map_or_else(|| String::from("?"), |since_epoch| since_epoch.as_secs().to_string())
+        };
+
+        vec![
+            (String::from("_last_updated"), seconds(self.last_updated)),
+            (String::from("_last_valid"), seconds(self.last_valid)),
+            (String::from("_stale"), self.stale.to_string()),
+        ]
+    }
+}
+
+/// # Documentation
+/// The `ModuleTimestamps` of each `Monitored` type
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timestamps {
+    pub battery: ModuleTimestamps,
+    pub bluetooth: ModuleTimestamps,
+    pub brightness: ModuleTimestamps,
+    pub fan_profile: ModuleTimestamps,
+    pub ram: ModuleTimestamps,
+    pub volume: ModuleTimestamps,
+}
+
 #[derive(Clone, Debug)]
 pub struct Snapshot {
     pub battery: Observed<Battery>,
@@ -30,23 +106,72 @@ pub struct Snapshot {
     pub fan_profile: Observed<FanProfile>,
     pub ram: Observed<Ram>,
     pub volume: Observed<Volume>,
-    pub timestamp: Instant,
+    pub last_known: LastKnown,
+    pub timestamps: Timestamps,
+    /// Number of changes made to the snapshot, which goes up by one for every `SnapshotEvent` broadcast
+    pub sequence: u64,
 }
 
 impl Default for Snapshot {
     fn default() -> Self {
         Self {
-            battery: Unavailable,
-            bluetooth: Unavailable,
-            brightness: Unavailable,
-            fan_profile: Unavailable,
-            ram: Unavailable,
-            volume: Unavailable,
-            timestamp: Instant::now(),
+            battery: Unavailable(UnavailableReason::not_read()),
+            bluetooth: Unavailable(UnavailableReason::not_read()),
+            brightness: Unavailable(UnavailableReason::not_read()),
+            fan_profile: Unavailable(UnavailableReason::not_read()),
+            ram: Unavailable(UnavailableReason::not_read()),
+            volume: Unavailable(UnavailableReason::not_read()),
+            last_known: LastKnown::default(),
+            timestamps: Timestamps::default(),
+            sequence: 0,
         }
     }
 }
 
+impl Snapshot {
+    /// # Documentation
+    /// A snapshot where every value is `Unavailable` for the same `reason`
+    #[must_use]
+    pub fn unavailable(reason: &UnavailableReason) -> Self {
+        Self {
+            battery: Unavailable(reason.clone()),
+            bluetooth: Unavailable(reason.clone()),
+            brightness: Unavailable(reason.clone()),
+            fan_profile: Unavailable(reason.clone()),
+            ram: Unavailable(reason.clone()),
+            volume: Unavailable(reason.clone()),
+            ..Self::default()
+        }
+    }
+
+    /// # Documentation
+    /// The tuples of every module with its timestamps, in the order of `TUPLE_NAMES`
+    #[must_use]
+    pub fn into_tuples(self) -> Vec<(String, Vec<(String, String)>)> {
+        let module = |tuple_name: TupleName, mut tuples: Vec<(String, String)>, timestamps: &ModuleTimestamps| {
+            tuples.extend(timestamps.to_tuples());
+            (TUPLE_NAMES[tuple_name as usize].to_string(), tuples)
+        };
+
+        vec![
+            module(TupleName::Volume, self.volume.to_tuples(), &self.timestamps.volume),
+            module(
+                TupleName::Brightness,
+                self.brightness.to_tuples(),
+                &self.timestamps.brightness,
+            ),
+            module(TupleName::Bluetooth, self.bluetooth.to_tuples(), &self.timestamps.bluetooth),
+            module(TupleName::Battery, self.battery.to_tuples(), &self.timestamps.battery),
+            module(TupleName::Ram, self.ram.to_tuples(), &self.timestamps.ram),
+            module(
+                TupleName::FanProfile,
+                self.fan_profile.to_tuples(),
+                &self.timestamps.fan_profile,
+            ),
+        ]
+    }
+}
+
 static CURRENT_SNAPSHOT: LazyLock<Arc<RwLock<Snapshot>>> = LazyLock::new(|| Arc::new(RwLock::new(Snapshot::default())));
 
 #[must_use]
@@ -63,15 +188,15 @@ pub async fn update_snapshot<M: Monitored + IntoSnapshotEvent + Notify<M>>(new_v
         update_monitored(&mut snapshot, new_value)
     };
 
-    // Spawn task to run read_until_valid if the new value is Unavailable (If the value isn't recovering)
-    if update.new == Unavailable && !update.old.is_recovering() {
+    // Spawn task to run read_until_valid if the new value is Unavailable (If the value isn't already being retried)
+    if matches!(update.new, Unavailable(_)) && !update.old.is_retrying() {
         info!("Spawning task to read {} until it is Valid: {update:?}", type_name::<M>());
 
         spawn_read_until_valid::<M>();
     }
 
-    // If the update changed something, and the old value isn't Recovering
-    if update.new != update.old && !update.old.is_recovering() {
+    // If the update changed something, and the old value isn't being retried
+    if update.new != update.old && !update.old.is_retrying() {
         // Send notification (if notify is implemented)
         match M::notify(update.clone()).await {
             Ok(()) => {}
@@ -82,6 +207,69 @@ pub async fn update_snapshot<M: Monitored + IntoSnapshotEvent + Notify<M>>(new_v
     update
 }
 
+/// # Documentation
+/// Add the current timestamps of `M` to its `tuples`
+pub async fn with_timestamps<M: Monitored>(mut tuples: Vec<(String, String)>) -> Vec<(String, String)> {
+    tuples.extend(M::timestamps(&mut current_snapshot().await).to_tuples());
+    tuples
+}
+
+/// # Documentation
+/// Mark `M` as stale if it hasn't been read for longer than `stale_after`, broadcasting its value again so listeners can show it
+#[instrument]
+pub async fn mark_if_stale<M: Monitored + IntoSnapshotEvent>(stale_after: Duration) {
+    let mut snapshot = CURRENT_SNAPSHOT.write().await;
+
+    let timestamps = M::timestamps(&mut snapshot);
+    if timestamps.stale || !timestamps.is_older_than(stale_after, SystemTime::now()) {
+        return;
+    }
+    timestamps.stale = true;
+
+    warn!(
+        "Value of type {} hasn't been read for over {stale_after:?}, marking it as stale",
+        type_name::<M>()
+    );
+
+    let current = M::get(&snapshot);
+    snapshot.sequence += 1;
+
+    broadcast_snapshot_event(M::into_event(MonitoredUpdate {
+        old: current.clone(),
+        new: current,
+        sequence: snapshot.sequence,
+        timestamps: *M::timestamps(&mut snapshot),
+    }));
+}
+
+/// # Documentation
+/// Get the value of `M` which changes should be made relative to.
+/// This is the current value if it is `Valid`, otherwise the latest value is read with `read` (The `read()` of the source making the change,
+/// so it uses that source's runner), falling back to the last known `Valid` value.
+/// Returns `None` if `M` has never been `Valid`
+pub async fn known_value<M: Monitored, F: Future<Output = Result<Observed<M>, DaemonError>>>(read: F) -> Option<M> {
+    if let Valid(current) = M::get(&current_snapshot().await) {
+        return Some(current);
+    }
+
+    match read.await {
+        Ok(Valid(latest)) => Some(latest),
+        // Use the last known value (Which may have been updated by read)
+        Ok(_) | Err(_) => M::last_known(&current_snapshot().await),
+    }
+}
+
+/// # Documentation
+/// Get the value of `M` which relative changes should be made to, see `known_value()`
+///
+/// # Errors
+/// Returns an error if `M` has never been `Valid`
+pub fn require_known<M: Monitored>(known: Option<&M>) -> Result<M, DaemonError> {
+    known
+        .cloned()
+        .ok_or_else(|| DaemonError::NoKnownValue(type_name::<M>().to_string()))
+}
+
 static SNAPSHOT_EVENTS: LazyLock<broadcast::Sender<SnapshotEvent>> = LazyLock::new(|| {
     let (tx, _) = broadcast::channel(64);
     tx
@@ -92,6 +280,8 @@ pub fn subscribe_snapshot() -> broadcast::Receiver<SnapshotEvent> {
 }
 
 pub fn broadcast_snapshot_event(event: SnapshotEvent) {
+    record_event(&event);
+
     // Drop since only returns Err when there are no receivers
     let _ = SNAPSHOT_EVENTS.send(event);
 }
@@ -106,6 +296,67 @@ pub enum SnapshotEvent {
     Volume(MonitoredUpdate<Volume>),
 }
 
+impl SnapshotEvent {
+    /// # Documentation
+    /// The sequence number of this change, which is one more than the change before it
+    #[must_use]
+    pub const fn sequence(&self) -> u64 {
+        match self {
+            Self::Battery(update) => update.sequence,
+            Self::Bluetooth(update) => update.sequence,
+            Self::Brightness(update) => update.sequence,
+            Self::FanProfile(update) => update.sequence,
+            Self::Ram(update) => update.sequence,
+            Self::Volume(update) => update.sequence,
+        }
+    }
+
+    /// # Documentation
+    /// The module which changed
+    #[must_use]
+    pub const fn module(&self) -> TupleName {
+        match self {
+            Self::Battery(_) => TupleName::Battery,
+            Self::Bluetooth(_) => TupleName::Bluetooth,
+            Self::Brightness(_) => TupleName::Brightness,
+            Self::FanProfile(_) => TupleName::FanProfile,
+            Self::Ram(_) => TupleName::Ram,
+            Self::Volume(_) => TupleName::Volume,
+        }
+    }
+
+    /// # Documentation
+    /// The module which changed, and its new value and timestamps as tuples
+    #[must_use]
+    pub fn into_tuples(self) -> (TupleName, Vec<(String, String)>) {
+        let (tuple_name, mut tuples, timestamps) = match self {
+            Self::Battery(update) => (TupleName::Battery, update.new.to_tuples(), update.timestamps),
+            Self::Bluetooth(update) => (TupleName::Bluetooth, update.new.to_tuples(), update.timestamps),
+            Self::Brightness(update) => (TupleName::Brightness, update.new.to_tuples(), update.timestamps),
+            Self::FanProfile(update) => (TupleName::FanProfile, update.new.to_tuples(), update.timestamps),
+            Self::Ram(update) => (TupleName::Ram, update.new.to_tuples(), update.timestamps),
+            Self::Volume(update) => (TupleName::Volume, update.new.to_tuples(), update.timestamps),
+        };
+        tuples.extend(timestamps.to_tuples());
+
+        (tuple_name, tuples)
+    }
+
+    /// # Documentation
+    /// The timestamps of the module which changed
+    #[must_use]
+    pub const fn timestamps(&self) -> ModuleTimestamps {
+        match self {
+            Self::Battery(update) => update.timestamps,
+            Self::Bluetooth(update) => update.timestamps,
+            Self::Brightness(update) => update.timestamps,
+            Self::FanProfile(update) => update.timestamps,
+            Self::Ram(update) => update.timestamps,
+            Self::Volume(update) => update.timestamps,
+        }
+    }
+}
+
 pub trait IntoSnapshotEvent: Monitored {
     fn into_event(update: MonitoredUpdate<Self>) -> SnapshotEvent;
 }
@@ -0,0 +1,201 @@
+use std::{
+    fs::{self, Permissions},
+    os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{instrument, warn};
+
+use crate::{config::get_config, error::DaemonError};
+
+const SOCKET_DIR: &str = "bar_daemon";
+const SOCKET_NAME: &str = "bar_daemon.sock";
+
+/// # Documentation
+/// The socket path used when neither `--socket` nor the config give one.
+/// This is `$XDG_RUNTIME_DIR/bar_daemon/bar_daemon.sock`, or a directory in /tmp/ which belongs to this user when `$XDG_RUNTIME_DIR` isn't set
+#[must_use]
+pub fn default_socket_path() -> PathBuf {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()).map_or_else(
+        || std::env::temp_dir().join(format!("{SOCKET_DIR}-{}", current_uid())),
+        |dir| PathBuf::from(dir).join(SOCKET_DIR),
+    );
+
+    runtime_dir.join(SOCKET_NAME)
+}
+
+/// # Documentation
+/// The socket path from the config, or the default socket path
+#[must_use]
+pub fn socket_path() -> PathBuf {
+    get_config().socket_path.unwrap_or_else(default_socket_path)
+}
+
+/// # Documentation
+/// Bind a listener at `socket_path` which only this user can connect to.
+/// The parent directory is created (only accessible by this user) if it doesn't exist, and a stale socket is replaced.
+/// The socket is only bound once its directory is known to be private, so no other user can connect before its permissions are set
+///
+/// # Errors
+/// Returns an error if the parent directory can't be created, or isn't private to this user
+/// Returns an error if something other than a socket exists at `socket_path`
+/// Returns an error if the listener can't be bound, or its permissions can't be set
+#[instrument]
+pub fn bind_socket(socket_path: &Path) -> Result<UnixListener, DaemonError> {
+    create_socket_dir(socket_path)?;
+
+    // Remove an existing socket, but never anything else which is at the path
+    match fs::symlink_metadata(socket_path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(socket_path)?,
+        Ok(_) => {
+            return Err(DaemonError::PathRwError(format!(
+                "{} exists and is not a socket",
+                socket_path.display()
+            )));
+        }
+        Err(_) => {}
+    }
+
+    let listener = UnixListener::bind(socket_path)?;
+
+    // Only allow this user to connect to the socket
+    fs::set_permissions(socket_path, Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+}
+
+/// # Documentation
+/// Create the directory which holds the socket (only accessible by this user) if it doesn't exist,
+/// and check that it is a directory (not a symlink) which belongs to this user and has mode 700
+///
+/// # Errors
+/// Returns an error if the directory can't be created
+/// Returns an error if the directory is a symlink, belongs to another user, or other users can access it
+pub fn create_socket_dir(socket_path: &Path) -> Result<(), DaemonError> {
+    let Some(parent) = socket_path.parent().filter(|parent| !parent.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+
+    if !parent.exists() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .map_err(|e| DaemonError::PathCreateError(format!("{}: {e}", parent.display())))?;
+    }
+
+    // The directory may have existed already (or been swapped since it was created), so check what is there now
+    let metadata = fs::symlink_metadata(parent).map_err(|e| DaemonError::PathRwError(format!("{}: {e}", parent.display())))?;
+    let mode = metadata.permissions().mode() & 0o777;
+
+    let problem = if !metadata.file_type().is_dir() {
+        Some(String::from("is not a directory"))
+    } else if metadata.uid() != current_uid() {
+        Some(format!("belongs to another user ({})", metadata.uid()))
+    } else if mode != 0o700 {
+        Some(format!("has mode {mode:o} instead of 700"))
+    } else {
+        None
+    };
+
+    match problem {
+        Some(problem) => Err(DaemonError::PathRwError(format!(
+            "{} {problem}, so the socket would not be private",
+            parent.display()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// # Documentation
+/// Check that the process on the other end of `stream` belongs to the same user as the daemon
+#[must_use]
+pub fn is_same_user(stream: &UnixStream) -> bool {
+    match stream.peer_cred() {
+        Ok(cred) => cred.uid() == current_uid(),
+        Err(e) => {
+            warn!("Could not get credentials of connecting client: {e}");
+            false
+        }
+    }
+}
+
+pub(crate) fn current_uid() -> u32 {
+    // SAFETY: geteuid() is always successful and has no side effects
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use tokio::net::UnixStream;
+
+    use super::{bind_socket, is_same_user};
+    use crate::error::DaemonError;
+
+    #[tokio::test]
+    async fn socket_is_private_to_user() {
+        let dir = std::env::temp_dir().join(format!("bar_daemon_socket_test_{}", std::process::id()));
+        let socket_path = dir.join("private").join("bar_daemon.sock");
+
+        let listener = bind_socket(&socket_path);
+        assert!(listener.is_ok(), "{listener:?}");
+
+        let mode = |path| fs::metadata(path).map(|metadata| metadata.permissions().mode() & 0o777).ok();
+        assert_eq!(mode(&socket_path), Some(0o600));
+        assert_eq!(mode(&dir.join("private")), Some(0o700));
+
+        // A stale socket is replaced
+        drop(listener);
+        assert!(bind_socket(&socket_path).is_ok());
+
+        // Anything else is left alone
+        let file_path = dir.join("private").join("not_a_socket");
+        let _ = fs::write(&file_path, "");
+        assert!(matches!(bind_socket(&file_path), Err(DaemonError::PathRwError(_))));
+        assert!(file_path.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn socket_dir_must_be_private() {
+        let dir = std::env::temp_dir().join(format!("bar_daemon_socket_dir_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::create_dir_all(&dir);
+        let _ = fs::set_permissions(&dir, fs::Permissions::from_mode(0o700));
+
+        // A directory which other users can access is refused, and left as it is
+        let shared = dir.join("shared");
+        let _ = fs::create_dir(&shared);
+        let _ = fs::set_permissions(&shared, fs::Permissions::from_mode(0o755));
+        assert!(matches!(
+            bind_socket(&shared.join("bar_daemon.sock")),
+            Err(DaemonError::PathRwError(_))
+        ));
+        assert!(!shared.join("bar_daemon.sock").exists());
+
+        // So is a symlink, even to a private directory
+        let private = dir.join("private");
+        let link = dir.join("link");
+        let _ = fs::create_dir(&private);
+        let _ = fs::set_permissions(&private, fs::Permissions::from_mode(0o700));
+        let _ = std::os::unix::fs::symlink(&private, &link);
+        assert!(matches!(
+            bind_socket(&link.join("bar_daemon.sock")),
+            Err(DaemonError::PathRwError(_))
+        ));
+        assert!(bind_socket(&private.join("bar_daemon.sock")).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn same_user_is_accepted() {
+        let (stream, _other) = UnixStream::pair().unwrap_or_else(|e| panic!("{e}"));
+
+        assert!(is_same_user(&stream));
+    }
+}
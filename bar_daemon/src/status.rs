@@ -0,0 +1,216 @@
+use std::{
+    fmt::Write,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery, bluetooth, brightness,
+    command::default_runner,
+    config::get_config,
+    fan_profile,
+    listener::{ListenerMetrics, SharedClients, listener_metrics},
+    observed::{
+        Observed::{self, Failed, Recovering, Unavailable, Valid},
+        UnavailableReason,
+    },
+    ram,
+    snapshot::{ModuleTimestamps, current_snapshot},
+    tuples::{TUPLE_NAMES, TupleName},
+    volume,
+};
+
+/// Time which the daemon started at, used for its uptime
+static STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// # Documentation
+/// Remember that the daemon started now, so its uptime can be shown
+pub fn mark_started() {
+    *STARTED.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+}
+
+/// # Documentation
+/// The health of the daemon, shown by `bar_daemon status`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonStatus {
+    pub version: String,
+    pub uptime: Duration,
+    pub modules: Vec<ModuleStatus>,
+    /// Number of clients connected with `listen` or `subscribe`
+    pub listeners: usize,
+    pub listener_metrics: ListenerMetrics,
+}
+
+/// # Documentation
+/// Where a module's value comes from, and why it may be unavailable
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModuleStatus {
+    pub module: TupleName,
+    /// Command which the value is read with, and where it is run (e.g. `wpctl (system)`)
+    pub source: String,
+    /// `Valid`, `Unavailable`, `Recovering`, or `Failed`
+    pub state: String,
+    /// Why the value is unavailable, or why it last failed to be read
+    pub last_error: Option<UnavailableReason>,
+    /// Time between polls, or `None` for values which are read when they change
+    pub poll_interval: Option<Duration>,
+    pub timestamps: ModuleTimestamps,
+}
+
+/// # Documentation
+/// Get the status of the daemon and each of its modules
+pub async fn daemon_status(clients: &SharedClients) -> DaemonStatus {
+    let snapshot = current_snapshot().await;
+    let times = snapshot.timestamps;
+    let poll_interval = Some(Duration::from_millis(get_config().polling_rate));
+
+    let modules = vec![
+        module_status(TupleName::Volume, volume::COMMAND, &snapshot.volume, times.volume, None),
+        module_status(
+            TupleName::Brightness,
+            brightness::COMMAND,
+            &snapshot.brightness,
+            times.brightness,
+            None,
+        ),
+        module_status(
+            TupleName::Bluetooth,
+            bluetooth::COMMAND,
+            &snapshot.bluetooth,
+            times.bluetooth,
+            None,
+        ),
+        module_status(
+            TupleName::Battery,
+            battery::COMMAND,
+            &snapshot.battery,
+            times.battery,
+            poll_interval,
+        ),
+        module_status(TupleName::Ram, ram::COMMAND, &snapshot.ram, times.ram, poll_interval),
+        module_status(
+            TupleName::FanProfile,
+            fan_profile::COMMAND,
+            &snapshot.fan_profile,
+            times.fan_profile,
+            poll_interval,
+        ),
+    ];
+
+    let uptime = STARTED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .map_or(Duration::ZERO, |started| started.elapsed());
+
+    DaemonStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime,
+        modules,
+        listeners: clients.lock().await.len(),
+        listener_metrics: listener_metrics(),
+    }
+}
+
+fn module_status<T>(
+    module: TupleName,
+    command: &str,
+    observed: &Observed<T>,
+    timestamps: ModuleTimestamps,
+    poll_interval: Option<Duration>,
+) -> ModuleStatus {
+    let state = match observed {
+        Valid(_) => "Valid",
+        Unavailable(_) => "Unavailable",
+        Recovering => "Recovering",
+        Failed { .. } => "Failed",
+    };
+
+    ModuleStatus {
+        module,
+        source: format!("{command} ({})", default_runner().kind()),
+        state: state.to_string(),
+        last_error: observed.reason().cloned(),
+        poll_interval,
+        timestamps,
+    }
+}
+
+/// # Documentation
+/// Format a duration in the largest units which fit, e.g. `1h 2m 3s`
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m {seconds}s"),
+        _ => format!("{hours}h {minutes}m {seconds}s"),
+    }
+}
+
+fn format_age(time: Option<SystemTime>, now: SystemTime) -> String {
+    time.map_or_else(
+        || String::from("never"),
+        |time| format!("{} ago", format_duration(now.duration_since(time).unwrap_or_default())),
+    )
+}
+
+impl std::fmt::Display for DaemonStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let now = SystemTime::now();
+        let metrics = &self.listener_metrics;
+
+        writeln!(f, "bar_daemon {} (Up {})", self.version, format_duration(self.uptime))?;
+        writeln!(
+            f,
+            "Listeners: {} ({} frames dropped, {} disconnected for being slow, {} events missed)",
+            self.listeners, metrics.dropped_frames, metrics.slow_disconnects, metrics.lagged_events
+        )?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<12} {:<12} {:<26} {:<8} {:<14} {:<14} Error",
+            "Module", "State", "Source", "Polled", "Last Read", "Last Valid"
+        )?;
+
+        for module in &self.modules {
+            let mut state = module.state.clone();
+            if module.timestamps.stale {
+                let _ = write!(state, " (Stale)");
+            }
+
+            writeln!(
+                f,
+                "{:<12} {:<12} {:<26} {:<8} {:<14} {:<14} {}",
+                TUPLE_NAMES[module.module as usize],
+                state,
+                module.source,
+                module
+                    .poll_interval
+                    .map_or_else(|| String::from("No"), |interval| format!("{}ms", interval.as_millis())),
+                format_age(module.timestamps.last_updated, now),
+                format_age(module.timestamps.last_valid, now),
+                module.last_error.as_ref().map_or_else(String::new, ToString::to_string),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::format_duration;
+
+    #[test]
+    fn durations_use_largest_units() {
+        assert_eq!(format_duration(Duration::from_secs(5)), "5s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_duration(Duration::from_secs(3600 + 125)), "1h 2m 5s");
+    }
+}
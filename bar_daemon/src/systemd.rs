@@ -0,0 +1,359 @@
+use std::{
+    ffi::{OsStr, OsString},
+    os::{
+        fd::FromRawFd,
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::net::UnixListener;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    config::get_config,
+    error::DaemonError,
+    observed::Observed,
+    polled::pollers_alive,
+    snapshot::{Snapshot, current_snapshot, subscribe_snapshot},
+    tuples::TUPLE_NAMES,
+};
+
+/// First file descriptor passed by systemd socket activation
+const LISTEN_FDS_START: i32 = 3;
+
+/// Variables which systemd sets for the daemon, which commands that the daemon runs shouldn't inherit
+const SYSTEMD_VARIABLES: &[&str] = &[
+    "LISTEN_PID",
+    "LISTEN_FDS",
+    "LISTEN_FDNAMES",
+    "NOTIFY_SOCKET",
+    "WATCHDOG_PID",
+    "WATCHDOG_USEC",
+];
+
+/// What systemd passed to the daemon in its environment
+#[derive(Debug, Clone, Default)]
+pub struct SystemdEnv {
+    /// Number of sockets passed by socket activation (`LISTEN_FDS`), 0 when they weren't passed to this process
+    pub listen_fds: i32,
+    /// Socket which states are sent to (`NOTIFY_SOCKET`), when started by a `Type=notify` service
+    pub notify_socket: Option<OsString>,
+    /// How often the watchdog should be pinged (Half of `WatchdogSec=`), or `None` if the watchdog isn't enabled for this process
+    pub watchdog_interval: Option<Duration>,
+}
+
+impl SystemdEnv {
+    /// # Documentation
+    /// Read what systemd passed to this process, then remove the variables (Like `sd_listen_fds(1)` and `sd_notify(1, ...)`),
+    /// so commands which the daemon runs don't inherit them
+    ///
+    /// # Safety
+    /// Modifies the environment, so no other thread may be running (e.g. call it in `main` before the tokio runtime is built)
+    #[must_use]
+    pub unsafe fn take() -> Self {
+        let this_process = |variable: &str| std::env::var(variable).is_ok_and(|pid| pid == std::process::id().to_string());
+
+        // The file descriptors are only for this process if LISTEN_PID is this process
+        let listen_fds = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|fds| fds.parse::<i32>().ok())
+            .filter(|_| this_process("LISTEN_PID"))
+            .unwrap_or(0);
+
+        // The watchdog is only for this process if WATCHDOG_PID is unset or this process
+        let watchdog_interval = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && (std::env::var_os("WATCHDOG_PID").is_none() || this_process("WATCHDOG_PID")))
+            .map(|usec| Duration::from_micros(usec / 2));
+
+        let notify_socket = std::env::var_os("NOTIFY_SOCKET");
+
+        for variable in SYSTEMD_VARIABLES {
+            // SAFETY: The caller guarantees that no other thread is running, so nothing reads the environment at the same time
+            unsafe { std::env::remove_var(variable) };
+        }
+
+        Self {
+            listen_fds,
+            notify_socket,
+            watchdog_interval,
+        }
+    }
+
+    /// # Documentation
+    /// Send a state (e.g. `READY=1`) to systemd, does nothing if the daemon wasn't started by a `Type=notify` service
+    pub fn notify(&self, state: &str) {
+        let Some(notify_socket) = &self.notify_socket else {
+            return;
+        };
+
+        match send_state(notify_socket, state) {
+            Ok(_) => debug!("Sent '{state}' to systemd"),
+            Err(e) => warn!("Could not send '{state}' to systemd: {e}"),
+        }
+    }
+}
+
+/// # Documentation
+/// Take the listener passed by systemd socket activation, if the daemon was socket activated
+///
+/// # Errors
+/// Returns an error if the passed file descriptor isn't a listening Unix stream socket, or can't be used as a listener
+#[instrument]
+pub fn activated_listener(systemd: &SystemdEnv) -> Result<Option<UnixListener>, DaemonError> {
+    let fd_count = systemd.listen_fds;
+
+    if fd_count < 1 {
+        return Ok(None);
+    }
+
+    if fd_count > 1 {
+        warn!("{fd_count} sockets were passed by systemd, only the first is used");
+    }
+
+    // Make sure the file descriptor is what the socket unit should pass, before taking ownership of it
+    check_listening_socket(LISTEN_FDS_START)?;
+
+    // SAFETY: systemd passes ownership of the listening socket at LISTEN_FDS_START, and nothing else uses it
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+
+    info!("Using socket passed by systemd");
+
+    Ok(Some(UnixListener::from_std(listener)?))
+}
+
+/// # Documentation
+/// Check that `fd` is a Unix stream socket which is listening (e.g. not a datagram socket, or a file)
+///
+/// # Errors
+/// Returns an error if `fd` isn't open, or isn't a listening Unix stream socket
+fn check_listening_socket(fd: i32) -> Result<(), DaemonError> {
+    let invalid = |problem: &str| {
+        DaemonError::SocketError(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("File descriptor {fd} passed by systemd {problem}"),
+        ))
+    };
+
+    // SAFETY: fstat only writes to the stat struct, which is zeroed (a valid value) beforehand
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &raw mut stat) } != 0 {
+        return Err(DaemonError::SocketError(std::io::Error::last_os_error()));
+    }
+    if stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(invalid("is not a socket"));
+    }
+
+    let option = |name: i32| -> Result<i32, DaemonError> {
+        let mut value: i32 = 0;
+        let mut length = std::mem::size_of::<i32>() as libc::socklen_t;
+
+        // SAFETY: The value and its length point to an i32, which is the size of these options
+        let result = unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, name, (&raw mut value).cast(), &raw mut length) };
+        if result == 0 {
+            Ok(value)
+        } else {
+            Err(DaemonError::SocketError(std::io::Error::last_os_error()))
+        }
+    };
+
+    if option(libc::SO_DOMAIN)? != libc::AF_UNIX {
+        return Err(invalid("is not a Unix socket"));
+    }
+    if option(libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return Err(invalid("is not a stream socket"));
+    }
+    if option(libc::SO_ACCEPTCONN)? == 0 {
+        return Err(invalid("is not listening"));
+    }
+
+    Ok(())
+}
+
+fn send_state(notify_socket: &OsStr, state: &str) -> std::io::Result<usize> {
+    // Sockets starting with '@' are in the abstract namespace
+    let address = match notify_socket.as_encoded_bytes() {
+        [b'@', name @ ..] => SocketAddr::from_abstract_name(name)?,
+        _ => SocketAddr::from_pathname(notify_socket)?,
+    };
+
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)
+}
+
+/// # Documentation
+/// Describe the modules which are unavailable in the snapshot, for `STATUS=`
+#[must_use]
+pub fn status(snapshot: &Snapshot) -> String {
+    fn describe<T>(name: &str, observed: &Observed<T>) -> Option<String> {
+        match observed {
+            Observed::Valid(_) => None,
+            Observed::Recovering => Some(format!("{name} (Recovering)")),
+            _ => Some(format!(
+                "{name} ({})",
+                observed.reason().map_or_else(String::new, |reason| reason.kind.to_string())
+            )),
+        }
+    }
+
+    let unavailable = [
+        describe(TUPLE_NAMES[0], &snapshot.volume),
+        describe(TUPLE_NAMES[1], &snapshot.brightness),
+        describe(TUPLE_NAMES[2], &snapshot.bluetooth),
+        describe(TUPLE_NAMES[3], &snapshot.battery),
+        describe(TUPLE_NAMES[4], &snapshot.ram),
+        describe(TUPLE_NAMES[5], &snapshot.fan_profile),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    if unavailable.is_empty() {
+        String::from("All modules available")
+    } else {
+        format!("Unavailable: {}", unavailable.join(", "))
+    }
+}
+
+/// # Documentation
+/// Spawn tasks which keep systemd's `STATUS=` up to date, and ping the watchdog while the pollers are alive
+pub fn spawn_notifier(systemd: &SystemdEnv, shutdown_notify: Arc<tokio::sync::Notify>) {
+    // Nothing to do if the daemon wasn't started by a `Type=notify` service
+    if systemd.notify_socket.is_none() {
+        return;
+    }
+
+    let mut snapshot_rx = subscribe_snapshot();
+    let shutdown_notify_clone = shutdown_notify.clone();
+    let status_systemd = systemd.clone();
+    tokio::spawn(async move {
+        let mut last_status = String::new();
+
+        loop {
+            let current_status = status(&current_snapshot().await);
+
+            if current_status != last_status {
+                status_systemd.notify(&format!("STATUS={current_status}"));
+                last_status = current_status;
+            }
+
+            tokio::select! {
+                // Lagging only means some events were missed, the status is read from the snapshot anyway
+                _ = snapshot_rx.recv() => {}
+                () = shutdown_notify_clone.notified() => break,
+            }
+        }
+    });
+
+    let Some(interval) = systemd.watchdog_interval else {
+        return;
+    };
+    let systemd = systemd.clone();
+
+    // Pollers may take this long between reads without being considered stuck
+    let config = get_config();
+    let max_silence = Duration::from_millis(config.polling_rate.saturating_mul(2) + config.command_timeout.saturating_mul(2));
+
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(interval);
+
+        loop {
+            tokio::select! {
+                _ = timer.tick() => {
+                    if pollers_alive(max_silence) {
+                        systemd.notify("WATCHDOG=1");
+                    } else {
+                        warn!("A poller has stopped, not pinging the watchdog");
+                    }
+                }
+                () = shutdown_notify.notified() => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::{
+        fd::AsRawFd,
+        unix::net::{UnixDatagram, UnixListener, UnixStream},
+    };
+
+    use super::{check_listening_socket, send_state, status};
+    use crate::{
+        battery::Battery,
+        bluetooth::Bluetooth,
+        brightness::Brightness,
+        fan_profile::FanProfile,
+        observed::{
+            Observed::{Recovering, Unavailable, Valid},
+            UnavailableKind, UnavailableReason,
+        },
+        ram::Ram,
+        snapshot::Snapshot,
+        volume::Volume,
+    };
+
+    #[test]
+    fn status_lists_unavailable_modules() {
+        let mut snapshot = Snapshot {
+            volume: Valid(Volume::default()),
+            brightness: Valid(Brightness::default()),
+            bluetooth: Valid(Bluetooth::default()),
+            battery: Valid(Battery::default()),
+            ram: Valid(Ram::default()),
+            fan_profile: Valid(FanProfile::default()),
+            ..Snapshot::default()
+        };
+
+        assert_eq!(status(&snapshot), "All modules available");
+
+        snapshot.volume = Unavailable(UnavailableReason::new(UnavailableKind::CommandMissing, "wpctl"));
+        snapshot.bluetooth = Recovering;
+
+        assert_eq!(
+            status(&snapshot),
+            "Unavailable: volume (Command Missing), bluetooth (Recovering)"
+        );
+    }
+
+    #[test]
+    fn state_is_sent_to_notify_socket() {
+        let socket_path = std::env::temp_dir().join(format!("bar_daemon_notify_test_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let receiver = UnixDatagram::bind(&socket_path).unwrap_or_else(|e| panic!("{e}"));
+
+        assert!(send_state(socket_path.as_os_str(), "READY=1").is_ok());
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(&buf[..len], b"READY=1");
+
+        let _ = std::fs::remove_file(&socket_path);
+    }
+
+    #[test]
+    fn only_listening_unix_stream_sockets_are_accepted() {
+        let path = std::env::temp_dir().join(format!("bar_daemon_activation_test_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path).unwrap_or_else(|e| panic!("{e}"));
+        assert!(check_listening_socket(listener.as_raw_fd()).is_ok());
+
+        let (stream, _) = UnixStream::pair().unwrap_or_else(|e| panic!("{e}"));
+        assert!(check_listening_socket(stream.as_raw_fd()).is_err());
+
+        let datagram = UnixDatagram::unbound().unwrap_or_else(|e| panic!("{e}"));
+        assert!(check_listening_socket(datagram.as_raw_fd()).is_err());
+
+        let file = std::fs::File::open(&path).or_else(|_| std::fs::File::open("/dev/null"));
+        let file = file.unwrap_or_else(|e| panic!("{e}"));
+        assert!(check_listening_socket(file.as_raw_fd()).is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
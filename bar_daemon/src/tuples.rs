@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
 use crate::{
@@ -7,15 +8,15 @@ use crate::{
     error::DaemonError,
     fan_profile::FanProfile,
     monitored::Monitored,
-    observed::Observed::{Recovering, Unavailable, Valid},
+    observed::Observed::{Failed, Recovering, Unavailable, Valid},
     ram::Ram,
-    snapshot::current_snapshot,
+    snapshot::{current_snapshot, with_timestamps},
     volume::Volume,
 };
 
 pub const TUPLE_NAMES: &[&str] = &["volume", "brightness", "bluetooth", "battery", "ram", "fan_profile"];
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum TupleName {
     Volume = 0,
     Brightness = 1,
@@ -43,6 +44,20 @@ impl TryFrom<usize> for TupleName {
     }
 }
 
+impl std::str::FromStr for TupleName {
+    type Err = DaemonError;
+
+    /// # Errors
+    /// Fails if the name isn't in `TUPLE_NAMES`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        TUPLE_NAMES
+            .iter()
+            .position(|tuple_name| *tuple_name == name)
+            .ok_or_else(|| DaemonError::ParseError(format!("Unknown module '{name}', expected one of {TUPLE_NAMES:?}")))
+            .and_then(Self::try_from)
+    }
+}
+
 /// # Errors
 /// Returns an error if the specified tuples can't be gotten
 #[instrument]
@@ -61,20 +76,24 @@ async fn tuple_name_to_tuples_inner(tuple_name: &TupleName) -> Result<Vec<(Strin
     // use latest() for polled values and current_snapshot() for values which don't change without user intervention (Use latest if the current_snapshot() values are None)
     Ok(match tuple_name {
         TupleName::Volume => match current_snapshot().await.volume {
-            Valid(volume) => volume.to_tuples(),
-            Unavailable | Recovering => Volume::latest().await?.to_tuples(),
+            Valid(volume) => with_timestamps::<Volume>(volume.to_tuples()).await,
+            Unavailable(_) | Recovering | Failed { .. } => with_timestamps::<Volume>(Volume::latest().await?.to_tuples()).await,
         },
         TupleName::Brightness => match current_snapshot().await.brightness {
-            Valid(brightness) => brightness.to_tuples(),
-            Unavailable | Recovering => Brightness::latest().await?.to_tuples(),
+            Valid(brightness) => with_timestamps::<Brightness>(brightness.to_tuples()).await,
+            Unavailable(_) | Recovering | Failed { .. } => {
+                with_timestamps::<Brightness>(Brightness::latest().await?.to_tuples()).await
+            }
         },
         TupleName::Bluetooth => match current_snapshot().await.bluetooth {
-            Valid(bluetooth) => bluetooth.to_tuples(),
-            Unavailable | Recovering => Bluetooth::latest().await?.to_tuples(),
+            Valid(bluetooth) => with_timestamps::<Bluetooth>(bluetooth.to_tuples()).await,
+            Unavailable(_) | Recovering | Failed { .. } => {
+                with_timestamps::<Bluetooth>(Bluetooth::latest().await?.to_tuples()).await
+            }
         },
-        TupleName::Battery => Battery::latest().await?.to_tuples(),
-        TupleName::Ram => Ram::latest().await?.to_tuples(),
-        TupleName::FanProfile => FanProfile::latest().await?.to_tuples(), // Special case since the OS changes fan mode when plugging/unplugging AC
+        TupleName::Battery => with_timestamps::<Battery>(Battery::latest().await?.to_tuples()).await,
+        TupleName::Ram => with_timestamps::<Ram>(Ram::latest().await?.to_tuples()).await,
+        TupleName::FanProfile => with_timestamps::<FanProfile>(FanProfile::latest().await?.to_tuples()).await, // Special case since the OS changes fan mode when plugging/unplugging AC
     })
 }
 
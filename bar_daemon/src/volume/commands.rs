@@ -0,0 +1,60 @@
+use clap::{ArgAction, Subcommand};
+
+use crate::{
+    cli::parse_bool,
+    protocol::{DaemonItem, DaemonMessage},
+};
+
+use super::VolumeItem;
+
+#[derive(Subcommand)]
+pub enum VolumeGetCommands {
+    #[command(alias = "per", alias = "p")]
+    Percent,
+    #[command(alias = "m")]
+    Mute,
+    #[command(alias = "i")]
+    Icon,
+}
+
+#[derive(Subcommand)]
+pub enum VolumeSetCommands {
+    #[command(alias = "per", alias = "p")]
+    Percent {
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+    #[command(alias = "m")]
+    Mute {
+        #[arg(action = ArgAction::Set, value_parser = parse_bool)]
+        value: Option<bool>,
+    },
+}
+
+#[must_use]
+pub const fn match_get_commands(commands: &Option<VolumeGetCommands>) -> DaemonMessage {
+    DaemonMessage::Get {
+        item: match commands {
+            Some(commands) => match commands {
+                VolumeGetCommands::Percent => DaemonItem::Volume(VolumeItem::Percent),
+                VolumeGetCommands::Mute => DaemonItem::Volume(VolumeItem::Mute),
+                VolumeGetCommands::Icon => DaemonItem::Volume(VolumeItem::Icon),
+            },
+            None => DaemonItem::Volume(VolumeItem::All),
+        },
+    }
+}
+
+#[must_use]
+pub fn match_set_commands(commands: VolumeSetCommands) -> DaemonMessage {
+    match commands {
+        VolumeSetCommands::Percent { value } => DaemonMessage::Set {
+            item: DaemonItem::Volume(VolumeItem::Percent),
+            value,
+        },
+        VolumeSetCommands::Mute { value } => DaemonMessage::Set {
+            item: DaemonItem::Volume(VolumeItem::Mute),
+            value: value.map_or_else(|| "toggle".to_string(), |value| value.to_string()),
+        },
+    }
+}
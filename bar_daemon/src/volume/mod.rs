@@ -1,8 +1,11 @@
 use source::{VolumeSource, default_source};
 
-pub use value::{
-    Volume, VolumeGetCommands, VolumeItem, VolumeSetCommands, evaluate_item, match_get_commands, match_set_commands,
-};
+#[cfg(feature = "daemon")]
+pub use commands::{VolumeGetCommands, VolumeSetCommands, match_get_commands, match_set_commands};
+pub use source::COMMAND;
+pub use value::{Volume, VolumeItem, evaluate_item};
 
+#[cfg(feature = "daemon")]
+mod commands;
 mod source;
 mod value;
@@ -1,16 +1,15 @@
-use std::str::SplitWhitespace;
+use std::{str::SplitWhitespace, sync::Arc};
 
 use itertools::Itertools;
 use tracing::instrument;
 
 use super::Volume;
 use crate::{
-    command,
+    command::{CommandRunner, default_runner},
     error::DaemonError,
     log_linear::{linear_to_logarithmic, logarithmic_to_linear},
-    monitored::Monitored,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
-    snapshot::{current_snapshot, update_snapshot},
+    observed::Observed::{self, Valid},
+    snapshot::{known_value, require_known, update_snapshot},
 };
 
 pub trait VolumeSource {
@@ -22,36 +21,48 @@ pub trait VolumeSource {
     fn set_mute(&self, mute_str: &str) -> impl std::future::Future<Output = Result<(), DaemonError>> + std::marker::Send;
 }
 
+/// Command which the volume is read and set with
+pub const COMMAND: &str = "wpctl";
+
 // -------------- Default Source ---------------
 
 #[must_use]
 pub fn default_source() -> impl VolumeSource {
-    WpctlVolume
+    WpctlVolume::new(default_runner())
 }
 
 // ---------------- Wpctl Source ---------------
 
 #[derive(Debug)]
-pub struct WpctlVolume;
+pub struct WpctlVolume {
+    runner: Arc<dyn CommandRunner>,
+}
 
-impl VolumeSource for WpctlVolume {
-    // Read from commands
+impl WpctlVolume {
+    #[must_use]
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
 
-    #[instrument]
-    async fn read(&self) -> Result<Observed<Volume>, DaemonError> {
-        fn read_inner() -> Result<Volume, DaemonError> {
-            let output = get_wpctl_output()?;
-            let output_split = get_wpctl_split(&output);
+    async fn read_inner(&self) -> Result<Volume, DaemonError> {
+        let output = get_wpctl_output(self.runner.as_ref()).await?;
+        let output_split = get_wpctl_split(&output);
 
-            let percent = get_linear_percent_from_wpctl_split(output_split.clone())?;
+        let percent = get_linear_percent_from_wpctl_split(output_split.clone())?;
 
-            let mute = get_mute_from_wpctl_split(output_split);
+        let mute = get_mute_from_wpctl_split(output_split);
 
-            Ok(Volume { percent, mute })
-        }
+        Ok(Volume { percent, mute })
+    }
+}
+
+impl VolumeSource for WpctlVolume {
+    // Read from commands
 
+    #[instrument]
+    async fn read(&self) -> Result<Observed<Volume>, DaemonError> {
         // Set as unavailable if the inner function threw an error
-        let volume: Observed<_> = read_inner().into();
+        let volume: Observed<_> = self.read_inner().await.into();
 
         // Update current snapshot
         let _update = update_snapshot(volume.clone()).await;
@@ -64,95 +75,75 @@ impl VolumeSource for WpctlVolume {
     /// # Errors
     /// Returns an error if the command cannot be spawned
     /// Returns an error if values in the output of the command cannot be parsed
+    /// Returns an error if the percentage is relative and the volume has never been read
     #[instrument]
     async fn set_percent(&self, percent_str: &str) -> Result<(), DaemonError> {
-        // Get the current snapshot values
-        let volume_observed = match current_snapshot().await.volume {
-            Valid(volume) => Valid(volume),
-            Unavailable | Recovering => Volume::latest().await?,
-        };
-        let volume = volume_observed.clone().unwrap_or_default();
-
-        // If the percentage is a change, figure out the true percentage
-        let linear_percent = if percent_str.starts_with('+') || percent_str.starts_with('-') {
-            // Get the value of the percentage
-            let delta_percent = i32::try_from(
-                percent_str
-                    .trim_start_matches('+')
-                    .trim_start_matches('-')
-                    .to_string()
-                    .parse::<u32>()?,
-            )?;
-
-            // Calculate the new percentage based on the state's current value
-            (i32::try_from(volume.percent)?
-                + match percent_str.chars().next() {
-                    Some('+') => delta_percent,
-                    Some('-') => -delta_percent,
-                    _ => 0,
-                })
-            .clamp(0, 100) as u32
+        // Only a change in percentage needs the value which the volume was last known to be
+        let known = if percent_str.starts_with('+') || percent_str.starts_with('-') {
+            known_value(self.read()).await
         } else {
-            percent_str.parse::<u32>()?
+            None
         };
 
-        // Update the volume in the snapshot
-        let _update = update_snapshot(volume_observed.map(|volume| Volume {
-            percent: linear_percent,
-            ..volume
-        }))
-        .await;
+        // If the percentage is a change, figure out the true percentage
+        let linear_percent = get_percent_from_str(percent_str, known.as_ref())?;
 
         // Set the volume internally as a logarithmic value
         let logarithmic_percent = linear_to_logarithmic(f64::from(linear_percent));
 
         // Set the volume
-        let _ = command::run(
-            "wpctl",
-            &["set-volume", "@DEFAULT_SINK@", format!("{logarithmic_percent}%").as_str()],
-        )?;
+        let _ = self
+            .runner
+            .run(
+                COMMAND,
+                &["set-volume", "@DEFAULT_SINK@", format!("{logarithmic_percent}%").as_str()],
+            )
+            .await?;
+
+        // Update the snapshot with the volume which was set (wpctl rounds it, and the command may not have changed it)
+        let _volume = self.read().await?;
 
         Ok(())
     }
 
+    /// # Errors
+    /// Returns an error if the command cannot be spawned
+    /// Returns an error if the mute state is toggled and the volume has never been read
     #[instrument]
     async fn set_mute(&self, mute_str: &str) -> Result<(), DaemonError> {
-        let volume_observed = match current_snapshot().await.volume {
-            Valid(volume) => Valid(volume),
-            Unavailable | Recovering => Volume::latest().await?,
-        };
-        let volume = volume_observed.clone().unwrap_or_default();
-
-        let new_mute;
-
-        let mute = if mute_str == "toggle" {
-            new_mute = !volume.mute;
-
-            mute_str.to_string()
+        // Only toggling needs the value which the volume was last known to be
+        let known = if mute_str == "toggle" {
+            known_value(self.read()).await
         } else {
-            let mute = mute_str.parse::<bool>()?;
-            new_mute = mute;
-
-            u8::from(mute).to_string()
+            None
         };
 
-        // Set the mute state
-        let _ = command::run("wpctl", &["set-mute", "@DEFAULT_SINK@", mute.as_str()])?;
+        let (mute, new_mute) = get_mute_from_str(mute_str, known.as_ref())?;
 
-        // Update the volume in the snapshot
-        let _update = update_snapshot(volume_observed.map(|volume| Volume {
-            mute: new_mute,
-            ..volume
-        }))
-        .await;
+        // Set the mute state
+        let _ = self
+            .runner
+            .run(COMMAND, &["set-mute", "@DEFAULT_SINK@", mute.as_str()])
+            .await?;
+
+        // Update the volume in the snapshot, or read the whole volume if it wasn't known before
+        if let Some(volume) = known {
+            let _update = update_snapshot(Valid(Volume {
+                mute: new_mute,
+                ..volume
+            }))
+            .await;
+        } else {
+            let _volume = self.read().await?;
+        }
 
         Ok(())
     }
 }
 
-fn get_wpctl_output() -> Result<String, DaemonError> {
+async fn get_wpctl_output(runner: &dyn CommandRunner) -> Result<String, DaemonError> {
     // Get the volume and mute status as a string
-    command::run("wpctl", &["get-volume", "@DEFAULT_SINK@"])
+    runner.run(COMMAND, &["get-volume", "@DEFAULT_SINK@"]).await
 }
 
 fn get_wpctl_split(output: &str) -> SplitWhitespace<'_> {
@@ -174,3 +165,137 @@ fn get_linear_percent_from_wpctl_split(mut split: SplitWhitespace) -> Result<u32
         Err(DaemonError::ParseError(split.join(" ")))
     }
 }
+
+// ------------- Helper Functions --------------
+
+/// # Documentation
+/// Get the linear percentage which `percent_str` sets the volume to, relative changes (+5, -5) are applied to `known`
+fn get_percent_from_str(percent_str: &str, known: Option<&Volume>) -> Result<u32, DaemonError> {
+    if !(percent_str.starts_with('+') || percent_str.starts_with('-')) {
+        return Ok(percent_str.parse::<u32>()?);
+    }
+
+    let volume = require_known(known)?;
+
+    // Get the value of the percentage
+    let delta_percent = i32::try_from(
+        percent_str
+            .trim_start_matches('+')
+            .trim_start_matches('-')
+            .to_string()
+            .parse::<u32>()?,
+    )?;
+
+    // Calculate the new percentage based on the known value
+    Ok((i32::try_from(volume.percent)?
+        + match percent_str.chars().next() {
+            Some('+') => delta_percent,
+            Some('-') => -delta_percent,
+            _ => 0,
+        })
+    .clamp(0, 100) as u32)
+}
+
+/// # Documentation
+/// Get the argument for `wpctl set-mute` and the mute state which `mute_str` will result in
+fn get_mute_from_str(mute_str: &str, known: Option<&Volume>) -> Result<(String, bool), DaemonError> {
+    if mute_str == "toggle" {
+        Ok((mute_str.to_string(), !require_known(known)?.mute))
+    } else {
+        let mute = mute_str.parse::<bool>()?;
+
+        Ok((u8::from(mute).to_string(), mute))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{VolumeSource, WpctlVolume, get_mute_from_str, get_percent_from_str};
+    use crate::{command::fake::FakeRunner, error::DaemonError, log_linear::linear_to_logarithmic, volume::Volume};
+
+    const GET_VOLUME: &str = "wpctl get-volume @DEFAULT_SINK@";
+
+    fn source(output: &str) -> WpctlVolume {
+        WpctlVolume::new(Arc::new(FakeRunner::new().with_output(GET_VOLUME, output)))
+    }
+
+    #[tokio::test]
+    async fn reads_volume_and_mute() {
+        let volume = source("Volume: 1.00").read_inner().await;
+        assert_eq!(
+            volume.ok(),
+            Some(Volume {
+                percent: 100,
+                mute: false
+            })
+        );
+
+        let volume = source("Volume: 0.00 [MUTED]").read_inner().await;
+        assert_eq!(volume.ok(), Some(Volume { percent: 0, mute: true }));
+    }
+
+    #[tokio::test]
+    async fn unparseable_volume_is_an_error() {
+        let volume = source("Volume: loud").read_inner().await;
+        assert!(matches!(volume, Err(DaemonError::StringToFloatError(_))), "{volume:?}");
+
+        let volume = source("").read_inner().await;
+        assert!(matches!(volume, Err(DaemonError::ParseError(_))), "{volume:?}");
+    }
+
+    #[tokio::test]
+    async fn missing_wpctl_is_an_error() {
+        let source = WpctlVolume::new(Arc::new(FakeRunner::new()));
+
+        assert!(matches!(source.read_inner().await, Err(DaemonError::CommandNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn set_runs_wpctl() {
+        let set_volume = format!("wpctl set-volume @DEFAULT_SINK@ {}%", linear_to_logarithmic(30.0));
+        let set_mute = "wpctl set-mute @DEFAULT_SINK@ 1";
+        let runner = Arc::new(
+            FakeRunner::new()
+                .with_output(&set_volume, "")
+                .with_output(set_mute, "")
+                .with_output(GET_VOLUME, "Volume: 0.30 [MUTED]"),
+        );
+        let source = WpctlVolume::new(runner.clone());
+
+        // The volume is read back with the same runner once it has been set
+        assert!(source.set_percent("30").await.is_ok());
+        assert!(source.set_mute("true").await.is_ok());
+        assert_eq!(runner.invocations(), [set_volume.as_str(), GET_VOLUME, set_mute, GET_VOLUME]);
+    }
+
+    #[test]
+    fn relative_percent_is_applied_and_clamped() {
+        let known = Volume {
+            percent: 50,
+            mute: false,
+        };
+
+        assert_eq!(get_percent_from_str("30", None).ok(), Some(30));
+        assert_eq!(get_percent_from_str("+5", Some(&known)).ok(), Some(55));
+        assert_eq!(get_percent_from_str("-5", Some(&known)).ok(), Some(45));
+        assert_eq!(get_percent_from_str("+80", Some(&known)).ok(), Some(100));
+        assert_eq!(get_percent_from_str("-80", Some(&known)).ok(), Some(0));
+        assert!(matches!(get_percent_from_str("+5", None), Err(DaemonError::NoKnownValue(_))));
+        assert!(get_percent_from_str("+five", Some(&known)).is_err());
+    }
+
+    #[test]
+    fn mute_toggle_uses_known_state() {
+        let known = Volume { percent: 50, mute: true };
+
+        assert_eq!(
+            get_mute_from_str("toggle", Some(&known)).ok(),
+            Some((String::from("toggle"), false))
+        );
+        assert_eq!(get_mute_from_str("true", None).ok(), Some((String::from("1"), true)));
+        assert_eq!(get_mute_from_str("false", None).ok(), Some((String::from("0"), false)));
+        assert!(matches!(get_mute_from_str("toggle", None), Err(DaemonError::NoKnownValue(_))));
+    }
+}
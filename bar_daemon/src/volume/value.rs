@@ -1,19 +1,19 @@
-use clap::{ArgAction, Subcommand};
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
 use crate::{
-    ICON_EXT, NOTIFICATION_ID,
-    cli::parse_bool,
-    command,
+    ICON_EXT, NOTIFICATION_ID, command,
     config::get_config,
-    daemon::{DaemonItem, DaemonMessage, DaemonReply},
     error::DaemonError,
     impl_monitored,
     monitored::{Monitored, MonitoredUpdate},
     notification::Notify,
-    observed::Observed::{self, Recovering, Unavailable, Valid},
-    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot},
+    observed::{
+        Observed::{self, Failed, Recovering, Unavailable, Valid},
+        UnavailableReason,
+    },
+    protocol::{DaemonItem, DaemonReply},
+    snapshot::{IntoSnapshotEvent, Snapshot, SnapshotEvent, current_snapshot, with_timestamps},
     tuples::ToTuples,
 };
 
@@ -21,30 +21,6 @@ use super::{VolumeSource, default_source};
 
 const NOTIFICATION_OFFSET: u32 = 4;
 
-#[derive(Subcommand)]
-pub enum VolumeGetCommands {
-    #[command(alias = "per", alias = "p")]
-    Percent,
-    #[command(alias = "m")]
-    Mute,
-    #[command(alias = "i")]
-    Icon,
-}
-
-#[derive(Subcommand)]
-pub enum VolumeSetCommands {
-    #[command(alias = "per", alias = "p")]
-    Percent {
-        #[arg(allow_hyphen_values = true)]
-        value: String,
-    },
-    #[command(alias = "m")]
-    Mute {
-        #[arg(action = ArgAction::Set, value_parser = parse_bool)]
-        value: Option<bool>,
-    },
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum VolumeItem {
     Percent,
@@ -53,7 +29,7 @@ pub enum VolumeItem {
     All,
 }
 
-#[derive(Clone, Debug, Default, PartialEq, PartialOrd, Ord, Eq, bar_daemon_derive::IntoSnapshotEvent)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, PartialOrd, Ord, Eq, bar_daemon_derive::IntoSnapshotEvent)]
 pub struct Volume {
     pub percent: u32,
     pub mute: bool,
@@ -120,7 +96,7 @@ impl Notify<Self> for Volume {
     /// Returns an error if notification command could not be run
     #[instrument]
     async fn notify(update: MonitoredUpdate<Self>) -> Result<(), DaemonError> {
-        fn do_notification(new: &Volume) -> Result<(), DaemonError> {
+        async fn do_notification(new: &Volume) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -136,12 +112,13 @@ impl Notify<Self> for Volume {
                     format!("int:value:{}", new.percent).as_str(),
                     "Volume: ",
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
 
-        fn do_notification_unavailable() -> Result<(), DaemonError> {
+        async fn do_notification_unavailable(reason: Option<&UnavailableReason>) -> Result<(), DaemonError> {
             command::run(
                 "dunstify",
                 &[
@@ -152,16 +129,18 @@ impl Notify<Self> for Volume {
                     "-t",
                     get_config().notification_timeout.to_string().as_str(),
                     "Volume Unavailable",
+                    reason.map(ToString::to_string).unwrap_or_default().as_str(),
                 ],
-            )?;
+            )
+            .await?;
 
             Ok(())
         }
 
         // If the new values are valid
         match update.new {
-            Valid(new) => do_notification(&new)?,
-            Unavailable | Recovering => do_notification_unavailable()?,
+            Valid(new) => do_notification(&new).await?,
+            Unavailable(_) | Recovering | Failed { .. } => do_notification_unavailable(update.new.reason()).await?,
         }
 
         Ok(())
@@ -187,60 +166,19 @@ pub async fn evaluate_item(
         DaemonReply::Value { item, value }
     } else {
         // Get value (use current_snapshot since this won't change without bar_daemon changing it) (Use latest when current_snapshot is empty)
+        let volume = match current_snapshot().await.volume {
+            Valid(volume) => Valid(volume),
+            Unavailable(_) | Recovering | Failed { .. } => Volume::latest().await?,
+        };
+
         match volume_item {
-            VolumeItem::Percent => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.volume {
-                    Valid(volume) => volume.percent.to_string(),
-                    Unavailable | Recovering => Volume::latest().await?.map(|volume| volume.percent).to_string(),
-                },
-            },
-            VolumeItem::Mute => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.volume {
-                    Valid(volume) => volume.mute.to_string(),
-                    Unavailable | Recovering => Volume::latest().await?.map(|volume| volume.mute).to_string(),
-                },
-            },
-            VolumeItem::Icon => DaemonReply::Value {
-                item,
-                value: match current_snapshot().await.volume {
-                    Valid(volume) => volume.get_icon(),
-                    Unavailable | Recovering => Volume::latest().await?.map(|volume| volume.get_icon()).to_string(),
-                },
-            },
+            VolumeItem::Percent => DaemonReply::from_observed(item, volume.map(|volume| volume.percent)),
+            VolumeItem::Mute => DaemonReply::from_observed(item, volume.map(|volume| volume.mute)),
+            VolumeItem::Icon => DaemonReply::from_observed(item, volume.map(|volume| volume.get_icon())),
             VolumeItem::All => DaemonReply::Tuples {
                 item,
-                tuples: Volume::latest().await?.to_tuples(),
+                tuples: with_timestamps::<Volume>(Volume::latest().await?.to_tuples()).await,
             },
         }
     })
 }
-
-#[must_use]
-pub const fn match_get_commands(commands: &Option<VolumeGetCommands>) -> DaemonMessage {
-    DaemonMessage::Get {
-        item: match commands {
-            Some(commands) => match commands {
-                VolumeGetCommands::Percent => DaemonItem::Volume(VolumeItem::Percent),
-                VolumeGetCommands::Mute => DaemonItem::Volume(VolumeItem::Mute),
-                VolumeGetCommands::Icon => DaemonItem::Volume(VolumeItem::Icon),
-            },
-            None => DaemonItem::Volume(VolumeItem::All),
-        },
-    }
-}
-
-#[must_use]
-pub fn match_set_commands(commands: VolumeSetCommands) -> DaemonMessage {
-    match commands {
-        VolumeSetCommands::Percent { value } => DaemonMessage::Set {
-            item: DaemonItem::Volume(VolumeItem::Percent),
-            value,
-        },
-        VolumeSetCommands::Mute { value } => DaemonMessage::Set {
-            item: DaemonItem::Volume(VolumeItem::Mute),
-            value: value.map_or_else(|| "toggle".to_string(), |value| value.to_string()),
-        },
-    }
-}
@@ -0,0 +1,167 @@
+#![allow(dead_code)]
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bar_daemon::{
+    daemon::{DaemonOptions, request_status_from, run_daemon, send_daemon_message_to},
+    error::DaemonError,
+    protocol::{DaemonItem, DaemonMessage, DaemonReply, write_message},
+    simulate::Scenario,
+    status::DaemonStatus,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, Lines},
+    net::UnixStream,
+    sync::oneshot,
+    task::JoinHandle,
+};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A daemon running in this process, using simulated hardware and its own socket
+///
+/// The snapshot and command runner are shared by the whole process, so each test file only runs one daemon
+pub struct TestDaemon {
+    pub socket_path: PathBuf,
+    shutdown_tx: oneshot::Sender<()>,
+    handle: JoinHandle<Result<(), DaemonError>>,
+}
+
+impl TestDaemon {
+    /// Start a daemon with the given scenario, waiting until its socket can be connected to
+    pub async fn start(name: &str, scenario: &str) -> Self {
+        Self::start_with(name, scenario, false).await
+    }
+
+    /// Start a daemon, which replaces any daemon already using its socket if `replace` is set
+    pub async fn start_with(name: &str, scenario: &str, replace: bool) -> Self {
+        let scenario = match Scenario::parse(scenario) {
+            Ok(scenario) => scenario,
+            Err(e) => panic!("Invalid test scenario: {e}"),
+        };
+
+        let socket_path = std::env::temp_dir()
+            .join(format!("bar_daemon_test_{}_{name}", std::process::id()))
+            .join("bar_daemon.sock");
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        // The daemon doesn't use the user's config, or the services which would clash with their daemon
+        let options = DaemonOptions {
+            scenario: Some(scenario),
+            replace,
+            ..DaemonOptions::isolated(socket_path.clone())
+        };
+
+        let handle = tokio::spawn(run_daemon(options, async move {
+            let _ = shutdown_rx.await;
+        }));
+
+        wait_for_socket(&socket_path).await;
+
+        Self {
+            socket_path,
+            shutdown_tx,
+            handle,
+        }
+    }
+
+    /// Wait until the daemon's socket can be connected to
+    pub async fn wait_until_listening(&self) {
+        wait_for_socket(&self.socket_path).await;
+    }
+
+    pub async fn send(&self, message: DaemonMessage) -> DaemonReply {
+        match send_daemon_message_to(&self.socket_path, message).await {
+            Ok(reply) => reply,
+            Err(e) => panic!("Daemon did not reply: {e}"),
+        }
+    }
+
+    pub async fn get(&self, item: DaemonItem) -> DaemonReply {
+        self.send(DaemonMessage::Get { item }).await
+    }
+
+    pub async fn set(&self, item: DaemonItem, value: &str) -> DaemonReply {
+        self.send(DaemonMessage::Set {
+            item,
+            value: value.to_string(),
+        })
+        .await
+    }
+
+    /// Get the daemon's status once `done` is true for it, since values are read after the daemon starts listening
+    pub async fn status_when(&self, done: impl Fn(&DaemonStatus) -> bool) -> DaemonStatus {
+        let start = tokio::time::Instant::now();
+
+        loop {
+            match request_status_from(&self.socket_path).await {
+                Ok(DaemonReply::Status(status)) if done(&status) => return status,
+                Ok(DaemonReply::Status(status)) => {
+                    assert!(start.elapsed() < STARTUP_TIMEOUT, "Status did not change in time: {status:?}");
+                }
+                reply => panic!("Expected status, got {reply:?}"),
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Connect as a listener, returning the lines which the daemon broadcasts
+    pub async fn listen(&self) -> Lines<BufReader<UnixStream>> {
+        let mut stream = connect(&self.socket_path).await;
+
+        if let Err(e) = write_message(&mut stream, &DaemonMessage::Listen).await {
+            panic!("Could not send Listen: {e}");
+        }
+
+        BufReader::new(stream).lines()
+    }
+
+    /// Shut the daemon down, checking that it stopped cleanly and removed its socket
+    pub async fn stop(self) {
+        let _ = self.shutdown_tx.send(());
+
+        match tokio::time::timeout(STARTUP_TIMEOUT, self.handle).await {
+            Ok(Ok(Ok(()))) => {}
+            result => panic!("Daemon did not shut down cleanly: {result:?}"),
+        }
+
+        assert!(!self.socket_path.exists(), "Socket was not removed on shutdown");
+    }
+
+    /// Wait for the daemon to stop by itself (e.g. after being replaced)
+    pub async fn stopped(self) {
+        match tokio::time::timeout(STARTUP_TIMEOUT, self.handle).await {
+            Ok(Ok(Ok(()))) => {}
+            result => panic!("Daemon did not shut down cleanly: {result:?}"),
+        }
+    }
+}
+
+pub async fn connect(socket_path: &Path) -> UnixStream {
+    match UnixStream::connect(socket_path).await {
+        Ok(stream) => stream,
+        Err(e) => panic!("Could not connect to {}: {e}", socket_path.display()),
+    }
+}
+
+async fn wait_for_socket(socket_path: &Path) {
+    let start = tokio::time::Instant::now();
+
+    while UnixStream::connect(socket_path).await.is_err() {
+        assert!(start.elapsed() < STARTUP_TIMEOUT, "Daemon did not start listening in time");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Get the value of a reply, panicking if it isn't a `DaemonReply::Value`
+pub fn value(reply: DaemonReply) -> String {
+    match reply {
+        DaemonReply::Value { value, .. } => value,
+        reply => panic!("Expected a value, got {reply:?}"),
+    }
+}
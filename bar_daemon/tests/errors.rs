@@ -0,0 +1,96 @@
+#![cfg(feature = "daemon")]
+
+mod common;
+
+use std::time::Duration;
+
+use bar_daemon::{
+    battery::BatteryItem,
+    error::ErrorCode,
+    fan_profile::FanProfileItem,
+    observed::UnavailableKind,
+    protocol::{BUFFER_SIZE, DaemonItem, DaemonMessage, DaemonReply, read_reply},
+    volume::VolumeItem,
+};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use common::{TestDaemon, connect};
+
+const SCENARIO: &str = r#"
+[initial]
+unavailable = ["fan_profile"]
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failures_are_replied_to() {
+    let daemon = TestDaemon::start("failures", SCENARIO).await;
+
+    // Invalid values and read-only items are replied to with an error
+    match daemon.set(DaemonItem::Volume(VolumeItem::Percent), "loud").await {
+        DaemonReply::Error { code, module, .. } => {
+            assert_eq!(code, ErrorCode::InvalidValue);
+            assert_eq!(module.as_deref(), Some("volume"));
+        }
+        reply => panic!("Expected an error, got {reply:?}"),
+    }
+
+    match daemon.set(DaemonItem::Battery(BatteryItem::Percent), "50").await {
+        DaemonReply::Error { code, .. } => assert_eq!(code, ErrorCode::InvalidMessage),
+        reply => panic!("Expected an error, got {reply:?}"),
+    }
+
+    // Values which can't be read are replied to with the reason
+    match daemon.get(DaemonItem::FanProfile(FanProfileItem::Profile)).await {
+        DaemonReply::Unavailable { value, reason, .. } => {
+            assert_eq!(value, "?");
+            assert_eq!(reason.kind, UnavailableKind::CommandFailed);
+        }
+        reply => panic!("Expected an unavailable value, got {reply:?}"),
+    }
+
+    // Messages which can't be decoded are replied to, and the connection stays open
+    let mut stream = connect(&daemon.socket_path).await;
+
+    for _ in 0..2 {
+        let _ = stream.write_all(&[0xff, 0xff, 0xff, 0x00]).await;
+
+        match read_reply(&mut stream).await {
+            Ok(DaemonReply::Error { code, .. }) => assert_eq!(code, ErrorCode::InvalidMessage),
+            reply => panic!("Expected an error, got {reply:?}"),
+        }
+    }
+
+    // A request is read whole when it arrives in parts, and requests which arrive together are each replied to
+    let get = postcard::to_stdvec_cobs(&DaemonMessage::Get {
+        item: DaemonItem::Volume(VolumeItem::Percent),
+    })
+    .unwrap_or_else(|e| panic!("{e}"));
+    let (start, end) = get.split_at(2);
+
+    let _ = stream.write_all(start).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let _ = stream.write_all(&[end, &get].concat()).await;
+
+    // Both replies may arrive in one read, so they are split at the end of each frame
+    let mut replies = BufReader::new(&mut stream);
+    for _ in 0..2 {
+        let mut frame = Vec::new();
+        let _ = replies.read_until(0, &mut frame).await;
+
+        match postcard::from_bytes_cobs::<DaemonReply>(&mut frame) {
+            Ok(DaemonReply::Value { value, .. }) => assert_eq!(value, "50"),
+            reply => panic!("Expected the volume, got {reply:?}"),
+        }
+    }
+
+    // A request longer than any valid one is refused, and the connection is closed
+    let _ = stream.write_all(&[0x01; BUFFER_SIZE + 1]).await;
+
+    match read_reply(&mut stream).await {
+        Ok(DaemonReply::Error { code, .. }) => assert_eq!(code, ErrorCode::InvalidMessage),
+        reply => panic!("Expected an error, got {reply:?}"),
+    }
+    assert!(read_reply(&mut stream).await.is_err());
+
+    daemon.stop().await;
+}
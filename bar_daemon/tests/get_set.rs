@@ -0,0 +1,41 @@
+#![cfg(feature = "daemon")]
+
+mod common;
+
+use bar_daemon::{
+    bluetooth::BluetoothItem,
+    protocol::{DaemonItem, DaemonReply},
+    volume::VolumeItem,
+};
+
+use common::{TestDaemon, value};
+
+const SCENARIO: &str = r#"
+[initial]
+volume = 50
+bluetooth = true
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_and_set_over_socket() {
+    let daemon = TestDaemon::start("get_set", SCENARIO).await;
+
+    // Values are read from the simulated hardware
+    assert_eq!(value(daemon.get(DaemonItem::Volume(VolumeItem::Percent)).await), "50");
+    assert_eq!(value(daemon.get(DaemonItem::Bluetooth(BluetoothItem::State)).await), "true");
+
+    // Relative and absolute sets change the hardware, and later gets see the change (64 is read back from wpctl exactly)
+    assert_eq!(value(daemon.set(DaemonItem::Volume(VolumeItem::Percent), "+14").await), "+14");
+    assert_eq!(value(daemon.get(DaemonItem::Volume(VolumeItem::Percent)).await), "64");
+
+    let _ = value(daemon.set(DaemonItem::Bluetooth(BluetoothItem::State), "toggle").await);
+    assert_eq!(value(daemon.get(DaemonItem::Bluetooth(BluetoothItem::State)).await), "false");
+
+    // All modules are returned together
+    match daemon.get(DaemonItem::All).await {
+        DaemonReply::AllTuples { tuples } => assert_eq!(tuples.len(), 6, "{tuples:?}"),
+        reply => panic!("Expected all tuples, got {reply:?}"),
+    }
+
+    daemon.stop().await;
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "daemon")]
+
+mod common;
+
+use std::time::Duration;
+
+use bar_daemon::{
+    daemon::request_history_from,
+    module_value::ModuleValue,
+    protocol::{DaemonItem, DaemonReply},
+    tuples::TupleName,
+    volume::VolumeItem,
+};
+
+use common::{TestDaemon, value};
+
+const SCENARIO: &str = r#"
+[initial]
+volume = 50
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn history_keeps_each_change() {
+    let daemon = TestDaemon::start("history", SCENARIO).await;
+
+    let _ = value(daemon.get(DaemonItem::Volume(VolumeItem::Percent)).await);
+    // wpctl shows the volume to 2 decimal places, so these are the volumes which are read back exactly
+    let _ = value(daemon.set(DaemonItem::Volume(VolumeItem::Percent), "64").await);
+    let _ = value(daemon.set(DaemonItem::Volume(VolumeItem::Percent), "81").await);
+
+    let entries = match request_history_from(&daemon.socket_path, TupleName::Volume, Some(Duration::from_secs(3600))).await {
+        Ok(DaemonReply::History {
+            module: TupleName::Volume,
+            entries,
+        }) => entries,
+        reply => panic!("Expected volume history, got {reply:?}"),
+    };
+
+    // Changes are kept oldest first, ending with the latest value
+    let percents = entries
+        .iter()
+        .filter_map(|entry| match &entry.value {
+            Ok(ModuleValue::Volume(volume)) => Some(volume.percent),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert!(percents.ends_with(&[64, 81]), "{entries:?}");
+    assert!(
+        entries.windows(2).all(|pair| pair[0].sequence < pair[1].sequence),
+        "{entries:?}"
+    );
+
+    // Other modules have their own history
+    match request_history_from(&daemon.socket_path, TupleName::FanProfile, None).await {
+        Ok(DaemonReply::History { entries, .. }) => {
+            assert!(entries.iter().all(|entry| !matches!(entry.value, Ok(ModuleValue::Volume(_)))));
+        }
+        reply => panic!("Expected fan profile history, got {reply:?}"),
+    }
+
+    daemon.stop().await;
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "daemon")]
+
+mod common;
+
+use bar_daemon::{
+    daemon::{DaemonOptions, run_daemon},
+    error::DaemonError,
+    protocol::DaemonItem,
+    volume::VolumeItem,
+};
+
+use common::{TestDaemon, value};
+
+const SCENARIO: &str = r#"
+[initial]
+volume = 50
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_one_daemon_uses_a_socket() {
+    let daemon = TestDaemon::start("instance", SCENARIO).await;
+
+    // A second daemon refuses to start, and leaves the running daemon alone
+    let options = DaemonOptions::isolated(daemon.socket_path.clone());
+    let result = run_daemon(options, std::future::pending()).await;
+
+    assert!(matches!(result, Err(DaemonError::AlreadyRunning(_))), "{result:?}");
+    assert_eq!(value(daemon.get(DaemonItem::Volume(VolumeItem::Percent)).await), "50");
+
+    // A daemon started with replace asks the running daemon to shut down, then takes its socket
+    let replacement = TestDaemon::start_with("instance", SCENARIO, true).await;
+    daemon.stopped().await;
+    replacement.wait_until_listening().await;
+
+    assert_eq!(value(replacement.get(DaemonItem::Volume(VolumeItem::Percent)).await), "50");
+
+    replacement.stop().await;
+}
@@ -0,0 +1,99 @@
+#![cfg(feature = "daemon")]
+
+mod common;
+
+use std::time::Duration;
+
+use bar_daemon::{protocol::DaemonItem, tuples::TUPLE_NAMES, volume::VolumeItem};
+use tokio::{
+    io::{BufReader, Lines},
+    net::UnixStream,
+};
+
+use common::{TestDaemon, value};
+
+const SCENARIO: &str = r#"
+[initial]
+volume = 50
+battery_state = "Not charging"
+"#;
+
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn next_frame(lines: &mut Lines<BufReader<UnixStream>>) -> serde_json::Value {
+    let line = match tokio::time::timeout(FRAME_TIMEOUT, lines.next_line()).await {
+        Ok(Ok(Some(line))) => line,
+        result => panic!("Listener stopped receiving frames: {result:?}"),
+    };
+
+    match serde_json::from_str(&line) {
+        Ok(frame) => frame,
+        Err(e) => panic!("Frame is not JSON ({e}): {line}"),
+    }
+}
+
+fn sequence_of(frame: &serde_json::Value) -> u64 {
+    match frame["_frame"]["sequence"].as_str().map(str::parse) {
+        Some(Ok(sequence)) => sequence,
+        _ => panic!("Frame has no sequence number: {frame}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn listeners_receive_changes_in_order() {
+    let daemon = TestDaemon::start("listen", SCENARIO).await;
+
+    // Make sure the volume has been read before it is changed
+    assert_eq!(value(daemon.get(DaemonItem::Volume(VolumeItem::Percent)).await), "50");
+
+    let mut lines = daemon.listen().await;
+
+    // The daemon sends its current state first, so the listener is added before anything changes
+    let snapshot = next_frame(&mut lines).await;
+    assert_eq!(snapshot["volume"]["percent"], "50", "{snapshot}");
+    assert_eq!(snapshot["_frame"]["kind"], "snapshot", "{snapshot}");
+
+    // The timestamps are in the first frame too, not only in updates
+    assert_ne!(snapshot["volume"]["_last_updated"], "?", "{snapshot}");
+    assert_ne!(snapshot["volume"]["_last_valid"], "?", "{snapshot}");
+    assert_eq!(snapshot["volume"]["_stale"], "false", "{snapshot}");
+    for module in TUPLE_NAMES {
+        for timestamp in ["_last_updated", "_last_valid", "_stale"] {
+            assert!(
+                snapshot[module][timestamp].is_string(),
+                "{module} has no {timestamp}: {snapshot}"
+            );
+        }
+    }
+
+    let mut sequence = sequence_of(&snapshot);
+
+    for percent in ["10", "20", "30"] {
+        assert_eq!(
+            value(daemon.set(DaemonItem::Volume(VolumeItem::Percent), percent).await),
+            percent
+        );
+    }
+
+    // Collect the volume from each frame until the last change has been broadcast
+    let mut volumes = vec![];
+    while volumes.last().is_none_or(|volume| volume != "30") {
+        let frame = next_frame(&mut lines).await;
+
+        // Each change follows the one before it, so no changes were missed
+        assert_eq!(frame["_frame"]["kind"], "update", "{frame}");
+        assert_eq!(sequence_of(&frame), sequence + 1, "{frame}");
+        sequence += 1;
+
+        if let Some(volume) = frame["volume"]["percent"].as_str() {
+            // Other modules can change between volume changes
+            if volumes.last().is_none_or(|last| last != volume) {
+                volumes.push(volume.to_string());
+            }
+        }
+    }
+
+    assert_eq!(volumes, ["10", "20", "30"]);
+
+    daemon.stop().await;
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "daemon")]
+
+mod common;
+
+use std::time::Duration;
+
+use bar_daemon::listener::listen_to_writer;
+use tokio::io::{AsyncBufReadExt, BufReader, DuplexStream, Lines};
+
+use common::TestDaemon;
+
+const SCENARIO: &str = r#"
+[initial]
+volume = 50
+"#;
+
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn next_frame(lines: &mut Lines<BufReader<DuplexStream>>) -> serde_json::Value {
+    let line = match tokio::time::timeout(FRAME_TIMEOUT, lines.next_line()).await {
+        Ok(Ok(Some(line))) => line,
+        result => panic!("Listener did not write a frame: {result:?}"),
+    };
+
+    match serde_json::from_str(&line) {
+        Ok(frame) => frame,
+        Err(e) => panic!("Frame is not JSON ({e}): {line}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn listener_survives_daemon_restart() {
+    let daemon = TestDaemon::start("listen_reconnect", SCENARIO).await;
+    let socket_path = daemon.socket_path.clone();
+
+    let (mut output, reader) = tokio::io::duplex(64 * 1024);
+    let mut lines = BufReader::new(reader).lines();
+
+    let listen_path = socket_path.clone();
+    let listener = tokio::spawn(async move { listen_to_writer(&listen_path, &mut output).await });
+
+    assert_eq!(next_frame(&mut lines).await["volume"]["percent"], "50");
+
+    // Every value is unknown while the daemon is stopped
+    daemon.stop().await;
+
+    let frame = next_frame(&mut lines).await;
+    assert_eq!(frame["volume"]["percent"], "?", "{frame}");
+    assert_eq!(frame["battery"]["percent"], "?", "{frame}");
+    assert!(
+        frame["ram"]["_error"]
+            .as_str()
+            .is_some_and(|error| error.starts_with("Daemon Unavailable")),
+        "{frame}"
+    );
+
+    // The full state is written again once the daemon is back
+    let daemon = TestDaemon::start("listen_reconnect", SCENARIO).await;
+
+    assert_eq!(next_frame(&mut lines).await["volume"]["percent"], "50");
+    assert!(!listener.is_finished(), "Listener stopped");
+
+    listener.abort();
+    daemon.stop().await;
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "daemon")]
+
+mod common;
+
+use bar_daemon::tuples::TupleName;
+
+use common::TestDaemon;
+
+const SCENARIO: &str = r#"
+[initial]
+unavailable = ["ram"]
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn status_explains_unavailable_modules() {
+    let daemon = TestDaemon::start("status", SCENARIO).await;
+
+    // RAM is polled, so it may not have failed yet when the daemon starts listening
+    let status = daemon
+        .status_when(|status| {
+            status
+                .modules
+                .iter()
+                .any(|module| module.module == TupleName::Ram && module.last_error.is_some())
+        })
+        .await;
+
+    assert_eq!(status.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(status.modules.len(), 6);
+    assert_eq!(status.listeners, 0);
+
+    // Values come from the simulated hardware
+    let volume = status.modules.iter().find(|module| module.module == TupleName::Volume);
+    assert!(
+        volume.is_some_and(|volume| volume.source == "wpctl (simulated)" && volume.state == "Valid"),
+        "{status:?}"
+    );
+
+    // Modules which can't be read show why, and polled modules show their interval
+    let ram = status.modules.iter().find(|module| module.module == TupleName::Ram);
+    assert!(
+        ram.is_some_and(|ram| ram.state != "Valid" && ram.last_error.is_some() && ram.poll_interval.is_some()),
+        "{status:?}"
+    );
+
+    daemon.stop().await;
+}
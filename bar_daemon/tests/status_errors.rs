@@ -0,0 +1,36 @@
+#![cfg(feature = "daemon")]
+
+mod common;
+
+use common::TestDaemon;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn status_of_failing_modules_is_complete() {
+    // Every module fails with an error which is longer than the daemon's buffer on its own
+    let failure = "Simulated failure with a long explanation. ".repeat(40);
+    let scenario = format!(
+        r#"
+[initial]
+unavailable = ["volume", "brightness", "bluetooth", "battery", "ram", "fan_profile"]
+failure = "{failure}"
+"#
+    );
+    let daemon = TestDaemon::start("status_errors", &scenario).await;
+
+    let status = daemon
+        .status_when(|status| status.modules.iter().all(|module| module.last_error.is_some()))
+        .await;
+
+    assert_eq!(status.modules.len(), 6);
+    for module in &status.modules {
+        assert!(
+            module
+                .last_error
+                .as_ref()
+                .is_some_and(|reason| reason.message.contains(failure.trim_end())),
+            "{module:?}"
+        );
+    }
+
+    daemon.stop().await;
+}
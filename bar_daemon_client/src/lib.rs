@@ -0,0 +1,399 @@
+#![warn(clippy::all)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::nursery)]
+#![warn(clippy::unwrap_used)]
+#![warn(clippy::expect_used)]
+
+//! Async client for `bar_daemon`, which speaks the daemon's socket protocol so other tools don't have to
+
+use std::path::{Path, PathBuf};
+
+use futures::Stream;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::UnixStream,
+    sync::Mutex,
+};
+use tracing::{debug, warn};
+
+use bar_daemon::{
+    backoff::{Backoff, BackoffPolicy},
+    bluetooth::BluetoothItem,
+    brightness::BrightnessItem,
+    fan_profile::FanProfileItem,
+    observed::UnavailableReason,
+    protocol::{read_reply, write_message},
+    socket::socket_path,
+    volume::VolumeItem,
+};
+
+pub use bar_daemon::{
+    battery::{Battery, BatteryState},
+    bluetooth::Bluetooth,
+    brightness::Brightness,
+    error::{DaemonError, ErrorCode},
+    fan_profile::{FanProfile, FanState},
+    module_value::{ModuleState, ModuleValue},
+    protocol::{DaemonItem, DaemonMessage, DaemonReply},
+    ram::Ram,
+    tuples::TupleName,
+    volume::Volume,
+};
+
+/// Times a request tries to connect to the daemon before giving up
+const REQUEST_CONNECT_ATTEMPTS: u32 = 3;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error("{0}")]
+    Daemon(#[from] DaemonError),
+
+    #[error("Request failed ({code}): {message}")]
+    Request { code: ErrorCode, message: String },
+
+    #[error("Value is unavailable: {0}")]
+    Unavailable(UnavailableReason),
+
+    #[error("Unexpected reply from daemon: {0}")]
+    UnexpectedReply(String),
+}
+
+impl ClientError {
+    /// # Documentation
+    /// The category of error, using the same codes as the daemon and CLI
+    #[must_use]
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Daemon(e) => ErrorCode::from(e),
+            Self::Request { code, .. } => *code,
+            Self::Unavailable(_) | Self::UnexpectedReply(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+/// # Documentation
+/// A module of the daemon which can be read as a typed value with `Client::get`
+pub trait Module: Sized {
+    const MODULE: TupleName;
+
+    fn from_value(value: ModuleValue) -> Option<Self>;
+}
+
+macro_rules! impl_module {
+    ($type:ident) => {
+        impl Module for $type {
+            const MODULE: TupleName = TupleName::$type;
+
+            fn from_value(value: ModuleValue) -> Option<Self> {
+                match value {
+                    ModuleValue::$type(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_module!(Volume);
+impl_module!(Brightness);
+impl_module!(Bluetooth);
+impl_module!(Battery);
+impl_module!(Ram);
+impl_module!(FanProfile);
+
+/// # Documentation
+/// A connection to the daemon, which is re-established if the daemon restarts
+#[derive(Debug)]
+pub struct Client {
+    socket_path: PathBuf,
+    reconnect: BackoffPolicy,
+    stream: Mutex<Option<UnixStream>>,
+}
+
+/// Why a request failed, which decides whether it is safe to send again
+enum Failure {
+    /// The request was never sent, so it can always be sent again
+    Send(DaemonError),
+    /// The request may have been handled, so only requests which don't change anything are sent again
+    Reply(DaemonError),
+}
+
+impl Client {
+    /// # Documentation
+    /// Connect to the daemon at the socket path used by the CLI (The config's `socket_path`, or the default socket)
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be connected to
+    pub async fn connect() -> Result<Self, ClientError> {
+        Self::connect_to(socket_path()).await
+    }
+
+    /// # Documentation
+    /// Connect to the daemon listening at `socket_path`
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be connected to
+    pub async fn connect_to<P: Into<PathBuf>>(socket_path: P) -> Result<Self, ClientError> {
+        let socket_path = socket_path.into();
+        let stream = UnixStream::connect(&socket_path).await.map_err(DaemonError::from)?;
+
+        Ok(Self {
+            socket_path,
+            reconnect: BackoffPolicy {
+                initial_delay: 100,
+                multiplier: 2.0,
+                max_delay: 5000,
+                jitter: 0.1,
+            },
+            stream: Mutex::new(Some(stream)),
+        })
+    }
+
+    /// # Documentation
+    /// Use `policy` for the delays between attempts to reconnect to the daemon
+    #[must_use]
+    pub const fn with_reconnect(mut self, policy: BackoffPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    #[must_use]
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// # Documentation
+    /// Send a message to the daemon and wait for its reply, reconnecting if the connection was lost.
+    /// `DaemonReply::Error` is returned as `ClientError::Request`
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be reached, or the request failed
+    pub async fn request(&self, message: &DaemonMessage) -> Result<DaemonReply, ClientError> {
+        let read_only = matches!(message, DaemonMessage::Get { .. } | DaemonMessage::GetState { .. });
+
+        // Requests are sent one at a time, so replies can't be mixed up
+        self.request_on(&mut *self.stream.lock().await, message, read_only).await
+    }
+
+    async fn request_on(
+        &self,
+        stream: &mut Option<UnixStream>,
+        message: &DaemonMessage,
+        read_only: bool,
+    ) -> Result<DaemonReply, ClientError> {
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            let connection = match stream.as_mut() {
+                Some(connection) => connection,
+                None => stream.insert(self.reconnect_for_request().await?),
+            };
+
+            let error = match exchange(connection, message).await {
+                Ok(DaemonReply::Error { code, message, .. }) => return Err(ClientError::Request { code, message }),
+                Ok(reply) => return Ok(reply),
+                Err(Failure::Send(e)) => e,
+                Err(Failure::Reply(e)) if read_only => e,
+                Err(Failure::Reply(e)) => {
+                    *stream = None;
+                    return Err(e.into());
+                }
+            };
+
+            // The connection is broken, so a new one is made for the next request
+            *stream = None;
+
+            if attempt >= 2 {
+                return Err(error.into());
+            }
+
+            debug!("Connection to daemon lost, sending request again: {error}");
+        }
+    }
+
+    async fn reconnect_for_request(&self) -> Result<UnixStream, DaemonError> {
+        let mut backoff = Backoff::new(self.reconnect.clone());
+
+        loop {
+            match UnixStream::connect(&self.socket_path).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) if backoff.retries() + 1 >= REQUEST_CONNECT_ATTEMPTS => return Err(e.into()),
+                Err(_) => tokio::time::sleep(backoff.next_delay()).await,
+            }
+        }
+    }
+
+    /// # Documentation
+    /// Get the typed value of a module, e.g. `client.get::<Volume>()`
+    ///
+    /// # Errors
+    /// Returns `ClientError::Unavailable` if the daemon couldn't read the value
+    /// Returns an error if the daemon can't be reached, or the request failed
+    pub async fn get<M: Module>(&self) -> Result<M, ClientError> {
+        match self.request(&DaemonMessage::GetState { module: M::MODULE }).await? {
+            DaemonReply::State(ModuleState { value: Ok(value), .. }) => {
+                M::from_value(value.clone()).ok_or_else(|| ClientError::UnexpectedReply(format!("{value:?}")))
+            }
+            DaemonReply::State(ModuleState { value: Err(reason), .. }) => Err(ClientError::Unavailable(reason)),
+            reply => Err(ClientError::UnexpectedReply(format!("{reply:?}"))),
+        }
+    }
+
+    /// # Documentation
+    /// Set an item to `value`, in the same way as `bar_daemon set`
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be reached, or the value couldn't be set
+    pub async fn set<S: Into<String>>(&self, item: DaemonItem, value: S) -> Result<(), ClientError> {
+        match self
+            .request(&DaemonMessage::Set {
+                item,
+                value: value.into(),
+            })
+            .await?
+        {
+            DaemonReply::Value { .. } => Ok(()),
+            reply => Err(ClientError::UnexpectedReply(format!("{reply:?}"))),
+        }
+    }
+
+    /// # Documentation
+    /// Set the volume percent, either absolute (`"50"`) or relative (`"+5"`, `"-5"`)
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be reached, or the volume couldn't be set
+    pub async fn set_volume_percent<S: Into<String>>(&self, value: S) -> Result<(), ClientError> {
+        self.set(DaemonItem::Volume(VolumeItem::Percent), value).await
+    }
+
+    /// # Documentation
+    /// Set whether the volume is muted, toggling it when `mute` is `None`
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be reached, or mute couldn't be set
+    pub async fn set_volume_mute(&self, mute: Option<bool>) -> Result<(), ClientError> {
+        self.set(DaemonItem::Volume(VolumeItem::Mute), toggle_or(mute)).await
+    }
+
+    /// # Documentation
+    /// Set the monitor brightness percent, either absolute (`"50"`) or relative (`"+5"`, `"-5"`)
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be reached, or the brightness couldn't be set
+    pub async fn set_monitor_brightness<S: Into<String>>(&self, value: S) -> Result<(), ClientError> {
+        self.set(DaemonItem::Brightness(BrightnessItem::Monitor), value).await
+    }
+
+    /// # Documentation
+    /// Set the keyboard brightness percent, either absolute (`"50"`) or relative (`"+5"`, `"-5"`)
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be reached, or the brightness couldn't be set
+    pub async fn set_keyboard_brightness<S: Into<String>>(&self, value: S) -> Result<(), ClientError> {
+        self.set(DaemonItem::Brightness(BrightnessItem::Keyboard), value).await
+    }
+
+    /// # Documentation
+    /// Turn bluetooth on or off, toggling it when `state` is `None`
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be reached, or bluetooth couldn't be set
+    pub async fn set_bluetooth(&self, state: Option<bool>) -> Result<(), ClientError> {
+        self.set(DaemonItem::Bluetooth(BluetoothItem::State), toggle_or(state)).await
+    }
+
+    /// # Documentation
+    /// Set the fan profile by name, or to `"next"` or `"prev"`
+    ///
+    /// # Errors
+    /// Returns an error if the daemon can't be reached, or the profile couldn't be set
+    pub async fn set_fan_profile<S: Into<String>>(&self, value: S) -> Result<(), ClientError> {
+        self.set(DaemonItem::FanProfile(FanProfileItem::Profile), value).await
+    }
+
+    /// # Documentation
+    /// Subscribe to changes, the stream yields the state of every module, then the state of each module which changes.
+    /// If the daemon goes away the stream waits for it to come back, and yields the state of every module again
+    pub fn subscribe(&self) -> impl Stream<Item = ModuleState> + Send + 'static {
+        let subscription = Subscription {
+            socket_path: self.socket_path.clone(),
+            backoff: Backoff::new(self.reconnect.clone()),
+            reader: None,
+        };
+
+        futures::stream::unfold(subscription, |mut subscription| async move {
+            let state = subscription.next_state().await;
+
+            Some((state, subscription))
+        })
+    }
+}
+
+fn toggle_or(value: Option<bool>) -> String {
+    value.map_or_else(|| String::from("toggle"), |value| value.to_string())
+}
+
+async fn exchange(stream: &mut UnixStream, message: &DaemonMessage) -> Result<DaemonReply, Failure> {
+    write_message(stream, message).await.map_err(Failure::Send)?;
+
+    // Each reply is one frame, so a large reply is read whole and nothing is left for the next request
+    read_reply(stream).await.map_err(Failure::Reply)
+}
+
+struct Subscription {
+    socket_path: PathBuf,
+    backoff: Backoff,
+    reader: Option<BufReader<UnixStream>>,
+}
+
+impl Subscription {
+    async fn next_state(&mut self) -> ModuleState {
+        loop {
+            if self.reader.is_none() {
+                self.reader = Some(self.resubscribe().await);
+            }
+
+            let Some(reader) = self.reader.as_mut() else {
+                continue;
+            };
+
+            // Each state is a postcard COBS frame, which ends with a zero byte
+            let mut frame = Vec::new();
+            match reader.read_until(0, &mut frame).await {
+                Ok(0) | Err(_) => {
+                    warn!("Subscription to the daemon was lost, reconnecting");
+                    self.reader = None;
+                }
+                Ok(_) => match postcard::from_bytes_cobs::<ModuleState>(&mut frame) {
+                    Ok(state) => {
+                        self.backoff.reset();
+                        return state;
+                    }
+                    Err(e) => warn!("Could not decode state sent by the daemon: {e}"),
+                },
+            }
+        }
+    }
+
+    async fn resubscribe(&mut self) -> BufReader<UnixStream> {
+        loop {
+            match subscribe_at(&self.socket_path).await {
+                Ok(reader) => return reader,
+                Err(e) => {
+                    let delay = self.backoff.next_delay();
+                    debug!("Could not subscribe to the daemon, retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
+async fn subscribe_at(socket_path: &Path) -> Result<BufReader<UnixStream>, DaemonError> {
+    let mut stream = UnixStream::connect(socket_path).await?;
+    write_message(&mut stream, &DaemonMessage::Subscribe).await?;
+
+    Ok(BufReader::new(stream))
+}
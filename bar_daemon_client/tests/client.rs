@@ -0,0 +1,155 @@
+use std::{path::Path, time::Duration};
+
+use bar_daemon::{
+    daemon::{DaemonOptions, run_daemon},
+    error::DaemonError,
+    protocol::BUFFER_SIZE,
+    simulate::Scenario,
+};
+use bar_daemon_client::{
+    Client, ClientError, DaemonMessage, DaemonReply, ErrorCode, ModuleState, ModuleValue, Ram, TupleName, Volume,
+};
+use futures::StreamExt;
+use tokio::{net::UnixStream, sync::oneshot, task::JoinHandle};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCENARIO: &str = r#"
+[initial]
+volume = 50
+bluetooth = true
+"#;
+
+/// Start a daemon with simulated hardware, returning the sender which shuts it down
+async fn start_daemon(socket_path: &Path) -> (oneshot::Sender<()>, JoinHandle<Result<(), DaemonError>>) {
+    let scenario = Scenario::parse(SCENARIO).unwrap_or_else(|e| panic!("Invalid test scenario: {e}"));
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let options = DaemonOptions {
+        scenario: Some(scenario),
+        ..DaemonOptions::isolated(socket_path.to_path_buf())
+    };
+
+    let handle = tokio::spawn(run_daemon(options, async move {
+        let _ = shutdown_rx.await;
+    }));
+
+    let start = tokio::time::Instant::now();
+    while UnixStream::connect(socket_path).await.is_err() {
+        assert!(start.elapsed() < TIMEOUT, "Daemon did not start listening in time");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    (shutdown_tx, handle)
+}
+
+async fn stop_daemon(daemon: (oneshot::Sender<()>, JoinHandle<Result<(), DaemonError>>)) {
+    let _ = daemon.0.send(());
+
+    match tokio::time::timeout(TIMEOUT, daemon.1).await {
+        Ok(Ok(Ok(()))) => {}
+        result => panic!("Daemon did not shut down cleanly: {result:?}"),
+    }
+}
+
+async fn next_state<S: futures::Stream<Item = ModuleState> + Unpin>(stream: &mut S) -> ModuleState {
+    match tokio::time::timeout(TIMEOUT, stream.next()).await {
+        Ok(Some(state)) => state,
+        result => panic!("No state was received: {result:?}"),
+    }
+}
+
+// The daemon's snapshot is shared by the whole process, so one test uses one daemon throughout
+#[tokio::test(flavor = "multi_thread")]
+async fn client_gets_sets_subscribes_and_reconnects() {
+    let socket_path = std::env::temp_dir()
+        .join(format!("bar_daemon_client_test_{}", std::process::id()))
+        .join("bar_daemon.sock");
+    let daemon = start_daemon(&socket_path).await;
+
+    let client = Client::connect_to(&socket_path).await.unwrap_or_else(|e| panic!("{e}"));
+
+    // Values are typed
+    let volume = client.get::<Volume>().await.unwrap_or_else(|e| panic!("{e}"));
+    assert_eq!(
+        volume,
+        Volume {
+            percent: 50,
+            mute: false
+        }
+    );
+
+    // Replies of any size are read whole, so the connection can be used for the next request
+    // Every set is a change which is kept in the history, since 0 and 100 are read back exactly
+    for percent in ["0", "100"].into_iter().cycle().take(100) {
+        client.set_volume_percent(percent).await.unwrap_or_else(|e| panic!("{e}"));
+    }
+    client.set_volume_percent("50").await.unwrap_or_else(|e| panic!("{e}"));
+
+    let history = client
+        .request(&DaemonMessage::History {
+            module: TupleName::Volume,
+            since: None,
+        })
+        .await;
+    assert!(
+        matches!(&history, Ok(DaemonReply::History { entries, .. }) if entries.len() > 1),
+        "{history:?}"
+    );
+    let reply_size = history
+        .as_ref()
+        .map_or(0, |reply| postcard::to_stdvec(reply).map_or(0, |bytes| bytes.len()));
+    assert!(reply_size > BUFFER_SIZE, "History is only {reply_size} bytes");
+    assert!(matches!(
+        client.request(&DaemonMessage::Status).await,
+        Ok(DaemonReply::Status(_))
+    ));
+    assert_eq!(client.get::<Volume>().await.map(|volume| volume.percent).ok(), Some(50));
+
+    // Subscribers are sent every module, then each change
+    let mut updates = Box::pin(client.subscribe());
+    for _ in 0..6 {
+        let _ = next_state(&mut updates).await;
+    }
+
+    // 64 is read back from wpctl exactly
+    client.set_volume_percent("+14").await.unwrap_or_else(|e| panic!("{e}"));
+    assert_eq!(client.get::<Volume>().await.map(|volume| volume.percent).ok(), Some(64));
+
+    loop {
+        let state = next_state(&mut updates).await;
+        if state.module == TupleName::Volume {
+            assert!(
+                matches!(state.value, Ok(ModuleValue::Volume(Volume { percent: 64, .. }))),
+                "{state:?}"
+            );
+            break;
+        }
+    }
+
+    // Errors keep the daemon's error codes
+    let error = client.set_fan_profile("Turbo").await;
+    assert!(
+        matches!(
+            &error,
+            Err(ClientError::Request {
+                code: ErrorCode::InvalidValue,
+                ..
+            })
+        ),
+        "{error:?}"
+    );
+    assert!(client.get::<Ram>().await.is_ok());
+
+    // When the daemon restarts, requests and the subscription carry on with the new daemon
+    stop_daemon(daemon).await;
+    let daemon = start_daemon(&socket_path).await;
+
+    // The simulated hardware belongs to the process, so it keeps the volume which was set
+    assert_eq!(client.get::<Volume>().await.map(|volume| volume.percent).ok(), Some(64));
+
+    let modules = [next_state(&mut updates).await.module, next_state(&mut updates).await.module];
+    assert_eq!(modules, [TupleName::Volume, TupleName::Brightness]);
+
+    stop_daemon(daemon).await;
+}